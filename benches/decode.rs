@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::disassembler::disassemble;
+use intel4004_emu::intel4004::Intel4004;
+
+const STEPS: u64 = 100_000;
+
+// Mix of ALU, register, RAM and jump instructions running in a loop.
+const PROGRAM: &str = "
+        FIM P0, 0x12
+        SRC P0
+loop:   LDM 5
+        XCH R2
+        ADD R2
+        DAA
+        RAL
+        WRM
+        RDM
+        INC R3
+        JCN Z, loop
+        ISZ R4, loop
+        JUN loop
+";
+
+fn execute(c: &mut Criterion) {
+    let asm = assemble(PROGRAM).unwrap();
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+
+    let mut group = c.benchmark_group("execute");
+    group.throughput(Throughput::Elements(STEPS));
+    group.bench_function("clock", |b| b.iter(|| {
+        for _ in 0..STEPS {
+            cpu.clock();
+        }
+        black_box(cpu.get_acc())
+    }));
+    group.finish();
+}
+
+fn disassemble_rom(c: &mut Criterion) {
+    let rom: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+
+    let mut group = c.benchmark_group("disassemble");
+    group.throughput(Throughput::Bytes(rom.len() as u64));
+    group.bench_function("rom", |b| b.iter(|| {
+        let mut addr = 0;
+        while (addr as usize) < rom.len() {
+            let (text, size) = disassemble(black_box(&rom), addr, None);
+            black_box(text);
+            addr += size;
+        }
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, execute, disassemble_rom);
+criterion_main!(benches);
@@ -0,0 +1,35 @@
+/* Drives the emulator from C. Build the library with `cargo build`, then:
+ *   cc examples/c/bench.c -Iinclude -Ltarget/debug -lintel4004_emu -o bench && LD_LIBRARY_PATH=target/debug ./bench
+ */
+#include <stdio.h>
+
+#include "intel4004.h"
+
+/* FIM P0, 0x40; SRC P0; RDR; WMP; JUN 0x005 */
+static const uint8_t PROGRAM[] = {0x20, 0x40, 0x21, 0xEA, 0xE1, 0x40, 0x05};
+
+static void ram_write(void *user, uint8_t chip, uint8_t value) {
+    printf("RAM %X <- %X\n", chip, value);
+    *(int *)user += 1;
+}
+
+static int32_t rom_read(void *user, uint8_t chip) {
+    (void)user;
+    return chip == 4 ? 0xA : -1;
+}
+
+int main(void) {
+    int writes = 0;
+    I4004Machine *machine = i4004_new(0, 0);
+
+    i4004_load_rom(machine, PROGRAM, sizeof PROGRAM);
+    i4004_set_io(machine, NULL, rom_read, ram_write, &writes);
+
+    I4004RunResult result;
+    i4004_run(machine, 1000, -1, &result);
+    printf("exit %d at %03X after %llu cycles, ACC=%X, %d write(s)\n", result.exit, result.address,
+        (unsigned long long)result.cycles, i4004_get_acc(machine), writes);
+
+    i4004_free(machine);
+    return result.exit == I4004_EXIT_HALT && writes == 1 ? 0 : 1;
+}
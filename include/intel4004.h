@@ -0,0 +1,131 @@
+/* C API of the intel4004_emu crate. Generated by cbindgen from src/ffi.rs, don't edit.
+ *
+ * Every function takes a machine returned by i4004_new that was not freed. */
+
+#ifndef INTEL4004_H
+#define INTEL4004_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum I4004Exit {
+  I4004_EXIT_CYCLE_LIMIT,
+  I4004_EXIT_INSTRUCTION_LIMIT,
+  I4004_EXIT_ADDRESS,
+  I4004_EXIT_HALT,
+  I4004_EXIT_PORT,
+  I4004_EXIT_WATCHPOINT,
+  I4004_EXIT_UNDEFINED,
+  I4004_EXIT_STRICT,
+  I4004_EXIT_UNINITIALIZED,
+} I4004Exit;
+
+// A 4004 with its ROM and RAM chips.
+typedef struct I4004Machine I4004Machine;
+
+// Called after WRR or WMP with the chip number and the value written, can be NULL.
+typedef void (*I4004PortWrite)(void *user, uint8_t chip, uint8_t value);
+
+// Called by RDR, returns the 4 bits on the I/O lines of the chip or -1 to read back the last value written. Can be
+// NULL.
+typedef int32_t (*I4004PortRead)(void *user, uint8_t chip);
+
+typedef struct I4004RunResult {
+  enum I4004Exit exit;
+  uint16_t address;
+  uint64_t instructions;
+  uint64_t cycles;
+} I4004RunResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// New machine with 1-16 populated ROM and RAM chips, 0 populates all of them. Free it with `i4004_free`.
+struct I4004Machine *i4004_new(uint32_t rom_chips,
+                               uint32_t ram_chips);
+
+// NULL is ignored.
+void i4004_free(struct I4004Machine *machine);
+
+// Copy `length` bytes of ROM image to the chips starting at address 0. Returns 0, or -1 if it is larger than the
+// populated ROM.
+int32_t i4004_load_rom(struct I4004Machine *machine,
+                       const uint8_t *bytes,
+                       size_t length);
+
+// Send port accesses to the callbacks, any of them can be NULL. `user` is passed back to every call, the callbacks must
+// stay callable with it as long as the machine runs. Replaces the callbacks set before.
+void i4004_set_io(struct I4004Machine *machine,
+                  I4004PortWrite rom_write,
+                  I4004PortRead rom_read,
+                  I4004PortWrite ram_write,
+                  void *user);
+
+// Execute one instruction, returns the instruction cycles it took.
+uint32_t i4004_step(struct I4004Machine *machine);
+
+// Assert RESET: clears the CPU registers, RAM and ports, the ROM is kept.
+void i4004_reset(struct I4004Machine *machine);
+
+// Run until a JUN to itself, the PC reaching `stop_at` (-1 for none) or `max_cycles` instruction cycles, then fill
+// `result` unless it is NULL. Returns 0 if the program halted or reached `stop_at`, 1 if the cycles ran out, 2 on a
+// trapped undefined opcode, 3 on a failed strict mode check and 4 on an uninitialized read.
+int32_t i4004_run(struct I4004Machine *machine,
+                  uint64_t max_cycles,
+                  int32_t stop_at,
+                  struct I4004RunResult *result);
+
+uint16_t i4004_get_pc(struct I4004Machine *machine);
+
+void i4004_set_pc(struct I4004Machine *machine, uint16_t pc);
+
+uint8_t i4004_get_acc(struct I4004Machine *machine);
+
+void i4004_set_acc(struct I4004Machine *machine, uint8_t acc);
+
+bool i4004_get_carry(struct I4004Machine *machine);
+
+void i4004_set_carry(struct I4004Machine *machine, bool carry);
+
+// Level of the TEST pin.
+bool i4004_get_test(struct I4004Machine *machine);
+
+void i4004_set_test(struct I4004Machine *machine, bool test);
+
+// Index register 0-15, 0 for others.
+uint8_t i4004_get_reg(struct I4004Machine *machine, uint8_t r);
+
+void i4004_set_reg(struct I4004Machine *machine, uint8_t r, uint8_t value);
+
+// Stack level 0-2, 0 for others.
+uint16_t i4004_get_stack(struct I4004Machine *machine, uint8_t level);
+
+// Address sent by the last SRC.
+uint8_t i4004_get_src(struct I4004Machine *machine);
+
+// RAM character 0-63 of chip 0-15 (4 * bank + chip), 0 outside them.
+uint8_t i4004_read_ram(struct I4004Machine *machine, uint8_t chip, uint8_t index);
+
+void i4004_write_ram(struct I4004Machine *machine, uint8_t chip, uint8_t index, uint8_t value);
+
+// Status character 0-15 of a RAM chip, 4 per register.
+uint8_t i4004_read_status(struct I4004Machine *machine, uint8_t chip, uint8_t index);
+
+void i4004_write_status(struct I4004Machine *machine, uint8_t chip, uint8_t index, uint8_t value);
+
+// Output lines of a RAM chip, written by WMP.
+uint8_t i4004_read_ram_port(struct I4004Machine *machine, uint8_t chip);
+
+// I/O lines of a ROM chip, written by WRR.
+uint8_t i4004_read_rom_port(struct I4004Machine *machine, uint8_t chip);
+
+// Drive the I/O lines of a ROM chip, read by RDR when no read callback answers.
+void i4004_write_rom_port(struct I4004Machine *machine, uint8_t chip, uint8_t value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* INTEL4004_H */
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::disassembler::{disassemble_for, discover_for, ByteKind};
+use super::opcodes::{Model, Op};
+use super::symbols::SymbolTable;
+
+// Control flow analysis
+
+/// How a basic block ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Fallthrough,                                                    // Next instruction starts another block.
+    Jump,                                                           // JUN
+    Branch,                                                         // JCN and ISZ, taken target first.
+    Call(u16),                                                      // JMS, the successor is the return address.
+    Return,                                                         // BBL
+    Indirect,                                                       // JIN, target only known at runtime.
+    Stop,                                                           // Undefined opcode or end of the image.
+}
+
+#[derive(Debug, Clone)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub end: u16,                                                   // Address after the last instruction.
+    pub exit: Exit,
+    pub successors: Vec<u16>,
+    pub uses_fin: bool,                                             // Contains a FIN, which reads ROM indirectly.
+}
+
+pub struct ControlFlowGraph {
+    pub model: Model,
+    pub entry_points: Vec<u16>,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+}
+
+/// Targets of a control transfer instruction and the address after it.
+fn targets(model: Model, rom: &[u8], addr: u16) -> (Vec<u16>, u16) {
+    let op_code = rom[addr as usize];
+    let data = rom.get(addr as usize + 1).copied().unwrap_or(0) as u16;
+    let next = addr + model.decode(op_code).size;
+
+    let targets = match op_code & 0xF0 {
+        0x10 | 0x70 => vec![(next & 0xF00) | data],
+        0x40 | 0x50 => vec![((op_code as u16 & 0x0F) << 8) | data],
+        _ => Vec::new(),
+    };
+    (targets, next)
+}
+
+fn ends_block(model: Model, op_code: u8) -> bool {
+    matches!(model.decode(op_code).op, Op::Jcn | Op::Isz | Op::Jun | Op::Jms | Op::Bbl | Op::Bbs | Op::Jin)
+}
+
+impl ControlFlowGraph {
+    /// Split the code reachable from the entry points into basic blocks.
+    pub fn build(rom: &[u8], entry_points: &[u16]) -> Self {
+        Self::build_for(Model::I4004, rom, entry_points)
+    }
+
+    /// `build` with the instruction set of `model`, BBS returns like BBL.
+    pub fn build_for(model: Model, rom: &[u8], entry_points: &[u16]) -> Self {
+        let map = discover_for(model, rom, entry_points, None);
+        let is_code = |addr: u16| map.kinds.get(addr as usize) == Some(&ByteKind::Code);
+
+        let mut leaders: BTreeSet<u16> = entry_points.iter().copied().filter(|a| is_code(*a)).collect();
+        for addr in (0..rom.len() as u16).filter(|a| is_code(*a)) {
+            if ends_block(model, rom[addr as usize]) {
+                let (targets, next) = targets(model, rom, addr);
+                leaders.extend(targets.into_iter().chain(std::iter::once(next)).filter(|a| is_code(*a)));
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        for start in &leaders {
+            let mut addr = *start;
+            let mut uses_fin = false;
+
+            let (exit, successors, end) = loop {
+                let op = model.decode(rom[addr as usize]).op;
+                let (targets, next) = targets(model, rom, addr);
+                uses_fin |= op == Op::Fin;
+
+                let exit = match op {
+                    Op::Jcn | Op::Isz => Some(Exit::Branch),
+                    Op::Jun => Some(Exit::Jump),
+                    Op::Jms => Some(Exit::Call(targets[0])),
+                    Op::Bbl | Op::Bbs => Some(Exit::Return),
+                    Op::Jin => Some(Exit::Indirect),
+                    _ if !is_code(next) => Some(Exit::Stop),
+                    _ if leaders.contains(&next) => Some(Exit::Fallthrough),
+                    _ => None,
+                };
+
+                if let Some(exit) = exit {
+                    let successors = match exit {
+                        Exit::Branch => targets.into_iter().chain(std::iter::once(next)).collect(),
+                        Exit::Jump => targets,
+                        Exit::Call(_) | Exit::Fallthrough => vec![next],
+                        _ => Vec::new(),
+                    };
+                    break (exit, successors.into_iter().filter(|a| is_code(*a)).collect(), next);
+                }
+                addr = next;
+            };
+
+            blocks.insert(*start, BasicBlock { start: *start, end, exit, successors, uses_fin });
+        }
+
+        ControlFlowGraph { model, entry_points: entry_points.to_vec(), blocks }
+    }
+
+    /// Block containing `addr`.
+    pub fn block_at(&self, addr: u16) -> Option<&BasicBlock> {
+        self.blocks.range(..=addr).next_back().map(|(_, b)| b).filter(|b| addr < b.end)
+    }
+
+    /// Graphviz DOT with the disassembly of each block. Call edges are dashed, blocks ending in JIN or using FIN are
+    /// highlighted since their targets are not followed.
+    pub fn to_dot(&self, rom: &[u8], symbols: Option<&SymbolTable>) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if let Some(name) = symbols.and_then(|s| s.label_at(block.start)) {
+                label.push_str(&format!("{}:\\l", name));
+            }
+
+            let mut addr = block.start;
+            while addr < block.end {
+                let (text, size) = disassemble_for(self.model, rom, addr, symbols);
+                label.push_str(&format!("{:03X}  {}\\l", addr, text));
+                addr += size;
+            }
+
+            let style = match block.exit {
+                Exit::Indirect => ", color=red",
+                _ if block.uses_fin => ", color=blue",
+                _ => "",
+            };
+            dot.push_str(&format!("    b{:03X} [label=\"{}\"{}];\n", block.start, label.replace('"', "\\\""), style));
+
+            for successor in &block.successors {
+                dot.push_str(&format!("    b{:03X} -> b{:03X};\n", block.start, successor));
+            }
+            if let Exit::Call(target) = block.exit {
+                if self.blocks.contains_key(&target) {
+                    dot.push_str(&format!("    b{:03X} -> b{:03X} [style=dashed];\n", block.start, target));
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// --- Call graph ---
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry: u16,
+    pub blocks: Vec<u16>,                                           // Blocks reachable without following calls.
+    pub calls: Vec<(u16, u16)>,                                     // JMS address and target.
+    pub returns: bool,
+}
+
+/// Subroutines found from JMS targets, plus the entry points as roots.
+pub struct CallGraph {
+    pub roots: Vec<u16>,
+    pub functions: BTreeMap<u16, Function>,
+}
+
+impl CallGraph {
+    pub fn build(cfg: &ControlFlowGraph) -> Self {
+        let mut entries: BTreeSet<u16> = cfg.entry_points.iter().copied().collect();
+        for block in cfg.blocks.values() {
+            if let Exit::Call(target) = block.exit {
+                entries.insert(target);
+            }
+        }
+
+        let mut functions = BTreeMap::new();
+        for entry in entries.into_iter().filter(|e| cfg.blocks.contains_key(e)) {
+            let mut function = Function { entry, blocks: Vec::new(), calls: Vec::new(), returns: false };
+            let mut seen = BTreeSet::new();
+            let mut work = vec![entry];
+
+            while let Some(start) = work.pop() {
+                if !seen.insert(start) {
+                    continue;
+                }
+                let block = &cfg.blocks[&start];
+
+                match block.exit {
+                    Exit::Call(target) if cfg.blocks.contains_key(&target) => function.calls.push((block.end - 2, target)),
+                    Exit::Return => function.returns = true,
+                    _ => {}
+                }
+                work.extend(block.successors.iter().copied());
+            }
+
+            function.blocks = seen.into_iter().collect();
+            function.calls.sort();
+            functions.insert(entry, function);
+        }
+
+        CallGraph { roots: cfg.entry_points.clone(), functions }
+    }
+
+    /// Deepest JMS nesting below `entry`, the number of stack levels it needs. None if it is recursive.
+    pub fn max_depth(&self, entry: u16) -> Option<usize> {
+        self.depth(entry, &mut Vec::new())
+    }
+
+    fn depth(&self, entry: u16, path: &mut Vec<u16>) -> Option<usize> {
+        if path.contains(&entry) {
+            return None;
+        }
+
+        path.push(entry);
+        let mut depth = 0;
+        for (_, callee) in &self.functions[&entry].calls {
+            match self.depth(*callee, path) {
+                Some(d) => depth = depth.max(d + 1),
+                None => {
+                    path.pop();
+                    return None;
+                }
+            }
+        }
+        path.pop();
+
+        Some(depth)
+    }
+
+    /// Graphviz DOT with one node per subroutine, labelled with the stack levels its calls need.
+    pub fn to_dot(&self, symbols: Option<&SymbolTable>) -> String {
+        let mut dot = String::from("digraph calls {\n    node [shape=box];\n");
+
+        for function in self.functions.values() {
+            let name = match symbols {
+                Some(symbols) => symbols.format_address(function.entry),
+                None => format!("{:03X}", function.entry),
+            };
+            let depth = match self.max_depth(function.entry) {
+                Some(depth) => format!("depth {}", depth),
+                None => "recursive".to_string(),
+            };
+            let shape = if self.roots.contains(&function.entry) { ", shape=doubleoctagon" } else { "" };
+
+            dot.push_str(&format!("    f{:03X} [label=\"{}\\n{}\"{}];\n", function.entry, name, depth, shape));
+        }
+
+        for function in self.functions.values() {
+            let callees: BTreeSet<u16> = function.calls.iter().map(|(_, callee)| *callee).collect();
+            for callee in callees {
+                dot.push_str(&format!("    f{:03X} -> f{:03X};\n", function.entry, callee));
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// --- Stack depth ---
+
+/// A chain of calls from a root: each JMS address with its target.
+pub type CallChain = Vec<(u16, u16)>;
+
+pub struct StackReport {
+    pub levels: usize,                                              // Stack levels of the CPU checked for.
+    pub max_depth: Option<usize>,                                   // None if any root reaches recursion.
+    pub overflows: Vec<(u16, CallChain)>,                           // Root and the chain that needs one level more.
+    pub recursions: Vec<(u16, CallChain)>,                          // Root and the chain that calls itself again.
+}
+
+impl StackReport {
+    pub fn is_ok(&self) -> bool {
+        self.overflows.is_empty() && self.recursions.is_empty()
+    }
+
+    pub fn to_text(&self, symbols: Option<&SymbolTable>) -> String {
+        let name = |addr: u16| match symbols {
+            Some(symbols) => symbols.format_address(addr),
+            None => format!("{:03X}", addr),
+        };
+        let chain = |root: u16, chain: &CallChain| {
+            let calls: Vec<String> = chain.iter().map(|(site, callee)| format!("{} (JMS at {:03X})", name(*callee), site)).collect();
+            format!("{} -> {}", name(root), calls.join(" -> "))
+        };
+
+        let mut text = match self.max_depth {
+            Some(depth) => format!("worst-case call depth: {} of {} levels\n", depth, self.levels),
+            None => format!("worst-case call depth: unbounded (recursion), {} levels available\n", self.levels),
+        };
+        for (root, calls) in &self.overflows {
+            text.push_str(&format!("overflow: {}\n", chain(*root, calls)));
+        }
+        for (root, calls) in &self.recursions {
+            text.push_str(&format!("recursion: {}\n", chain(*root, calls)));
+        }
+        text
+    }
+}
+
+/// Walk every JMS path from the entry points and report the chains that nest deeper than the 3 level 4004 stack.
+pub fn check_stack(rom: &[u8], entry_points: &[u16]) -> StackReport {
+    check_stack_for(Model::I4004, rom, entry_points)
+}
+
+/// `check_stack` with the instruction set and stack levels of `model`.
+pub fn check_stack_for(model: Model, rom: &[u8], entry_points: &[u16]) -> StackReport {
+    let calls = CallGraph::build(&ControlFlowGraph::build_for(model, rom, entry_points));
+    let levels = model.stack_levels();
+    let mut report = StackReport { levels, max_depth: Some(0), overflows: Vec::new(), recursions: Vec::new() };
+
+    for root in calls.roots.iter().filter(|r| calls.functions.contains_key(r)) {
+        walk_calls(&calls, *root, *root, &mut Vec::new(), &mut report);
+
+        report.max_depth = match (report.max_depth, calls.max_depth(*root)) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+    }
+
+    report
+}
+
+fn walk_calls(calls: &CallGraph, root: u16, function: u16, chain: &mut CallChain, report: &mut StackReport) {
+    for call in &calls.functions[&function].calls {
+        chain.push(*call);
+
+        if call.1 == root || chain[..chain.len() - 1].iter().any(|(_, callee)| *callee == call.1) {
+            report.recursions.push((root, chain.clone()));
+        } else if chain.len() > report.levels {
+            report.overflows.push((root, chain.clone()));
+        } else {
+            walk_calls(calls, root, call.1, chain, report);
+        }
+
+        chain.pop();
+    }
+}
@@ -0,0 +1,1191 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use super::opcodes::{Model, Operands};
+use super::symbols::SymbolTable;
+
+// Assembler
+
+const MAX_ADDRESS: i64 = 0xFFF;                                     // 4 KiB of program memory (16 x 4001).
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_MACRO_DEPTH: usize = 32;
+
+/// Location of a source line, macro expansions keep the location of the call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub location: SourceLocation,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", self.location, severity, self.message)
+    }
+}
+
+/// One source line of the listing with the bytes it produced.
+#[derive(Debug, Clone)]
+pub struct ListingLine {
+    pub location: SourceLocation,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub expansion: bool,                                            // Line comes from a macro expansion.
+    pub instructions: Vec<u16>,                                     // Address of each instruction on the line.
+}
+
+/// Result of a successful assembly.
+#[derive(Debug)]
+pub struct Assembly {
+    pub image: Vec<u8>,                                             // ROM image from address 0 to the last byte emitted.
+    pub labels: HashMap<String, u16>,                               // Code and data addresses.
+    pub constants: HashMap<String, i64>,                            // EQU and SET values.
+    pub listing: Vec<ListingLine>,
+    pub warnings: Vec<AsmError>,
+}
+
+impl Assembly {
+    /// Labels as a symbol table, for the debugger and the disassembler.
+    pub fn symbols(&self) -> SymbolTable {
+        // Same order as a loaded symbol file, so the same label wins at a shared address.
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by(|a, b| a.1.cmp(b.1).then(a.0.cmp(b.0)));
+
+        let mut table = SymbolTable::new();
+        for (name, addr) in labels {
+            table.insert(name, *addr);
+        }
+        table
+    }
+
+    /// Listing with the address, the bytes and the source line. Macro expansions are marked with '+'.
+    pub fn listing_text(&self) -> String {
+        let mut text = String::from("ADDR  BYTES      LINE   SOURCE\n");
+
+        for line in &self.listing {
+            let address = if line.bytes.is_empty() { "   ".to_string() } else { format!("{:03X}", line.address) };
+            let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let marker = if line.expansion { '+' } else { ' ' };
+
+            text.push_str(&format!("{}   {:<9} {:>5}{}  {}\n", address, bytes.join(" "), line.location.line, marker, line.text));
+        }
+        text
+    }
+
+    pub fn save_listing(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.listing_text())
+    }
+
+    pub fn save_symbols(&self, filename: &str) -> io::Result<()> {
+        self.symbols().save(filename)
+    }
+}
+
+// --- Instruction set ---
+
+/// JCN condition mnemonics. Bit 3 inverts, bit 2 tests ACC = 0, bit 1 tests carry, bit 0 tests TEST.
+fn lookup_condition(name: &str) -> Option<u8> {
+    Some(match name {
+        "TZ" | "T0"        => 0x1,
+        "C" | "C1" | "CY"  => 0x2,
+        "Z" | "AZ" | "A0"  => 0x4,
+        "TN" | "T1"        => 0x9,
+        "NC" | "C0" | "CZ" => 0xA,
+        "NZ" | "AN"        => 0xC,
+        _ => return None,
+    })
+}
+
+fn is_directive(word: &str) -> bool {
+    matches!(word, "ORG" | "DB" | "DATA" | "EQU" | "SET" | "=" | "INCLUDE" | "IF" | "ELSE" | "ENDIF"
+        | "MACRO" | "ENDM" | "END")
+}
+
+// --- Expressions ---
+
+#[derive(Debug, PartialEq)]
+enum ExprError {
+    Undefined(String),
+    Syntax(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+    Here,                                                           // $, address of the current line.
+}
+
+const OPERATORS: [&str; 22] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+", "-", "*", "/", "%", "&", "|", "^", "~", "!", "<", ">", "(", ")",
+];
+
+fn parse_number(text: &str) -> Result<i64, ExprError> {
+    let lower = text.to_ascii_lowercase();
+
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(hex) = lower.strip_suffix('h') {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+
+    i64::from_str_radix(&digits.replace('_', ""), radix)
+        .map_err(|_| ExprError::Syntax(format!("invalid number '{}'", text)))
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '?' | '@')
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ExprError> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(Token::Num(parse_number(&word)?));
+        } else if c == '$' {
+            i += 1;
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                i += 1;
+            }
+            if start == i {
+                tokens.push(Token::Here);
+            } else {
+                let word: String = chars[start..i].iter().collect();
+                tokens.push(Token::Num(i64::from_str_radix(&word, 16).unwrap()));
+            }
+        } else if c == '\'' {
+            if i + 2 < chars.len() && chars[i + 2] == '\'' {
+                tokens.push(Token::Num(chars[i + 1] as i64));
+                i += 3;
+            } else {
+                return Err(ExprError::Syntax("invalid character literal".to_string()));
+            }
+        } else if is_ident_char(c) {
+            let start = i;
+            while i < chars.len() && is_ident_char(chars[i]) {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => return Err(ExprError::Syntax(format!("unexpected character '{}'", c))),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Precedence climbing evaluator, operators follow C precedence.
+struct ExprParser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    here: i64,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl<'a> ExprParser<'a> {
+    fn binary_precedence(op: &str) -> Option<u8> {
+        Some(match op {
+            "||" => 1,
+            "&&" => 2,
+            "|" => 3,
+            "^" => 4,
+            "&" => 5,
+            "==" | "!=" => 6,
+            "<" | ">" | "<=" | ">=" => 7,
+            "<<" | ">>" => 8,
+            "+" | "-" => 9,
+            "*" | "/" | "%" => 10,
+            _ => return None,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<(), ExprError> {
+        match self.next() {
+            Some(Token::Op(found)) if found == op => Ok(()),
+            _ => Err(ExprError::Syntax(format!("expected '{}'", op))),
+        }
+    }
+
+    fn expression(&mut self, min_precedence: u8) -> Result<i64, ExprError> {
+        let mut lhs = self.unary()?;
+
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            let precedence = match Self::binary_precedence(op) {
+                Some(p) if p >= min_precedence => p,
+                _ => break,
+            };
+            self.pos += 1;
+
+            let rhs = self.expression(precedence + 1)?;
+            lhs = match op {
+                "||" => ((lhs != 0) || (rhs != 0)) as i64,
+                "&&" => ((lhs != 0) && (rhs != 0)) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.checked_shl(rhs as u32).unwrap_or(0),
+                ">>" => lhs.checked_shr(rhs as u32).unwrap_or(0),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" | "%" if rhs == 0 => return Err(ExprError::Syntax("division by zero".to_string())),
+                "/" => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, ExprError> {
+        match self.next() {
+            Some(Token::Op("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(Token::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Op("(")) => {
+                let value = self.expression(0)?;
+                self.expect_op(")")?;
+                Ok(value)
+            }
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Here) => Ok(self.here),
+            Some(Token::Ident(name)) => {
+                // Nibble and page operators: HIGH/LOW split a byte, PAGE gives the 256 byte page of an address.
+                let function: Option<fn(i64) -> i64> = match name.to_ascii_uppercase().as_str() {
+                    "HIGH" => Some(|v| (v >> 4) & 0xF),
+                    "LOW" => Some(|v| v & 0xF),
+                    "PAGE" => Some(|v| (v >> 8) & 0xF),
+                    _ => None,
+                };
+
+                match function {
+                    Some(function) if self.peek() == Some(&Token::Op("(")) => {
+                        self.pos += 1;
+                        let value = self.expression(0)?;
+                        self.expect_op(")")?;
+                        Ok(function(value))
+                    }
+                    _ => (self.lookup)(&name).ok_or(ExprError::Undefined(name)),
+                }
+            }
+            _ => Err(ExprError::Syntax("expected a value".to_string())),
+        }
+    }
+}
+
+fn evaluate(text: &str, here: i64, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, ExprError> {
+    let tokens = tokenize(text)?;
+    if tokens.is_empty() {
+        return Err(ExprError::Syntax("missing expression".to_string()));
+    }
+
+    let mut parser = ExprParser { tokens, pos: 0, here, lookup };
+    let value = parser.expression(0)?;
+
+    if parser.pos != parser.tokens.len() {
+        return Err(ExprError::Syntax(format!("unexpected text in expression '{}'", text.trim())));
+    }
+    Ok(value)
+}
+
+// --- Source parsing ---
+
+/// Remove the comment from a line, ignoring ';' inside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Split an operand field on top level commas.
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), _) if q == c => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth == 0 => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() || !operands.is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    }
+}
+
+struct Statement {
+    label: Option<String>,
+    mnemonic: String,                                               // Upper case.
+    operands: String,
+}
+
+// --- Assembler ---
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+struct Conditional {
+    active: bool,                                                   // This branch is assembled.
+    parent_active: bool,
+    seen_else: bool,
+}
+
+enum Item {
+    Instruction { opcode: u8, format: Operands, operands: Vec<String> },
+    Data { values: Vec<String> },
+    Set { name: String, expr: String },
+}
+
+struct Line {
+    location: SourceLocation,
+    address: u16,
+    item: Item,
+    listing: usize,                                                 // Index of the source line in the listing.
+}
+
+/// Two pass assembler. The first pass expands includes, macros and conditionals and assigns addresses, the second
+/// pass evaluates operands and emits the image.
+pub struct Assembler {
+    include_dirs: Vec<PathBuf>,
+    predefined: Vec<(String, i64)>,
+    auto_pad: bool,
+    model: Model,
+
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    variables: HashMap<String, i64>,                                // SET symbols, may be redefined.
+    pending: Vec<(String, String, u16, SourceLocation)>,            // EQU with forward references and the PC of its line.
+    macros: HashMap<String, Macro>,
+    conditionals: Vec<Conditional>,
+    lines: Vec<Line>,
+    listing: Vec<ListingLine>,
+    pc: u16,
+    macro_count: usize,
+    ended: bool,
+    errors: Vec<AsmError>,
+    warnings: Vec<AsmError>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Assembler {
+            include_dirs: Vec::new(),
+            predefined: Vec::new(),
+            auto_pad: false,
+            model: Model::I4004,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            variables: HashMap::new(),
+            pending: Vec::new(),
+            macros: HashMap::new(),
+            conditionals: Vec::new(),
+            lines: Vec::new(),
+            listing: Vec::new(),
+            pc: 0x000,
+            macro_count: 0,
+            ended: false,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Add a directory searched by INCLUDE after the directory of the including file.
+    pub fn include_dir(&mut self, dir: &Path) {
+        self.include_dirs.push(dir.to_path_buf());
+    }
+
+    /// Move instructions that would misbehave at the end of a page to the start of the next one, filling the gap
+    /// with NOPs.
+    pub fn auto_pad(&mut self, auto_pad: bool) {
+        self.auto_pad = auto_pad;
+    }
+
+    /// Instruction set to accept, the 4040 adds HLT, BBS, LCR, OR4/OR5, AN6/AN7, DB0/DB1, SB0/SB1, EIN/DIN and RPM.
+    /// Each ROM bank of a 4040 is assembled on its own.
+    pub fn model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Define a constant before assembly, like an EQU at the top of the source.
+    pub fn define(&mut self, name: &str, value: i64) {
+        self.predefined.push((name.to_string(), value));
+    }
+
+    pub fn assemble_file(&mut self, path: &Path) -> Result<Assembly, Vec<AsmError>> {
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|e| vec![AsmError {
+            location: SourceLocation { file: name.clone(), line: 0 },
+            severity: Severity::Error,
+            message: format!("cannot read source: {}", e),
+        }])?;
+
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        self.assemble_source(&source, &name, &dir)
+    }
+
+    /// Assemble source text, INCLUDE paths are relative to the current directory.
+    pub fn assemble_str(&mut self, source: &str, name: &str) -> Result<Assembly, Vec<AsmError>> {
+        self.assemble_source(source, name, Path::new(""))
+    }
+
+    fn assemble_source(&mut self, source: &str, name: &str, dir: &Path) -> Result<Assembly, Vec<AsmError>> {
+        self.reset();
+
+        for (name, value) in self.predefined.clone() {
+            self.constants.insert(name, value);
+        }
+
+        self.process_file(source, name, dir, 0);
+
+        if !self.conditionals.is_empty() {
+            self.error(&SourceLocation { file: name.to_string(), line: source.lines().count() }, "missing ENDIF");
+        }
+
+        self.resolve_pending();
+        let image = self.emit();
+
+        if !self.errors.is_empty() {
+            let mut errors = std::mem::take(&mut self.errors);
+            errors.append(&mut self.warnings);
+            return Err(errors);
+        }
+
+        let mut constants = self.constants.clone();
+        constants.extend(self.variables.clone());
+
+        Ok(Assembly {
+            image,
+            labels: self.labels.clone(),
+            constants,
+            listing: std::mem::take(&mut self.listing),
+            warnings: std::mem::take(&mut self.warnings),
+        })
+    }
+
+    fn reset(&mut self) {
+        self.labels.clear();
+        self.constants.clear();
+        self.variables.clear();
+        self.pending.clear();
+        self.macros.clear();
+        self.conditionals.clear();
+        self.lines.clear();
+        self.listing.clear();
+        self.pc = 0x000;
+        self.macro_count = 0;
+        self.ended = false;
+        self.errors.clear();
+        self.warnings.clear();
+    }
+
+    fn error(&mut self, location: &SourceLocation, message: &str) {
+        self.errors.push(AsmError { location: location.clone(), severity: Severity::Error, message: message.to_string() });
+    }
+
+    fn warning(&mut self, location: &SourceLocation, message: &str) {
+        self.warnings.push(AsmError { location: location.clone(), severity: Severity::Warning, message: message.to_string() });
+    }
+
+    fn lookup(&self, name: &str) -> Option<i64> {
+        self.variables.get(name).copied()
+            .or_else(|| self.constants.get(name).copied())
+            .or_else(|| self.labels.get(name).map(|addr| *addr as i64))
+    }
+
+    fn eval(&self, text: &str, here: u16) -> Result<i64, ExprError> {
+        evaluate(text, here as i64, &|name| self.lookup(name))
+    }
+
+    /// Evaluate an expression that must be known during the first pass (ORG, IF, SET).
+    fn eval_now(&mut self, text: &str, location: &SourceLocation) -> Option<i64> {
+        match self.eval(text, self.pc) {
+            Ok(value) => Some(value),
+            Err(ExprError::Undefined(name)) => {
+                self.error(location, &format!("symbol '{}' must be defined before use here", name));
+                None
+            }
+            Err(ExprError::Syntax(message)) => {
+                self.error(location, &message);
+                None
+            }
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.constants.contains_key(name) || self.variables.contains_key(name)
+    }
+
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|c| c.active)
+    }
+
+    // --- First pass ---
+
+    fn process_file(&mut self, source: &str, name: &str, dir: &Path, depth: usize) {
+        let lines = source.lines()
+            .enumerate()
+            .map(|(i, text)| (SourceLocation { file: name.to_string(), line: i + 1 }, text.to_string()))
+            .collect();
+
+        self.process_lines(lines, dir, depth, 0);
+    }
+
+    fn process_lines(&mut self, lines: Vec<(SourceLocation, String)>, dir: &Path, depth: usize, macro_depth: usize) {
+        let mut definition: Option<(String, Macro, SourceLocation, usize)> = None;
+
+        for (location, text) in lines {
+            if self.ended {
+                return;
+            }
+
+            self.listing.push(ListingLine {
+                location: location.clone(),
+                address: self.pc,
+                bytes: Vec::new(),
+                text: text.clone(),
+                expansion: macro_depth > 0,
+                instructions: Vec::new(),
+            });
+
+            let statement = match self.parse_statement(strip_comment(&text)) {
+                Some(statement) => statement,
+                None => continue,
+            };
+
+            // Collect macro bodies verbatim until the matching ENDM.
+            if let Some((_, body, _, nesting)) = definition.as_mut() {
+                match statement.mnemonic.as_str() {
+                    "MACRO" => *nesting += 1,
+                    "ENDM" if *nesting > 0 => *nesting -= 1,
+                    "ENDM" => {
+                        let (name, body, _, _) = definition.take().unwrap();
+                        self.macros.insert(name, body);
+                        continue;
+                    }
+                    _ => {}
+                }
+                body.body.push(text);
+                continue;
+            }
+
+            if self.conditional(&statement, &location) || !self.active() {
+                continue;
+            }
+
+            if statement.mnemonic == "MACRO" {
+                match statement.label {
+                    Some(name) => {
+                        let params = split_operands(&statement.operands);
+                        definition = Some((name.to_ascii_uppercase(), Macro { params, body: Vec::new() }, location, 0));
+                    }
+                    None => self.error(&location, "MACRO needs a name"),
+                }
+                continue;
+            }
+
+            self.statement(statement, &location, dir, depth, macro_depth);
+        }
+
+        if let Some((name, _, location, _)) = definition {
+            self.error(&location, &format!("macro '{}' is missing ENDM", name));
+        }
+    }
+
+    fn parse_statement(&self, text: &str) -> Option<Statement> {
+        if text.trim().is_empty() {
+            return None;
+        }
+
+        let (first, rest) = split_word(text);
+        let (second, after_second) = split_word(rest);
+        let second_upper = second.to_ascii_uppercase();
+
+        if let Some(label) = first.strip_suffix(':') {
+            let (mnemonic, operands) = split_word(rest);
+            return Some(Statement {
+                label: Some(label.to_string()),
+                mnemonic: mnemonic.to_ascii_uppercase(),
+                operands: operands.to_string(),
+            });
+        }
+
+        if matches!(second_upper.as_str(), "EQU" | "SET" | "=" | "MACRO") {
+            return Some(Statement {
+                label: Some(first.to_string()),
+                mnemonic: second_upper,
+                operands: after_second.to_string(),
+            });
+        }
+
+        // A name in the first column that is not an instruction is a label.
+        let first_upper = first.to_ascii_uppercase();
+        let starts_line = !text.starts_with(char::is_whitespace);
+        let known = self.model.lookup(&first_upper).is_some() || is_directive(&first_upper)
+            || self.macros.contains_key(&first_upper);
+
+        if starts_line && !known {
+            return Some(Statement {
+                label: Some(first.to_string()),
+                mnemonic: second_upper,
+                operands: after_second.to_string(),
+            });
+        }
+
+        Some(Statement { label: None, mnemonic: first_upper, operands: rest.to_string() })
+    }
+
+    /// Handle IF/ELSE/ENDIF, returns true if the statement was a conditional directive.
+    fn conditional(&mut self, statement: &Statement, location: &SourceLocation) -> bool {
+        match statement.mnemonic.as_str() {
+            "IF" => {
+                let parent_active = self.active();
+                let active = parent_active && self.eval_now(&statement.operands, location).unwrap_or(0) != 0;
+
+                self.conditionals.push(Conditional { active, parent_active, seen_else: false });
+            }
+            "ELSE" => match self.conditionals.last_mut() {
+                Some(conditional) if !conditional.seen_else => {
+                    conditional.seen_else = true;
+                    conditional.active = conditional.parent_active && !conditional.active;
+                }
+                Some(_) => self.error(location, "ELSE already seen for this IF"),
+                None => self.error(location, "ELSE without IF"),
+            },
+            "ENDIF" => {
+                if self.conditionals.pop().is_none() {
+                    self.error(location, "ENDIF without IF");
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn define_label(&mut self, name: &str, location: &SourceLocation) {
+        if self.is_defined(name) {
+            self.error(location, &format!("symbol '{}' is already defined", name));
+        } else {
+            self.labels.insert(name.to_string(), self.pc);
+        }
+    }
+
+    fn statement(&mut self, statement: Statement, location: &SourceLocation, dir: &Path, depth: usize, macro_depth: usize) {
+        let Statement { label, mnemonic, operands } = statement;
+
+        match mnemonic.as_str() {
+            "EQU" | "=" | "SET" if label.is_none() => {
+                return self.error(location, &format!("{} needs a name", mnemonic));
+            }
+            "EQU" | "=" => {
+                let name = label.unwrap_or_default();
+                if self.is_defined(&name) {
+                    return self.error(location, &format!("symbol '{}' is already defined", name));
+                }
+
+                match self.eval(&operands, self.pc) {
+                    Ok(value) => { self.constants.insert(name, value); }
+                    Err(ExprError::Undefined(_)) => self.pending.push((name, operands, self.pc, location.clone())),
+                    Err(ExprError::Syntax(message)) => self.error(location, &message),
+                }
+                return;
+            }
+            "SET" => {
+                let name = label.unwrap_or_default();
+                if self.labels.contains_key(&name) || self.constants.contains_key(&name) {
+                    return self.error(location, &format!("symbol '{}' is already defined", name));
+                }
+
+                if let Some(value) = self.eval_now(&operands, location) {
+                    self.variables.insert(name.clone(), value);
+                    self.push_line(location, Item::Set { name, expr: operands });
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if self.auto_pad {
+            if let Some((opcode, format)) = self.model.lookup(&mnemonic) {
+                self.pad(opcode, format, location);
+            }
+        }
+
+        if let Some(label) = &label {
+            self.define_label(label, location);
+        }
+
+        match mnemonic.as_str() {
+            "" => {}
+            "ORG" => {
+                if let Some(value) = self.eval_now(&operands, location) {
+                    if (0..=MAX_ADDRESS).contains(&value) {
+                        self.pc = value as u16;
+                    } else {
+                        self.error(location, &format!("ORG address {:#X} is outside program memory", value));
+                    }
+                }
+            }
+            "DB" | "DATA" => {
+                let mut values = Vec::new();
+                for operand in split_operands(&operands) {
+                    match operand.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                        Some(string) => values.extend(string.bytes().map(|b| b.to_string())),
+                        None => values.push(operand),
+                    }
+                }
+                if values.is_empty() {
+                    return self.error(location, "DB needs at least one value");
+                }
+
+                let size = values.len() as u16;
+                self.push_line(location, Item::Data { values });
+                self.advance(size, location);
+            }
+            "INCLUDE" => self.include(&operands, location, dir, depth),
+            "END" => self.ended = true,
+            "ENDM" => self.error(location, "ENDM without MACRO"),
+            _ => {
+                if let Some((opcode, format)) = self.model.lookup(&mnemonic) {
+                    let operands = split_operands(&operands);
+                    self.push_line(location, Item::Instruction { opcode, format, operands });
+                    self.advance(format.size(), location);
+                } else if self.macros.contains_key(&mnemonic) {
+                    self.expand_macro(&mnemonic, &operands, location, dir, depth, macro_depth);
+                } else {
+                    self.error(location, &format!("unknown instruction '{}'", mnemonic));
+                }
+            }
+        }
+    }
+
+    fn push_line(&mut self, location: &SourceLocation, item: Item) {
+        self.lines.push(Line {
+            location: location.clone(),
+            address: self.pc,
+            item,
+            listing: self.listing.len() - 1,
+        });
+    }
+
+    /// Pad to the next page if the instruction at the current address would cross a page boundary.
+    fn pad(&mut self, opcode: u8, format: Operands, location: &SourceLocation) {
+        if page_hazard(self.pc, opcode, format).is_none() {
+            return;
+        }
+
+        let count = 0x100 - (self.pc & 0xFF);
+        self.warning(location, &format!("inserted {} byte(s) of padding to start a new page", count));
+        self.push_line(location, Item::Data { values: vec!["0".to_string(); count as usize] });
+        self.advance(count, location);
+    }
+
+    fn advance(&mut self, size: u16, location: &SourceLocation) {
+        if self.pc as i64 + size as i64 > MAX_ADDRESS + 1 {
+            self.error(location, "program exceeds 4 KiB of program memory");
+        }
+        self.pc = (self.pc + size) & 0xFFF;
+    }
+
+    fn include(&mut self, operands: &str, location: &SourceLocation, dir: &Path, depth: usize) {
+        let file = operands.trim().trim_matches('"');
+        if file.is_empty() {
+            return self.error(location, "INCLUDE needs a file name");
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return self.error(location, "INCLUDE nested too deeply");
+        }
+
+        let path = std::iter::once(dir.to_path_buf())
+            .chain(self.include_dirs.iter().cloned())
+            .map(|d| d.join(file))
+            .find(|p| p.is_file());
+
+        match path.map(|p| (fs::read_to_string(&p), p)) {
+            Some((Ok(source), path)) => {
+                let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+                self.process_file(&source, &path.display().to_string(), &dir, depth + 1);
+            }
+            Some((Err(e), path)) => self.error(location, &format!("cannot read '{}': {}", path.display(), e)),
+            None => self.error(location, &format!("include file '{}' not found", file)),
+        }
+    }
+
+    fn expand_macro(&mut self, name: &str, operands: &str, location: &SourceLocation, dir: &Path, depth: usize, macro_depth: usize) {
+        if macro_depth >= MAX_MACRO_DEPTH {
+            return self.error(location, &format!("macro '{}' expanded too deeply", name));
+        }
+
+        let args = split_operands(operands);
+        let (params, body) = {
+            let definition = &self.macros[name];
+            (definition.params.clone(), definition.body.clone())
+        };
+        if args.len() != params.len() {
+            let message = format!("macro '{}' expects {} arguments, found {}", name, params.len(), args.len());
+            return self.error(location, &message);
+        }
+
+        // \@ in a macro body expands to a number unique to each expansion, for local labels.
+        self.macro_count += 1;
+        let unique = format!("{:04}", self.macro_count);
+
+        let lines = body.iter()
+            .map(|line| (location.clone(), substitute(line, &params, &args).replace("\\@", &unique)))
+            .collect();
+
+        self.process_lines(lines, dir, depth, macro_depth + 1);
+    }
+
+    fn resolve_pending(&mut self) {
+        // Keep resolving while progress is made so EQU chains work in any order.
+        loop {
+            let before = self.pending.len();
+            let pending = std::mem::take(&mut self.pending);
+
+            for (name, expr, here, location) in pending {
+                match self.eval(&expr, here) {
+                    Ok(value) => { self.constants.insert(name, value); }
+                    Err(_) => self.pending.push((name, expr, here, location)),
+                }
+            }
+
+            if self.pending.is_empty() || self.pending.len() == before {
+                break;
+            }
+        }
+
+        for (_, expr, here, location) in std::mem::take(&mut self.pending) {
+            match self.eval(&expr, here) {
+                Err(ExprError::Undefined(name)) => self.error(&location, &format!("undefined symbol '{}'", name)),
+                Err(ExprError::Syntax(message)) => self.error(&location, &message),
+                Ok(_) => {}
+            }
+        }
+    }
+
+    // --- Second pass ---
+
+    fn emit(&mut self) -> Vec<u8> {
+        let mut image = Vec::new();
+        let mut written = Vec::new();
+        let lines = std::mem::take(&mut self.lines);
+
+        // SET symbols take the value they had at each line.
+        self.variables.clear();
+
+        for line in &lines {
+            let bytes = match &line.item {
+                Item::Set { name, expr } => {
+                    if let Ok(value) = self.eval(expr, line.address) {
+                        self.variables.insert(name.clone(), value);
+                    }
+                    continue;
+                }
+                Item::Data { values } => values.iter()
+                    .map(|v| self.operand(v, line, -128, 0xFF).unwrap_or(0) as u8)
+                    .collect(),
+                Item::Instruction { opcode, format, operands } => {
+                    self.listing[line.listing].instructions.push(line.address);
+                    self.encode(*opcode, *format, operands, line)
+                }
+            };
+
+            self.listing[line.listing].bytes.extend(&bytes);
+
+            for (i, byte) in bytes.into_iter().enumerate() {
+                let addr = line.address as usize + i;
+                if addr > MAX_ADDRESS as usize {
+                    break;
+                }
+                if image.len() <= addr {
+                    image.resize(addr + 1, 0x00);
+                    written.resize(addr + 1, false);
+                }
+                if written[addr] {
+                    self.error(&line.location, &format!("address {:#05X} is already in use", addr));
+                }
+                image[addr] = byte;
+                written[addr] = true;
+            }
+        }
+
+        self.lines = lines;
+        image
+    }
+
+    /// Evaluate an operand and check it is in range.
+    fn operand(&mut self, text: &str, line: &Line, min: i64, max: i64) -> Option<i64> {
+        match self.eval(text, line.address) {
+            Ok(value) if (min..=max).contains(&value) => Some(value),
+            Ok(value) => {
+                self.error(&line.location, &format!("value {} is out of range ({}..{})", value, min, max));
+                None
+            }
+            Err(ExprError::Undefined(name)) => {
+                self.error(&line.location, &format!("undefined symbol '{}'", name));
+                None
+            }
+            Err(ExprError::Syntax(message)) => {
+                self.error(&line.location, &message);
+                None
+            }
+        }
+    }
+
+    /// Index register: R0-R15 or an expression.
+    fn register(&mut self, text: &str, line: &Line) -> u8 {
+        let upper = text.to_ascii_uppercase();
+        if let Some(n) = upper.strip_prefix('R').and_then(|n| n.parse::<u8>().ok()) {
+            if n < 16 {
+                return n;
+            }
+        }
+        self.operand(text, line, 0, 15).unwrap_or(0) as u8
+    }
+
+    /// Register pair: P0-P7, 0P-7P, R0R1-R14R15 or an expression.
+    fn pair(&mut self, text: &str, line: &Line) -> u8 {
+        let upper = text.to_ascii_uppercase();
+        let named = upper.strip_prefix('P').or_else(|| upper.strip_suffix('P'))
+            .and_then(|n| n.parse::<u8>().ok())
+            .or_else(|| {
+                let (first, second) = upper.strip_prefix('R')?.split_once('R')?;
+                let (first, second) = (first.parse::<u8>().ok()?, second.parse::<u8>().ok()?);
+                (first & 1 == 0 && second == first + 1).then_some(first / 2)
+            });
+
+        match named {
+            Some(n) if n < 8 => n,
+            _ => self.operand(text, line, 0, 7).unwrap_or(0) as u8,
+        }
+    }
+
+    fn condition(&mut self, text: &str, line: &Line) -> u8 {
+        match lookup_condition(&text.to_ascii_uppercase()) {
+            Some(condition) => condition,
+            None => self.operand(text, line, 0, 15).unwrap_or(0) as u8,
+        }
+    }
+
+    /// Short jump target, only the low 8 bits are encoded. The target must be in the page of the next instruction.
+    fn address8(&mut self, text: &str, line: &Line) -> u8 {
+        let target = self.operand(text, line, 0, MAX_ADDRESS).unwrap_or(0) as u16;
+        let page = ((line.address + 2) >> 8) & 0xF;
+
+        if target >> 8 != page {
+            let message = if line.address >> 8 != page {
+                format!("target {:#05X} is not in page {:X}, the instruction ends page {:X} so it jumps inside page {:X}",
+                    target, page, line.address >> 8, page)
+            } else {
+                format!("target {:#05X} is not in page {:X} of the instruction", target, page)
+            };
+            self.error(&line.location, &message);
+        }
+        (target & 0xFF) as u8
+    }
+
+    fn encode(&mut self, opcode: u8, format: Operands, operands: &[String], line: &Line) -> Vec<u8> {
+        let expected = match format {
+            Operands::None => 0,
+            Operands::Reg | Operands::Pair | Operands::Data4 | Operands::Addr12 => 1,
+            Operands::PairData8 | Operands::CondAddr8 | Operands::RegAddr8 => 2,
+        };
+        if operands.len() != expected {
+            self.error(&line.location, &format!("expected {} operand(s), found {}", expected, operands.len()));
+            return vec![0x00; format.size() as usize];
+        }
+
+        if let Some(hazard) = page_hazard(line.address, opcode, format) {
+            self.warning(&line.location, &hazard);
+        }
+
+        match format {
+            Operands::None => vec![opcode],
+            Operands::Reg => vec![opcode | self.register(&operands[0], line)],
+            Operands::Pair => vec![opcode | (self.pair(&operands[0], line) << 1)],
+            Operands::Data4 => vec![opcode | self.operand(&operands[0], line, 0, 15).unwrap_or(0) as u8],
+            Operands::PairData8 => {
+                let pair = self.pair(&operands[0], line);
+                let data = self.operand(&operands[1], line, -128, 0xFF).unwrap_or(0) as u8;
+                vec![opcode | (pair << 1), data]
+            }
+            Operands::CondAddr8 => {
+                let condition = self.condition(&operands[0], line);
+                vec![opcode | condition, self.address8(&operands[1], line)]
+            }
+            Operands::RegAddr8 => {
+                let register = self.register(&operands[0], line);
+                vec![opcode | register, self.address8(&operands[1], line)]
+            }
+            Operands::Addr12 => {
+                let addr = self.operand(&operands[0], line, 0, MAX_ADDRESS).unwrap_or(0) as u16;
+                vec![opcode | (addr >> 8) as u8, (addr & 0xFF) as u8]
+            }
+        }
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describe how an instruction at `addr` is affected by the end of its page.
+///
+/// The 4004 increments the PC before executing, so JCN and ISZ jump inside the page of the next instruction, and FIN
+/// and JIN use the page of the next instruction too. A 2-word instruction at the last address of a page fetches its
+/// second byte from the next page.
+fn page_hazard(addr: u16, opcode: u8, format: Operands) -> Option<String> {
+    let offset = addr & 0xFF;
+    let page = addr >> 8;
+    let next_page = (page + 1) & 0xF;
+
+    match format {
+        Operands::CondAddr8 | Operands::RegAddr8 if offset >= 0xFE => {
+            let name = if format == Operands::CondAddr8 { "JCN" } else { "ISZ" };
+            Some(format!("{} at the end of page {:X} jumps inside page {:X}", name, page, next_page))
+        }
+        Operands::Pair if opcode & 0xF0 == 0x30 && offset == 0xFF => {
+            let name = if opcode & 1 == 0 { "FIN" } else { "JIN" };
+            Some(format!("{} at the end of page {:X} uses page {:X}", name, page, next_page))
+        }
+        _ if format.size() == 2 && offset == 0xFF => {
+            Some(format!("2-word instruction at the end of page {:X} has its second byte in page {:X}", page, next_page))
+        }
+        _ => None,
+    }
+}
+
+/// Replace whole identifiers matching a macro parameter with the argument text.
+fn substitute(line: &str, params: &[String], args: &[String]) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut word = String::new();
+
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if is_ident_char(c) {
+            word.push(c);
+            continue;
+        }
+
+        match params.iter().position(|p| *p == word) {
+            Some(i) => result.push_str(&args[i]),
+            None => result.push_str(&word),
+        }
+        word.clear();
+
+        if c != '\n' {
+            result.push(c);
+        }
+    }
+    result
+}
+
+/// Assemble source text with default options.
+pub fn assemble(source: &str) -> Result<Assembly, Vec<AsmError>> {
+    Assembler::new().assemble_str(source, "<source>")
+}
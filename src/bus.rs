@@ -0,0 +1,80 @@
+// MCS-4 bus
+
+pub const PHASE_NS: u64 = 1350;                                     // One period of the 740 kHz clock.
+
+/// Clock phases of an instruction cycle. A1-A3 send the address, M1-M2 fetch the instruction, X1-X3 execute it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Phase {
+    #[default]
+    A1,
+    A2,
+    A3,
+    M1,
+    M2,
+    X1,
+    X2,
+    X3,
+}
+
+pub const PHASES: [Phase; 8] = [Phase::A1, Phase::A2, Phase::A3, Phase::M1, Phase::M2, Phase::X1, Phase::X2, Phase::X3];
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        ["A1", "A2", "A3", "M1", "M2", "X1", "X2", "X3"][self as usize]
+    }
+}
+
+/// Lines of the bus during one phase. Asserted lines read true or 1, on the real bus they are active low.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Signals {
+    pub phase: Phase,
+    pub data: u8,                                                   // D0-D3, 0 when nothing drives them.
+    pub sync: bool,                                                 // Sent by the CPU during X3.
+    pub cm_rom: u8,                                                 // CM-ROM, bit n for CM-ROMn of a 4040.
+    pub cm_ram: u8,                                                 // CM-RAM0-3, bit n for CM-RAMn.
+    pub time: u64,                                                  // Clock phases since the bus was built.
+}
+
+/// A chip on the bus besides the CPU, the 4001s and the 4002s, like glue logic or a third party memory.
+pub trait BusDevice {
+    /// Put a value on D0-D3 if the device outputs in this phase, after the CPU and the memory chips drove theirs.
+    fn drive(&mut self, _signals: &mut Signals) {}
+
+    /// The lines at the end of the phase, once every chip has driven them.
+    fn sample(&mut self, _signals: &Signals) {}
+}
+
+/// Bus lines and the extra devices connected to them, see `Intel4004::clock_bus`.
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub struct Bus {
+    pub signals: Signals,
+    devices: Vec<Box<dyn BusDevice>>,
+}
+
+#[cfg(feature = "std")]
+impl Bus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attach(&mut self, device: Box<dyn BusDevice>) {
+        self.devices.push(device);
+    }
+
+    pub fn devices(&self) -> &[Box<dyn BusDevice>] {
+        &self.devices
+    }
+
+    pub(crate) fn drive_devices(&mut self) {
+        for device in self.devices.iter_mut() {
+            device.drive(&mut self.signals);
+        }
+    }
+
+    pub(crate) fn sample_devices(&mut self) {
+        for device in self.devices.iter_mut() {
+            device.sample(&self.signals);
+        }
+    }
+}
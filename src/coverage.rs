@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+
+use super::assembler::ListingLine;
+use super::disassembler::{disassemble, discover, ByteKind};
+use super::symbols::SymbolTable;
+
+// Coverage
+
+const ROM_SIZE: usize = 4096;
+
+type LineCoverage = BTreeMap<usize, (u64, Vec<(u64, u64)>)>;       // Line, executions and branch outcomes.
+
+fn is_branch(op_code: u8) -> bool {
+    matches!(op_code & 0xF0, 0x10 | 0x70)                           // JCN and ISZ
+}
+
+/// Executed ROM addresses and the taken/not-taken outcomes of every JCN and ISZ.
+pub struct Coverage {
+    executions: Vec<u64>,
+    taken: Vec<u64>,
+    not_taken: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            executions: vec![0; ROM_SIZE],
+            taken: vec![0; ROM_SIZE],
+            not_taken: vec![0; ROM_SIZE],
+        }
+    }
+
+    /// Record one executed instruction, `next_pc` is the PC after it ran.
+    pub fn record(&mut self, pc: u16, op_code: u8, next_pc: u16) {
+        let addr = pc as usize % ROM_SIZE;
+        self.executions[addr] += 1;
+
+        if is_branch(op_code) {
+            if next_pc == pc + 2 {
+                self.not_taken[addr] += 1;
+            } else {
+                self.taken[addr] += 1;
+            }
+        }
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions[addr as usize % ROM_SIZE]
+    }
+
+    /// Times a branch at `addr` was taken and not taken.
+    pub fn branch(&self, addr: u16) -> (u64, u64) {
+        let addr = addr as usize % ROM_SIZE;
+        (self.taken[addr], self.not_taken[addr])
+    }
+
+    /// Merge the results of another run.
+    pub fn merge(&mut self, other: &Coverage) {
+        for addr in 0..ROM_SIZE {
+            self.executions[addr] += other.executions[addr];
+            self.taken[addr] += other.taken[addr];
+            self.not_taken[addr] += other.not_taken[addr];
+        }
+    }
+
+    fn branch_marker(&self, addr: u16, op_code: u8) -> String {
+        if !is_branch(op_code) {
+            return String::new();
+        }
+
+        let (taken, not_taken) = self.branch(addr);
+        let missing = match (taken, not_taken) {
+            (0, 0) => "  ; never reached",
+            (0, _) => "  ; never taken",
+            (_, 0) => "  ; never falls through",
+            _ => "",
+        };
+        format!("  [taken {}, not taken {}]{}", taken, not_taken, missing)
+    }
+
+    /// Disassembly of the code reachable from the reset vector, unexecuted instructions are marked with '#####'.
+    pub fn annotated_listing(&self, rom: &[u8], symbols: Option<&SymbolTable>) -> String {
+        let map = discover(rom, &[0x000], symbols);
+        let mut text = String::new();
+
+        for addr in (0..rom.len().min(ROM_SIZE) as u16).filter(|a| map.kinds[*a as usize] == ByteKind::Code) {
+            if let Some(label) = symbols.and_then(|s| s.label_at(addr)) {
+                text.push_str(&format!("{:>17}{}:\n", "", label));
+            }
+
+            let count = match self.executions(addr) {
+                0 => "#####".to_string(),
+                n => n.to_string(),
+            };
+            let (instruction, _) = disassemble(rom, addr, symbols);
+            text.push_str(&format!("{:>10}  {:03X}  {}{}\n", count, addr, instruction, self.branch_marker(addr, rom[addr as usize])));
+        }
+        text
+    }
+
+    /// Assembler listing with execution counts, lines without instructions are left blank.
+    pub fn annotated_source(&self, listing: &[ListingLine]) -> String {
+        let mut text = String::new();
+
+        for line in listing {
+            let count = match line.instructions.iter().map(|a| self.executions(*a)).max() {
+                None => String::new(),
+                Some(0) => "#####".to_string(),
+                Some(n) => n.to_string(),
+            };
+            let branches: String = line.instructions.iter()
+                .map(|a| self.branch_marker(*a, line.bytes[(*a - line.address) as usize]))
+                .collect();
+
+            text.push_str(&format!("{:>10} {:>5}  {}{}\n", count, line.location.line, line.text, branches));
+        }
+        text
+    }
+
+    /// lcov tracefile keyed by assembler source lines. Macro expansions count towards the line of the call.
+    pub fn lcov(&self, listing: &[ListingLine]) -> String {
+        let mut files: BTreeMap<&str, LineCoverage> = BTreeMap::new();
+
+        for line in listing.iter().filter(|l| !l.instructions.is_empty()) {
+            let entry = files.entry(line.location.file.as_str()).or_default()
+                .entry(line.location.line).or_insert((0, Vec::new()));
+            for addr in &line.instructions {
+                entry.0 = entry.0.max(self.executions(*addr));
+
+                let op_code = line.bytes[(*addr - line.address) as usize];
+                if is_branch(op_code) {
+                    entry.1.push(self.branch(*addr));
+                }
+            }
+        }
+
+        let mut text = String::new();
+        for (file, lines) in files {
+            text.push_str(&format!("TN:\nSF:{}\n", file));
+            lcov_record(&mut text, &lines);
+        }
+        text
+    }
+
+    /// lcov tracefile for a ROM without source, line N is ROM address N - 1.
+    pub fn lcov_by_address(&self, rom: &[u8], name: &str) -> String {
+        let map = discover(rom, &[0x000], None);
+        let mut lines = BTreeMap::new();
+
+        for addr in (0..rom.len().min(ROM_SIZE) as u16).filter(|a| map.kinds[*a as usize] == ByteKind::Code) {
+            let branches = if is_branch(rom[addr as usize]) { vec![self.branch(addr)] } else { Vec::new() };
+            lines.insert(addr as usize + 1, (self.executions(addr), branches));
+        }
+
+        let mut text = format!("TN:\nSF:{}\n", name);
+        lcov_record(&mut text, &lines);
+        text
+    }
+}
+
+fn lcov_record(text: &mut String, lines: &LineCoverage) {
+    let (mut found, mut hit) = (0, 0);
+
+    for (line, (_, branches)) in lines {
+        for (block, (taken, not_taken)) in branches.iter().enumerate() {
+            let reached = taken + not_taken > 0;
+            for (branch, count) in [taken, not_taken].into_iter().enumerate() {
+                let count = if reached { count.to_string() } else { "-".to_string() };
+                text.push_str(&format!("BRDA:{},{},{},{}\n", line, block, branch, count));
+            }
+            found += 2;
+            hit += (*taken > 0) as usize + (*not_taken > 0) as usize;
+        }
+    }
+    text.push_str(&format!("BRF:{}\nBRH:{}\n", found, hit));
+
+    for (line, (count, _)) in lines {
+        text.push_str(&format!("DA:{},{}\n", line, count));
+    }
+    let executed = lines.values().filter(|(count, _)| *count > 0).count();
+    text.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), executed));
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
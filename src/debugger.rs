@@ -0,0 +1,258 @@
+use std::{
+    collections::BTreeSet,
+    io,
+};
+
+use super::disassembler::disassemble_cpu;
+use super::intel4004::Intel4004;
+use super::runner::CpuState;
+use super::symbols::SymbolTable;
+
+// Debugger
+
+pub const CONTINUE_STEPS: usize = 10_000_000;                       // Steps before `continue` gives up.
+
+pub const HELP: &str = "commands:
+  s [n]       step n instructions (default 1)
+  c           continue until a breakpoint
+  b <addr>    add a breakpoint at a label or address
+  d <addr>    delete a breakpoint
+  bl          list breakpoints
+  r           show registers
+  m           show RAM and I/O ports
+  t           toggle tracing during continue
+  reset       assert RESET, the ROM and breakpoints are kept
+  q           quit";
+
+/// Why `Debugger::run` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    StepLimit,
+    Undefined(u16),                                                 // Trapped on an undefined opcode.
+    Strict(u16),                                                    // A strict mode check failed in halt mode.
+    Uninitialized(u16),                                             // The sanitizer caught an undefined read in halt mode.
+}
+
+pub struct Debugger {
+    pub cpu: Intel4004,
+    symbols: SymbolTable,
+    breakpoints: BTreeSet<u16>,
+    trace: bool,                                                    // Print every executed instruction.
+}
+
+/// Populated RAM chips with their status characters and output ports, then the ROM ports.
+pub fn memory_text(cpu: &Intel4004) -> String {
+    let mut text = String::new();
+
+    for (n, chip) in cpu.ram[..cpu.get_ram_chips()].iter().enumerate() {
+        text.push_str(&format!("RAM {:X} (bank {}, chip {}) output={:X}\n", n, n / 4, n % 4, chip.output));
+        for register in 0..4 {
+            let characters: String = chip.ram[register * 16..register * 16 + 16].iter().map(|c| format!("{:X}", c)).collect();
+            let status: String = chip.status[register * 4..register * 4 + 4].iter().map(|c| format!("{:X}", c)).collect();
+            text.push_str(&format!("  {}: {} {}\n", register, characters, status));
+        }
+    }
+
+    let ports: Vec<String> = cpu.rom[..cpu.get_rom_chips()].iter().map(|chip| format!("{:X}", chip.io.value())).collect();
+    text.push_str(&format!("ROM ports: {}\n", ports.join(" ")));
+    text
+}
+
+impl Debugger {
+    pub fn new(cpu: Intel4004) -> Self {
+        Debugger {
+            cpu,
+            symbols: SymbolTable::new(),
+            breakpoints: BTreeSet::new(),
+            trace: false,
+        }
+    }
+
+    /// Load the symbol file written by the assembler.
+    pub fn load_symbols(&mut self, filename: &str) -> io::Result<()> {
+        self.symbols = SymbolTable::load(filename)?;
+        Ok(())
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Add a breakpoint at a label or address, returns the resolved address.
+    pub fn add_breakpoint(&mut self, location: &str) -> Option<u16> {
+        let addr = self.symbols.resolve(location)?;
+        self.breakpoints.insert(addr);
+        Some(addr)
+    }
+
+    pub fn remove_breakpoint(&mut self, location: &str) -> Option<u16> {
+        let addr = self.symbols.resolve(location)?;
+        self.breakpoints.remove(&addr).then_some(addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Breakpoint addresses with their labels.
+    pub fn list_breakpoints(&self) -> Vec<String> {
+        self.breakpoints.iter()
+            .map(|addr| format!("{:03X} {}", addr, self.symbols.format_address(*addr)))
+            .collect()
+    }
+
+    /// Instruction at `addr` with labels, as shown in traces.
+    pub fn trace_line(&self, addr: u16) -> String {
+        let (text, _) = disassemble_cpu(&self.cpu, addr, Some(&self.symbols));
+        let label = self.symbols.label_at(addr).map(|l| format!("{}:", l)).unwrap_or_default();
+
+        format!("{:03X}  {:<12} {}", addr, label, text)
+    }
+
+    /// Execute one instruction and return its trace line, with a note if it trapped and any strict mode or sanitizer
+    /// diagnostics. Stepping on a trap skips the undefined opcode, stepping after a diagnostic halt resumes.
+    pub fn step(&mut self) -> String {
+        self.advance(true)
+    }
+
+    /// `step`, the line is only built if it is `wanted` or trace is on. It is empty otherwise.
+    fn advance(&mut self, wanted: bool) -> String {
+        if let Some(addr) = self.cpu.get_trap() {
+            self.cpu.clear_trap();
+            self.cpu.set_pc((addr + 1) & 0xFFF);
+            return format!("skipped undefined opcode at {:03X}", addr);
+        }
+
+        let reported = match self.cpu.strict_mut() {
+            Some(strict) => {
+                strict.resume();
+                strict.diagnostics().len()
+            }
+            None => 0,
+        };
+        let sanitized = match self.cpu.sanitizer_mut() {
+            Some(sanitizer) => {
+                sanitizer.resume();
+                sanitizer.diagnostics().len()
+            }
+            None => 0,
+        };
+
+        if !wanted && !self.trace {
+            self.cpu.clock();
+            return String::new();
+        }
+
+        let mut line = self.trace_line(self.cpu.get_pc());
+        self.cpu.clock();
+        if let Some(addr) = self.cpu.get_trap() {
+            line.push_str(&format!("\nundefined opcode {:02X} at {:03X}", self.cpu.fetch_u8(addr), addr));
+        }
+        if let Some(strict) = self.cpu.strict() {
+            for diagnostic in &strict.diagnostics()[reported..] {
+                line.push_str(&format!("\nstrict: {}", diagnostic.message));
+            }
+        }
+        if let Some(sanitizer) = self.cpu.sanitizer() {
+            for diagnostic in &sanitizer.diagnostics()[sanitized..] {
+                line.push_str(&format!("\nuninitialized: {}", diagnostic.message));
+            }
+        }
+
+        if self.trace {
+            println!("{}", line);
+        }
+        line
+    }
+
+    /// Run until a breakpoint is reached or `max_steps` instructions have been executed. The instruction at the
+    /// current PC always executes, so calling `run` again continues past the breakpoint.
+    pub fn run(&mut self, max_steps: usize) -> StopReason {
+        for i in 0..max_steps {
+            let pc = self.cpu.get_pc();
+            if i > 0 && self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+            self.advance(false);
+            if let Some(addr) = self.cpu.get_trap() {
+                return StopReason::Undefined(addr);
+            }
+            if self.cpu.strict().is_some_and(|strict| strict.is_halted()) {
+                return StopReason::Strict(pc);
+            }
+            if self.cpu.sanitizer().is_some_and(|sanitizer| sanitizer.is_halted()) {
+                return StopReason::Uninitialized(pc);
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    /// Interpret one command line, see `HELP`. Returns the text to show, or `None` for quit.
+    pub fn execute(&mut self, line: &str) -> Option<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let output = match words.as_slice() {
+            [] => String::new(),
+            ["s" | "step"] => self.step(),
+            ["s" | "step", n] => match parse_count(n) {
+                Some(n) => (0..n).map(|_| self.step()).collect::<Vec<String>>().join("\n"),
+                None => format!("bad count '{}'", n),
+            },
+            ["c" | "continue"] => match self.run(CONTINUE_STEPS) {
+                StopReason::Breakpoint(addr) => format!("breakpoint {}", self.symbols.format_address(addr)),
+                StopReason::StepLimit => format!("stopped after {} steps", CONTINUE_STEPS),
+                StopReason::Undefined(addr) => format!("undefined opcode {:02X} at {}", self.cpu.fetch_u8(addr),
+                    self.symbols.format_address(addr)),
+                StopReason::Strict(addr) => {
+                    let diagnostic = self.cpu.strict().and_then(|strict| strict.diagnostics().last());
+                    format!("strict mode at {}: {}", self.symbols.format_address(addr),
+                        diagnostic.map_or("", |d| d.message.as_str()))
+                }
+                StopReason::Uninitialized(addr) => {
+                    let diagnostic = self.cpu.sanitizer().and_then(|sanitizer| sanitizer.diagnostics().last());
+                    format!("uninitialized read at {}: {}", self.symbols.format_address(addr),
+                        diagnostic.map_or("", |d| d.message.as_str()))
+                }
+            },
+            ["b" | "break", location] => match self.add_breakpoint(location) {
+                Some(addr) => format!("breakpoint at {:03X}", addr),
+                None => format!("unknown address '{}'", location),
+            },
+            ["d" | "delete", location] => match self.remove_breakpoint(location) {
+                Some(addr) => format!("deleted breakpoint at {:03X}", addr),
+                None => format!("no breakpoint at '{}'", location),
+            },
+            ["bl"] => self.list_breakpoints().join("\n"),
+            ["r" | "regs"] => CpuState::capture(&self.cpu).to_string(),
+            ["m" | "mem"] => memory_text(&self.cpu).trim_end().to_string(),
+            ["t" | "trace"] => {
+                self.trace = !self.trace;
+                format!("trace {}", if self.trace { "on" } else { "off" })
+            }
+            ["reset"] => {
+                self.cpu.reset();
+                self.trace_line(self.cpu.get_pc())
+            }
+            ["q" | "quit"] => return None,
+            ["h" | "help"] => HELP.to_string(),
+            _ => "unknown command, type 'help'".to_string(),
+        };
+        Some(output)
+    }
+}
+
+fn parse_count(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
@@ -1,11 +1,277 @@
 use super::intel4001::Intel4001;
 use super::intel4002::Intel4002;
 use super::intel4004::Intel4004;
+use super::opcodes::{Model, Op, Operands};
+use super::symbols::SymbolTable;
+
+pub use super::opcodes::{instruction_size, is_undefined};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
 
 use arbitrary_int::{u4};
 
 // Disassembler
 
+/// JCN condition as the assembler mnemonic when there is one.
+fn condition_name(condition: u8) -> String {
+    match condition {
+        0x1 => "TZ".to_string(),
+        0x2 => "C".to_string(),
+        0x4 => "Z".to_string(),
+        0x9 => "TN".to_string(),
+        0xA => "NC".to_string(),
+        0xC => "NZ".to_string(),
+        _ => condition.to_string(),
+    }
+}
+
+/// Disassemble the instruction at `addr`, returns the text and the size in bytes. Jump targets are shown as labels
+/// when symbols are given, undefined opcodes as DB so the output can be assembled again.
+pub fn disassemble(rom: &[u8], addr: u16, symbols: Option<&SymbolTable>) -> (String, u16) {
+    disassemble_for(Model::I4004, rom, addr, symbols)
+}
+
+/// `disassemble` with the instruction set of `model`.
+pub fn disassemble_for(model: Model, rom: &[u8], addr: u16, symbols: Option<&SymbolTable>) -> (String, u16) {
+    disassemble_with(model, |a| rom.get(a as usize).copied().unwrap_or(0x00), addr, symbols)
+}
+
+/// `disassemble` in the selected ROM bank of `cpu`, reading program memory in place instead of from an image.
+pub fn disassemble_cpu(cpu: &Intel4004, addr: u16, symbols: Option<&SymbolTable>) -> (String, u16) {
+    disassemble_with(cpu.get_model(), |a| cpu.fetch_u8(a), addr, symbols)
+}
+
+fn disassemble_with(model: Model, fetch: impl Fn(u16) -> u8, addr: u16, symbols: Option<&SymbolTable>) -> (String, u16) {
+    let op_code = fetch(addr);
+    let opa = op_code & 0x0F;
+    let data = fetch(addr + 1);
+
+    let target = |a: u16| match symbols {
+        Some(symbols) => symbols.format_address(a),
+        None => format!("0x{:03X}", a),
+    };
+    let short_target = ((addr + 2) & 0xF00) | data as u16;           // JCN and ISZ jump inside the page of the next instruction.
+    let long_target = ((opa as u16) << 8) | data as u16;
+
+    let instruction = model.decode(op_code);
+    let mnemonic = instruction.op.mnemonic();
+
+    let mut text = String::with_capacity(24);
+    text.push_str(mnemonic);
+
+    let _ = match instruction.operands {
+        _ if instruction.op == Op::Undefined => write!(text, "DB 0x{:02X}", op_code),
+        Operands::None => Ok(()),
+        Operands::Reg => write!(text, " R{}", opa),
+        Operands::Pair => write!(text, " P{}", opa >> 1),
+        Operands::Data4 => write!(text, " {}", opa),
+        Operands::PairData8 => write!(text, " P{}, 0x{:02X}", opa >> 1, data),
+        Operands::CondAddr8 => write!(text, " {}, {}", condition_name(opa), target(short_target)),
+        Operands::RegAddr8 => write!(text, " R{}, {}", opa, target(short_target)),
+        Operands::Addr12 => write!(text, " {}", target(long_target)),
+    };
+    (text, instruction.size)
+}
+
+// --- Code discovery ---
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteKind {
+    Unknown,                                                        // Never reached, emitted as DB.
+    Code,                                                           // First byte of an instruction.
+    Operand,                                                        // Second byte of a 2-word instruction.
+    Data,                                                           // Read by FIN.
+}
+
+/// Code found by following the control flow of a ROM image.
+pub struct CodeMap {
+    pub kinds: Vec<ByteKind>,
+    pub labels: BTreeMap<u16, String>,                              // Jump, call and FIN table targets.
+    fin_tables: BTreeMap<u16, u16>,                                 // FIM P0 address -> table it points to.
+}
+
+/// Follow the control flow from the entry points (the reset vector is 0x000). JUN, JMS, JCN and ISZ targets are
+/// followed, BBL and JIN end a path. A FIN after a FIM P0 in the same path marks the byte it reads as data.
+pub fn discover(rom: &[u8], entry_points: &[u16], symbols: Option<&SymbolTable>) -> CodeMap {
+    discover_for(Model::I4004, rom, entry_points, symbols)
+}
+
+/// `discover` with the instruction set of `model`, BBS ends a path like BBL.
+pub fn discover_for(model: Model, rom: &[u8], entry_points: &[u16], symbols: Option<&SymbolTable>) -> CodeMap {
+    let mut map = CodeMap {
+        kinds: vec![ByteKind::Unknown; rom.len()],
+        labels: BTreeMap::new(),
+        fin_tables: BTreeMap::new(),
+    };
+    let mut work: Vec<u16> = entry_points.to_vec();
+
+    let add_label = |map: &mut CodeMap, addr: u16, prefix: &str| {
+        let name = symbols.and_then(|s| s.label_at(addr)).map(str::to_string)
+            .unwrap_or_else(|| format!("{}_{:03X}", prefix, addr));
+        map.labels.entry(addr).or_insert(name);
+    };
+
+    for entry in entry_points {
+        add_label(&mut map, *entry, "L");
+    }
+
+    while let Some(start) = work.pop() {
+        let mut addr = start;
+        let mut p0 = None;                                          // Value loaded into P0 by FIM on this path.
+        let mut p0_addr = 0;
+
+        while (addr as usize) < rom.len() && map.kinds[addr as usize] != ByteKind::Code {
+            let op_code = rom[addr as usize];
+            let size = model.decode(op_code).size;
+            let end = (addr + size) as usize;
+
+            if model.is_undefined(op_code) || end > rom.len() || map.kinds[addr as usize..end].iter().any(|k| *k == ByteKind::Code || *k == ByteKind::Operand) {
+                break;
+            }
+
+            map.kinds[addr as usize] = ByteKind::Code;
+            if size == 2 {
+                map.kinds[addr as usize + 1] = ByteKind::Operand;
+            }
+
+            let opa = op_code & 0x0F;
+            let data = rom.get(addr as usize + 1).copied().unwrap_or(0) as u16;
+            let next = addr + size;
+            let short_target = (next & 0xF00) | data;
+            let long_target = ((opa as u16) << 8) | data;
+
+            match model.decode(op_code).op {
+                Op::Jcn | Op::Isz => {
+                    add_label(&mut map, short_target, "L");
+                    work.push(short_target);
+                }
+                Op::Fim if opa == 0x0 => {
+                    p0 = Some(data);
+                    p0_addr = addr;
+                }
+                Op::Fin => {
+                    if let Some(p0) = p0 {
+                        let table = (next & 0xF00) | p0;
+                        if let Some(kind) = map.kinds.get_mut(table as usize) {
+                            if *kind == ByteKind::Unknown {
+                                *kind = ByteKind::Data;
+                            }
+                            add_label(&mut map, table, "data");
+                            map.fin_tables.insert(p0_addr, table);
+                        }
+                    }
+                }
+                Op::Jin => break,                                   // Target unknown.
+                Op::Jun => {
+                    add_label(&mut map, long_target, "L");
+                    work.push(long_target);
+                    break;
+                }
+                Op::Jms => {
+                    add_label(&mut map, long_target, "sub");
+                    work.push(long_target);
+                }
+                Op::Bbl | Op::Bbs => break,
+                _ => {}
+            }
+
+            // Anything else writing R0 or R1, or switching register banks, makes the FIN table unknown.
+            if matches!(op_code, 0x30 | 0x60 | 0x61 | 0x70 | 0x71 | 0xB0 | 0xB1)
+                || matches!(model.decode(op_code).op, Op::Sb0 | Op::Sb1) {
+                p0 = None;
+            }
+
+            addr = next;
+        }
+    }
+
+    map
+}
+
+/// Disassemble a whole ROM image into source that assembles back to the same bytes. Code is found with `discover`,
+/// everything else is emitted as DB.
+pub fn disassemble_source(rom: &[u8], entry_points: &[u16], symbols: Option<&SymbolTable>) -> String {
+    disassemble_source_for(Model::I4004, rom, entry_points, symbols)
+}
+
+/// `disassemble_source` with the instruction set of `model`.
+pub fn disassemble_source_for(model: Model, rom: &[u8], entry_points: &[u16], symbols: Option<&SymbolTable>) -> String {
+    let map = discover_for(model, rom, entry_points, symbols);
+
+    // Labels in the middle of an instruction or outside the image can't be placed on a line, define them with EQU.
+    let mut table = SymbolTable::new();
+    let mut source = String::from("; Disassembled by intel4004_emu\n\n");
+    let mut equates = false;
+
+    for (addr, name) in &map.labels {
+        table.insert(name, *addr);
+
+        if map.kinds.get(*addr as usize).is_none_or(|k| *k == ByteKind::Operand) {
+            source.push_str(&format!("{} EQU 0x{:03X}\n", name, addr));
+            equates = true;
+        }
+    }
+    if equates {
+        source.push('\n');
+    }
+
+    let mut addr = 0;
+    while (addr as usize) < rom.len() {
+        if let Some(name) = map.labels.get(&addr) {
+            source.push_str(&format!("{}:\n", name));
+        }
+
+        if map.kinds[addr as usize] == ByteKind::Code {
+            let text = match map.fin_tables.get(&addr) {
+                Some(table) => format!("FIM P0, {} & 0xFF", map.labels[table]),
+                None => disassemble_for(model, rom, addr, Some(&table)).0,
+            };
+            source.push_str(&format!("        {}\n", text));
+            addr += model.decode(rom[addr as usize]).size;
+            continue;
+        }
+
+        // Up to 8 data bytes per line, stopping at code and labels.
+        let mut bytes = vec![format!("0x{:02X}", rom[addr as usize])];
+        addr += 1;
+        while (addr as usize) < rom.len() && bytes.len() < 8 && map.kinds[addr as usize] != ByteKind::Code
+            && !map.labels.contains_key(&addr) {
+            bytes.push(format!("0x{:02X}", rom[addr as usize]));
+            addr += 1;
+        }
+        source.push_str(&format!("        DB {}\n", bytes.join(", ")));
+    }
+
+    source
+}
+
+/// The part of `rom` in the ROM bank selected on `cpu`, which is what its PC addresses.
+pub fn selected_bank<'a>(cpu: &Intel4004, rom: &'a [u8]) -> &'a [u8] {
+    rom.get(cpu.get_rom_bank() * 0x1000..).unwrap_or(&[])
+}
+
+/// Print `count` instructions starting at `start`, with labels when symbols are given.
+pub fn print_disassembly(rom: &[u8], start: u16, count: usize, symbols: Option<&SymbolTable>) {
+    let mut addr = start;
+
+    for _ in 0..count {
+        if addr as usize >= rom.len() {
+            break;
+        }
+
+        if let Some(label) = symbols.and_then(|s| s.label_at(addr)) {
+            println!("{}:", label);
+        }
+
+        let (text, size) = disassemble(rom, addr, symbols);
+        let bytes: Vec<String> = (addr..addr + size).map(|a| format!("{:02X}", rom.get(a as usize).copied().unwrap_or(0))).collect();
+        println!("  {:03X}  {:<6} {}", addr, bytes.join(" "), text);
+
+        addr += size;
+    }
+}
+
 pub fn print_cpu_state(cpu: &Intel4004) {
 
     println!("\n-- CPU --: ");
@@ -35,12 +301,12 @@ pub fn print_index(index: &[u4; 16]) {
     println!(" {:#01X} {:#01X} {:#01X} {:#01X}", index[12].value(), index[13].value(), index[14].value(), index[15].value());
 }
 
-pub fn print_stack(stack: &[u16; 3]) {
+pub fn print_stack(stack: &[u16]) {
     println!("\nStack:");
 
-    println!(" Level 1: {:#02X}", stack[0]);
-    println!(" Level 2: {:#02X}", stack[1]);
-    println!(" Level 3: {:#02X}", stack[2]);
+    for (level, addr) in stack.iter().enumerate() {
+        println!(" Level {}: {:#02X}", level + 1, addr);
+    }
 }
 
 pub fn print_rom(rom: &Intel4001) {
@@ -0,0 +1,298 @@
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::c_void;
+use std::{ptr, slice};
+
+use arbitrary_int::u4;
+
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_CHIPS};
+use super::peripherals::Peripheral;
+use super::runner::{run, ExitReason, RunLimits};
+
+// C API, declared in include/intel4004.h. Every function takes a machine returned by `i4004_new` that was not freed,
+// pointer arguments must point to what their documentation says.
+
+/// A 4004 with its ROM and RAM chips.
+pub struct I4004Machine {
+    cpu: Intel4004,
+}
+
+/// Called after WRR or WMP with the chip number and the value written, can be NULL.
+pub type I4004PortWrite = Option<extern "C" fn(user: *mut c_void, chip: u8, value: u8)>;
+
+/// Called by RDR, returns the 4 bits on the I/O lines of the chip or -1 to read back the last value written. Can be
+/// NULL.
+pub type I4004PortRead = Option<extern "C" fn(user: *mut c_void, chip: u8) -> i32>;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I4004Exit {
+    CycleLimit,
+    InstructionLimit,
+    Address,                                                        // PC reached the stop address.
+    Halt,                                                           // JUN to itself.
+    Port,
+    Watchpoint,
+    Undefined,                                                      // Trapped on an undefined opcode.
+    Strict,                                                         // A strict mode check failed.
+    Uninitialized,                                                  // The sanitizer caught an undefined read.
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct I4004RunResult {
+    pub exit: I4004Exit,
+    pub address: u16,                                               // PC when the run stopped.
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+/// Forwards port accesses to the callbacks given to `i4004_set_io`.
+struct Callbacks {
+    rom_write: I4004PortWrite,
+    rom_read: I4004PortRead,
+    ram_write: I4004PortWrite,
+    user: *mut c_void,
+}
+
+impl Peripheral for Callbacks {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        if let Some(callback) = self.rom_write {
+            callback(self.user, chip as u8, value);
+        }
+    }
+
+    fn rom_port_read(&mut self, chip: usize) -> Option<u8> {
+        let value = self.rom_read?(self.user, chip as u8);
+        (value >= 0).then_some(value as u8 & 0xF)
+    }
+
+    fn ram_port_write(&mut self, chip: usize, value: u8) {
+        if let Some(callback) = self.ram_write {
+            callback(self.user, chip as u8, value);
+        }
+    }
+}
+
+unsafe fn cpu<'a>(machine: *mut I4004Machine) -> &'a mut Intel4004 {
+    &mut (*machine).cpu
+}
+
+// --- Machine ---
+
+/// New machine with 1-16 populated ROM and RAM chips, 0 populates all of them. Free it with `i4004_free`.
+#[no_mangle]
+pub extern "C" fn i4004_new(rom_chips: u32, ram_chips: u32) -> *mut I4004Machine {
+    let count = |n: u32, all: usize| if n == 0 { all } else { n as usize };
+    let cpu = Intel4004::with_chips(count(rom_chips, ROM_CHIPS), count(ram_chips, RAM_CHIPS));
+    Box::into_raw(Box::new(I4004Machine { cpu }))
+}
+
+/// NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_free(machine: *mut I4004Machine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// Copy `length` bytes of ROM image to the chips starting at address 0. Returns 0, or -1 if it is larger than the
+/// populated ROM.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_load_rom(machine: *mut I4004Machine, bytes: *const u8, length: usize) -> i32 {
+    let cpu = cpu(machine);
+    if length > cpu.get_rom_chips() * 256 || (bytes.is_null() && length > 0) {
+        return -1;
+    }
+    if length > 0 {
+        cpu.load_bytes(slice::from_raw_parts(bytes, length));
+    }
+    0
+}
+
+/// Send port accesses to the callbacks, any of them can be NULL. `user` is passed back to every call, the callbacks must
+/// stay callable with it as long as the machine runs. Replaces the callbacks set before.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_io(machine: *mut I4004Machine, rom_write: I4004PortWrite, rom_read: I4004PortRead,
+    ram_write: I4004PortWrite, user: *mut c_void) {
+    cpu(machine).attach_peripheral(Box::new(Callbacks { rom_write, rom_read, ram_write, user }));
+}
+
+// --- Execution ---
+
+/// Execute one instruction, returns the instruction cycles it took.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_step(machine: *mut I4004Machine) -> u32 {
+    let cpu = cpu(machine);
+    let size = super::opcodes::decode(cpu.fetch_u8(cpu.get_pc())).size;
+    cpu.clock();
+    size as u32
+}
+
+/// Assert RESET: clears the CPU registers, RAM and ports, the ROM is kept.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_reset(machine: *mut I4004Machine) {
+    cpu(machine).reset();
+}
+
+/// Run until a JUN to itself, the PC reaching `stop_at` (-1 for none) or `max_cycles` instruction cycles, then fill
+/// `result` unless it is NULL. Returns 0 if the program halted or reached `stop_at`, 1 if the cycles ran out, 2 on a
+/// trapped undefined opcode, 3 on a failed strict mode check and 4 on an uninitialized read.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_run(machine: *mut I4004Machine, max_cycles: u64, stop_at: i32,
+    result: *mut I4004RunResult) -> i32 {
+    let mut limits = RunLimits::new();
+    limits.max_cycles(max_cycles);
+    limits.halt_on_self_jump(true);
+    if stop_at >= 0 {
+        limits.stop_at(stop_at as u16 & 0xFFF);
+    }
+
+    let outcome = run(cpu(machine), &limits);
+    let exit = match outcome.reason {
+        ExitReason::CycleLimit => I4004Exit::CycleLimit,
+        ExitReason::InstructionLimit => I4004Exit::InstructionLimit,
+        ExitReason::Address(_) => I4004Exit::Address,
+        ExitReason::Halt(_) => I4004Exit::Halt,
+        ExitReason::Port(..) => I4004Exit::Port,
+        ExitReason::Watchpoint(..) => I4004Exit::Watchpoint,
+        ExitReason::Undefined(..) => I4004Exit::Undefined,
+        ExitReason::Strict(_) => I4004Exit::Strict,
+        ExitReason::Uninitialized(_) => I4004Exit::Uninitialized,
+    };
+    if !result.is_null() {
+        ptr::write(result, I4004RunResult {
+            exit,
+            address: outcome.state.pc,
+            instructions: outcome.instructions,
+            cycles: outcome.cycles,
+        });
+    }
+    match exit {
+        I4004Exit::CycleLimit | I4004Exit::InstructionLimit => 1,
+        I4004Exit::Undefined => 2,
+        I4004Exit::Strict => 3,
+        I4004Exit::Uninitialized => 4,
+        _ => 0,
+    }
+}
+
+// --- Registers ---
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_pc(machine: *mut I4004Machine) -> u16 {
+    cpu(machine).get_pc()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_pc(machine: *mut I4004Machine, pc: u16) {
+    cpu(machine).set_pc(pc & 0xFFF);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_acc(machine: *mut I4004Machine) -> u8 {
+    cpu(machine).get_acc()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_acc(machine: *mut I4004Machine, acc: u8) {
+    cpu(machine).set_acc(acc & 0xF);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_carry(machine: *mut I4004Machine) -> bool {
+    cpu(machine).get_carry()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_carry(machine: *mut I4004Machine, carry: bool) {
+    cpu(machine).set_carry(carry);
+}
+
+/// Level of the TEST pin.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_test(machine: *mut I4004Machine) -> bool {
+    cpu(machine).get_test()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_test(machine: *mut I4004Machine, test: bool) {
+    cpu(machine).set_test(test);
+}
+
+/// Index register 0-15, 0 for others.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_reg(machine: *mut I4004Machine, r: u8) -> u8 {
+    cpu(machine).get_index().get(r as usize).map_or(0, |value| value.value())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_set_reg(machine: *mut I4004Machine, r: u8, value: u8) {
+    if r < 16 {
+        cpu(machine).set_index(r as usize, u4::new(value & 0xF));
+    }
+}
+
+/// Stack level 0-2, 0 for others.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_stack(machine: *mut I4004Machine, level: u8) -> u16 {
+    cpu(machine).get_stack().get(level as usize).copied().unwrap_or(0)
+}
+
+/// Address sent by the last SRC.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_get_src(machine: *mut I4004Machine) -> u8 {
+    cpu(machine).get_ram_addrs()
+}
+
+// --- RAM and ports ---
+
+/// RAM character 0-63 of chip 0-15 (4 * bank + chip), 0 outside them.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_read_ram(machine: *mut I4004Machine, chip: u8, index: u8) -> u8 {
+    let cpu = cpu(machine);
+    cpu.ram.get(chip as usize).and_then(|ram| ram.ram.get(index as usize)).copied().unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_write_ram(machine: *mut I4004Machine, chip: u8, index: u8, value: u8) {
+    let chip = cpu(machine).ram.get_mut(chip as usize);
+    if let Some(character) = chip.and_then(|ram| ram.ram.get_mut(index as usize)) {
+        *character = value & 0xF;
+    }
+}
+
+/// Status character 0-15 of a RAM chip, 4 per register.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_read_status(machine: *mut I4004Machine, chip: u8, index: u8) -> u8 {
+    let cpu = cpu(machine);
+    cpu.ram.get(chip as usize).and_then(|ram| ram.status.get(index as usize)).copied().unwrap_or(0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn i4004_write_status(machine: *mut I4004Machine, chip: u8, index: u8, value: u8) {
+    let chip = cpu(machine).ram.get_mut(chip as usize);
+    if let Some(character) = chip.and_then(|ram| ram.status.get_mut(index as usize)) {
+        *character = value & 0xF;
+    }
+}
+
+/// Output lines of a RAM chip, written by WMP.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_read_ram_port(machine: *mut I4004Machine, chip: u8) -> u8 {
+    cpu(machine).ram.get(chip as usize).map_or(0, |ram| ram.output)
+}
+
+/// I/O lines of a ROM chip, written by WRR.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_read_rom_port(machine: *mut I4004Machine, chip: u8) -> u8 {
+    cpu(machine).rom.get(chip as usize).map_or(0, |rom| rom.io.value())
+}
+
+/// Drive the I/O lines of a ROM chip, read by RDR when no read callback answers.
+#[no_mangle]
+pub unsafe extern "C" fn i4004_write_rom_port(machine: *mut I4004Machine, chip: u8, value: u8) {
+    if let Some(rom) = cpu(machine).rom.get_mut(chip as usize) {
+        rom.io = u4::new(value & 0xF);
+    }
+}
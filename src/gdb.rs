@@ -0,0 +1,487 @@
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use arbitrary_int::u4;
+
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_CHIPS};
+use super::opcodes::Model;
+
+// GDB remote serial protocol
+
+pub const RAM_BASE: u16 = 0x1000;                                   // RAM characters, chip * 64 + character.
+pub const STATUS_BASE: u16 = 0x2000;                                // Status characters, chip * 16 + character.
+const POLL_INSTRUCTIONS: u64 = 10_000;                              // Instructions between checks for an interrupt.
+const STACK_REGISTER: usize = 19;                                   // After PC, ACC, CY and R0-R15.
+
+/// Register layout reported to gdb: pc, acc, cy, r0-r15, one register per stack level and on the 4040 the register
+/// and ROM banks. PC and the stack levels are 16 bits, the rest 8, all little endian.
+fn target_xml(model: Model) -> String {
+    let mut regs = vec![r#"<reg name="pc" bitsize="16" type="code_ptr" regnum="0"/>"#.to_string()];
+    regs.extend(["acc", "cy"].map(|name| format!(r#"<reg name="{}" bitsize="8"/>"#, name)));
+    regs.extend((0..16).map(|n| format!(r#"<reg name="r{}" bitsize="8"/>"#, n)));
+    regs.extend((0..model.stack_levels()).map(|n| format!(r#"<reg name="s{}" bitsize="16" type="code_ptr"/>"#, n)));
+    if model == Model::I4040 {
+        regs.extend(["sb", "db"].map(|name| format!(r#"<reg name="{}" bitsize="8"/>"#, name)));
+    }
+
+    let regs: Vec<String> = regs.iter().map(|reg| format!("    {}\n", reg)).collect();
+    format!(r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.intel4004.core">
+{}  </feature>
+</target>"#, regs.concat())
+}
+
+/// Why the target stopped, turned into a stop reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Step,
+    Breakpoint,
+    Watchpoint(u16),
+    Interrupt,
+    Undefined,                                                      // Trapped on an undefined opcode.
+    Check,                                                          // Strict mode or the sanitizer halted the CPU.
+    Halted,                                                         // HLT or the STOP pin, 4040 only.
+}
+
+/// Serves one gdb connection. Memory is one address space: ROM at 0x000-0xFFF, then one nibble per byte for the RAM
+/// characters at `RAM_BASE` and the status characters at `STATUS_BASE`.
+pub struct GdbStub {
+    pub cpu: Intel4004,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, u8)>,                                    // Address and value when the run started.
+    no_ack: bool,                                                   // QStartNoAckMode was accepted.
+}
+
+impl GdbStub {
+    pub fn new(cpu: Intel4004) -> Self {
+        GdbStub {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            no_ack: false,
+        }
+    }
+
+    // --- Memory space ---
+
+    pub fn read_memory(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x000..=0xFFF => Some(self.cpu.fetch_u8(addr)),
+            _ => match self.ram_location(addr)? {
+                (chip, index, false) => Some(self.cpu.ram[chip].ram[index]),
+                (chip, index, true) => Some(self.cpu.ram[chip].status[index]),
+            },
+        }
+    }
+
+    /// ROM writes go to the selected bank like reads. Writes to unpopulated chips are ignored, like the hardware does.
+    pub fn write_memory(&mut self, addr: u16, value: u8) -> Option<()> {
+        match addr {
+            0x000..=0xFFF => {
+                let chip = self.cpu.get_rom_bank() * ROM_CHIPS + (addr as usize >> 8);
+                if chip < self.cpu.get_rom_chips() {
+                    self.cpu.rom[chip].rom[addr as usize & 0xFF] = value;
+                }
+            }
+            _ => match self.ram_location(addr)? {
+                (chip, _, _) if chip >= self.cpu.get_ram_chips() => {}
+                (chip, index, false) => self.cpu.ram[chip].ram[index] = value & 0xF,
+                (chip, index, true) => self.cpu.ram[chip].status[index] = value & 0xF,
+            },
+        }
+        Some(())
+    }
+
+    /// Chip, index and whether it is a status character.
+    fn ram_location(&self, addr: u16) -> Option<(usize, usize, bool)> {
+        let ram_end = RAM_BASE + RAM_CHIPS as u16 * 64;
+        let status_end = STATUS_BASE + RAM_CHIPS as u16 * 16;
+
+        match addr {
+            a if (RAM_BASE..ram_end).contains(&a) => {
+                let offset = (a - RAM_BASE) as usize;
+                Some((offset / 64, offset % 64, false))
+            }
+            a if (STATUS_BASE..status_end).contains(&a) => {
+                let offset = (a - STATUS_BASE) as usize;
+                Some((offset / 16, offset % 16, true))
+            }
+            _ => None,
+        }
+    }
+
+    // --- Registers ---
+
+    fn register_count(&self) -> usize {
+        let banks = if self.cpu.get_model() == Model::I4040 { 2 } else { 0 };
+        STACK_REGISTER + self.cpu.get_stack().len() + banks
+    }
+
+    fn register(&self, n: usize) -> Vec<u8> {
+        let levels = self.cpu.get_stack().len();
+        match n {
+            0 => self.cpu.get_pc().to_le_bytes().to_vec(),
+            1 => vec![self.cpu.get_acc()],
+            2 => vec![self.cpu.get_carry() as u8],
+            3..=18 => vec![self.cpu.get_index()[n - 3].value()],
+            _ if n < STACK_REGISTER + levels => self.cpu.get_stack()[n - STACK_REGISTER].to_le_bytes().to_vec(),
+            _ if n == STACK_REGISTER + levels => vec![self.cpu.get_register_bank() as u8],
+            _ => vec![self.cpu.get_rom_bank() as u8],
+        }
+    }
+
+    fn set_register(&mut self, n: usize, bytes: &[u8]) {
+        let levels = self.cpu.get_stack().len();
+        let word = u16::from_le_bytes([bytes[0], *bytes.get(1).unwrap_or(&0)]) & 0xFFF;
+        match n {
+            0 => self.cpu.set_pc(word),
+            1 => self.cpu.set_acc(bytes[0] & 0xF),
+            2 => self.cpu.set_carry(bytes[0] != 0),
+            3..=18 => self.cpu.set_index(n - 3, u4::new(bytes[0] & 0xF)),
+            _ if n < STACK_REGISTER + levels => {
+                let mut stack = self.cpu.get_stack().to_vec();
+                stack[n - STACK_REGISTER] = word;
+                self.cpu.set_stack(&stack);
+            }
+            _ if n == STACK_REGISTER + levels => self.cpu.set_register_bank((bytes[0] & 1) as usize),
+            _ => self.cpu.set_rom_bank((bytes[0] & 1) as usize),
+        }
+    }
+
+    fn register_size(&self, n: usize) -> usize {
+        if n == 0 || (STACK_REGISTER..STACK_REGISTER + self.cpu.get_stack().len()).contains(&n) { 2 } else { 1 }
+    }
+
+    // --- Execution ---
+
+    /// Execute until a breakpoint or a watchpoint hits, the CPU stops executing or `interrupted` returns true. It is
+    /// asked every `POLL_INSTRUCTIONS` instructions. The first instruction always executes, to leave a breakpoint.
+    fn resume(&mut self, interrupted: &mut dyn FnMut() -> bool) -> Stop {
+        for i in 0..self.watchpoints.len() {
+            self.watchpoints[i].1 = self.read_memory(self.watchpoints[i].0).unwrap_or(0);
+        }
+
+        let mut count: u64 = 0;
+        loop {
+            self.cpu.clock();
+            count += 1;
+
+            if let Some(stop) = self.check_watchpoints().or_else(|| self.idle()) {
+                return stop;
+            }
+            if self.breakpoints.contains(&self.cpu.get_pc()) {
+                return Stop::Breakpoint;
+            }
+            if count.is_multiple_of(POLL_INSTRUCTIONS) && interrupted() {
+                return Stop::Interrupt;
+            }
+        }
+    }
+
+    /// Why the CPU will not run the next instruction, if it won't.
+    fn idle(&self) -> Option<Stop> {
+        if self.cpu.get_trap().is_some() {
+            Some(Stop::Undefined)
+        } else if self.cpu.strict().is_some_and(|strict| strict.is_halted())
+            || self.cpu.sanitizer().is_some_and(|sanitizer| sanitizer.is_halted()) {
+            Some(Stop::Check)
+        } else if self.cpu.is_halted() || self.cpu.get_stop_ack() {
+            Some(Stop::Halted)
+        } else {
+            None
+        }
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        for i in 0..self.watchpoints.len() {
+            let (addr, old) = self.watchpoints[i];
+            let new = self.read_memory(addr).unwrap_or(0);
+            if new != old {
+                self.watchpoints[i].1 = new;
+                return Some(Stop::Watchpoint(addr));
+            }
+        }
+        None
+    }
+
+    fn stop_reply(stop: Stop) -> String {
+        match stop {
+            Stop::Step | Stop::Breakpoint => "S05".to_string(),
+            Stop::Watchpoint(addr) => format!("T05watch:{:x};", addr),
+            Stop::Interrupt => "S02".to_string(),
+            Stop::Undefined => "S04".to_string(),                   // SIGILL.
+            Stop::Check => "S06".to_string(),                       // SIGABRT.
+            Stop::Halted => "S11".to_string(),                      // SIGSTOP.
+        }
+    }
+
+    // --- Packets ---
+
+    /// Reply to one packet, `None` when the session ends. Unsupported packets get an empty reply.
+    pub fn handle(&mut self, packet: &str, interrupted: &mut dyn FnMut() -> bool) -> Option<String> {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => (0..self.register_count()).flat_map(|n| self.register(n)).map(|b| format!("{:02x}", b)).collect(),
+            Some(b'G') => {
+                let bytes = decode_hex(&packet[1..]).unwrap_or_default();
+                let mut offset = 0;
+                for n in 0..self.register_count() {
+                    let size = self.register_size(n);
+                    if offset + size > bytes.len() {
+                        break;
+                    }
+                    self.set_register(n, &bytes[offset..offset + size]);
+                    offset += size;
+                }
+                "OK".to_string()
+            }
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16) {
+                Ok(n) if n < self.register_count() => self.register(n).iter().map(|b| format!("{:02x}", b)).collect(),
+                _ => "E01".to_string(),
+            },
+            Some(b'P') => match packet[1..].split_once('=') {
+                Some((n, value)) => match (usize::from_str_radix(n, 16), decode_hex(value)) {
+                    (Ok(n), Some(bytes)) if n < self.register_count() && !bytes.is_empty() => {
+                        self.set_register(n, &bytes);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            Some(b'm') => self.read_packet(&packet[1..]).unwrap_or_else(|| "E01".to_string()),
+            Some(b'M') => self.write_packet(&packet[1..]).unwrap_or_else(|| "E01".to_string()),
+            Some(b's') => {
+                self.resume_at(&packet[1..]);
+                self.cpu.clock();
+                Self::stop_reply(self.idle().unwrap_or(Stop::Step))
+            }
+            Some(b'c') => {
+                self.resume_at(&packet[1..]);
+                let stop = self.resume(interrupted);
+                Self::stop_reply(stop)
+            }
+            Some(b'Z') | Some(b'z') => self.point_packet(packet).unwrap_or_else(|| "E01".to_string()),
+            Some(b'H') => "OK".to_string(),
+            Some(b'D') => return None,
+            Some(b'k') => return None,
+            _ => self.query(packet),
+        };
+        Some(reply)
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+".to_string();
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let xml = target_xml(self.cpu.get_model());
+            return match range.split_once(',') {
+                Some((offset, length)) => {
+                    let offset = usize::from_str_radix(offset, 16).unwrap_or(0).min(xml.len());
+                    let length = usize::from_str_radix(length, 16).unwrap_or(0);
+                    let end = (offset + length).min(xml.len());
+                    format!("{}{}", if end == xml.len() { 'l' } else { 'm' }, &xml[offset..end])
+                }
+                None => "E01".to_string(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn resume_at(&mut self, addr: &str) {
+        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+            self.cpu.set_pc(addr & 0xFFF);
+        }
+    }
+
+    /// `addr,length`, stops at the first unmapped address.
+    fn read_packet(&self, args: &str) -> Option<String> {
+        let (addr, length) = args.split_once(',')?;
+        let addr = u16::from_str_radix(addr, 16).ok()?;
+        let length = u16::from_str_radix(length, 16).ok()?;
+
+        let bytes: Vec<u8> = (0..length).map_while(|i| self.read_memory(addr.checked_add(i)?)).collect();
+        if bytes.is_empty() && length > 0 {
+            return None;
+        }
+        Some(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    /// `addr,length:data`.
+    fn write_packet(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, _) = range.split_once(',')?;
+        let addr = u16::from_str_radix(addr, 16).ok()?;
+
+        for (i, byte) in decode_hex(data)?.into_iter().enumerate() {
+            self.write_memory(addr.checked_add(i as u16)?, byte)?;
+        }
+        Some("OK".to_string())
+    }
+
+    /// `Z<type>,addr,kind` inserts and `z` removes. Software and hardware breakpoints are the same thing here, only
+    /// write watchpoints are supported because they compare values.
+    fn point_packet(&mut self, packet: &str) -> Option<String> {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next()?;
+        let addr = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let length = fields.next().and_then(|l| u16::from_str_radix(l, 16).ok()).unwrap_or(1).max(1);
+
+        match (kind, insert) {
+            ("0" | "1", true) => {
+                self.breakpoints.insert(addr & 0xFFF);
+            }
+            ("0" | "1", false) => {
+                self.breakpoints.remove(&(addr & 0xFFF));
+            }
+            ("2", true) => {
+                for a in addr..addr.checked_add(length)? {
+                    self.read_memory(a)?;
+                    self.watchpoints.push((a, 0));
+                }
+            }
+            ("2", false) => {
+                let end = addr.checked_add(length)?;
+                self.watchpoints.retain(|(a, _)| !(addr..end).contains(a));
+            }
+            _ => return Some(String::new()),
+        }
+        Some("OK".to_string())
+    }
+
+    // --- Transport ---
+
+    /// Serve packets on a connected stream until gdb detaches or disconnects.
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;                                  // Packets are small and every one waits for a reply.
+        let mut connection = Connection { stream, pending: Vec::new() };
+
+        while let Some(packet) = connection.read_packet(self.no_ack)? {
+            let mut interrupted = || connection.poll_interrupt();
+            match self.handle(&packet, &mut interrupted) {
+                Some(reply) => connection.send(&reply, self.no_ack)?,
+                None => {
+                    connection.send("OK", self.no_ack)?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for one gdb connection on `addr` and serve it.
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        self.serve(stream)
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    pending: Vec<u8>,                                               // Bytes read while polling for an interrupt.
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if !self.pending.is_empty() {
+            return Ok(Some(self.pending.remove(0)));
+        }
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Next packet payload, acknowledged unless in no-ack mode. A packet with a bad checksum is asked for again.
+    fn read_packet(&mut self, no_ack: bool) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,                                // Acks and stray interrupts.
+            }
+
+            let mut payload = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => payload.push(byte),
+                }
+            }
+            let (Some(high), Some(low)) = (self.read_byte()?, self.read_byte()?) else {
+                return Ok(None);
+            };
+
+            let expected = decode_hex(&String::from_utf8_lossy(&[high, low])).and_then(|b| b.first().copied());
+            let valid = expected == Some(checksum(&payload));
+            if !no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&payload).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, payload: &str, no_ack: bool) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", payload, checksum(payload.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// gdb sends a bare 0x03 to stop a running target.
+    fn poll_interrupt(&mut self) -> bool {
+        let mut buffer = [0; 64];
+        if self.stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let read = self.stream.read(&mut buffer);
+        let _ = self.stream.set_nonblocking(false);
+
+        match read {
+            Ok(n) if n > 0 => {
+                let interrupted = buffer[..n].contains(&0x03);
+                self.pending.extend(buffer[..n].iter().filter(|b| **b != 0x03));
+                interrupted
+            }
+            _ => false,
+        }
+    }
+}
+
+pub fn checksum(payload: &[u8]) -> u8 {
+    payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
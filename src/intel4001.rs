@@ -1,3 +1,4 @@
+#[cfg(feature = "std")]
 use std::{
     io, 
     io::Read,
@@ -6,11 +7,26 @@ use std::{
 
 use arbitrary_int::{u4};
 
+use super::bus::{Phase, Signals};
+use super::peripherals::Peripheral;
+
 // Intel 4001(ROM)
 
 pub struct Intel4001 {
     pub rom: [u8; 256],      // 256 bytes.
     pub io: u4,                  // 4 bits I/O port to route data in and out of the system.
+    pub inputs: u8,              // I/O lines configured as inputs by the metal mask, one bit per line.
+    bus: BusLatch,
+}
+
+/// What the chip remembers from the bus between phases.
+#[derive(Clone, Copy, Default)]
+struct BusLatch {
+    addr: u8,                // Sent at A1 and A2.
+    selected: bool,          // A3 carried the chip number with CM-ROM, the chip sends the instruction at M1 and M2.
+    io: bool,                // CM-ROM at M2, an I/O instruction.
+    opa: u8,
+    port: bool,              // The last SRC chose this chip's I/O port.
 }
 
 impl Intel4001 {
@@ -18,6 +34,56 @@ impl Intel4001 {
         Intel4001 {
             rom: [0x00; 256],
             io: u4::new(0x0),
+            inputs: 0x0,
+            bus: BusLatch::default(),
+        }
+    }
+
+    /// RESET clears the I/O lines and what the chip latched from the bus.
+    pub fn reset(&mut self) {
+        self.io = u4::new(0x0);
+        self.bus = BusLatch::default();
+    }
+
+    /// Drive D0-D3 as chip `number` during a phase of the bus: the instruction at M1 and M2, the I/O lines at X2 of an
+    /// RDR. Chips 0-15 listen to CM-ROM0 and chips 16-31 to CM-ROM1, the chip number is the low 4 bits.
+    pub fn bus_drive(&mut self, number: usize, signals: &mut Signals, peripheral: Option<&mut dyn Peripheral>) {
+        match signals.phase {
+            Phase::M1 if self.bus.selected => signals.data = self.rom[self.bus.addr as usize] >> 4,
+            Phase::M2 if self.bus.selected => signals.data = self.rom[self.bus.addr as usize] & 0x0F,
+            Phase::X2 if self.bus.io && self.bus.port && self.bus.opa == 0xA => {
+                let input = peripheral.and_then(|p| p.rom_port_read(number));
+                signals.data = input.map_or(self.io.value(), |value| value & 0x0F);
+            }
+            _ => {}
+        }
+    }
+
+    /// Latch what chip `number` needs from the lines at the end of a phase.
+    pub fn bus_sample(&mut self, number: usize, signals: &Signals, peripheral: Option<&mut dyn Peripheral>) {
+        let cm_rom = signals.cm_rom & (1 << (number / 16)) != 0;
+        let chip = (number % 16) as u8;
+
+        match signals.phase {
+            Phase::A1 => {
+                self.bus.addr = signals.data;
+                self.bus.io = false;
+            }
+            Phase::A2 => self.bus.addr |= signals.data << 4,
+            Phase::A3 => self.bus.selected = cm_rom && signals.data == chip,
+            Phase::M2 => {
+                self.bus.selected = false;
+                self.bus.io = cm_rom;
+                self.bus.opa = signals.data;
+            }
+            Phase::X2 if cm_rom && !self.bus.io => self.bus.port = signals.data == chip,    // SRC
+            Phase::X2 if self.bus.io && self.bus.port && self.bus.opa == 0x2 => {             // WRR
+                self.io = u4::new(signals.data);
+                if let Some(peripheral) = peripheral {
+                    peripheral.rom_port_write(number, signals.data);
+                }
+            }
+            _ => {}
         }
     }
 
@@ -29,10 +95,20 @@ impl Intel4001 {
         0x00
     }
 
+    #[cfg(feature = "std")]
     pub fn load_rom(&mut self, filename: &str) -> io::Result<()>{
         let mut file = File::open(filename)?;
-        file.read(&mut self.rom)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        self.load_bytes(&bytes);
 
         Ok(())
     }
+
+    /// Copy a ROM image into the chip, anything past 256 bytes is ignored.
+    pub fn load_bytes(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(self.rom.len());
+        self.rom[..len].copy_from_slice(&bytes[..len]);
+    }
 }
\ No newline at end of file
@@ -1,9 +1,24 @@
+use super::bus::{Phase, Signals};
+use super::peripherals::Peripheral;
+
 // Intel 4002(RAM)
 
 pub struct Intel4002 {
     pub ram: [u8; 64],       // 64 4-bits characters.
     pub status: [u8; 16],    // 16 status characters.
     pub output: u8,          // Output lines.
+    bus: BusLatch,
+}
+
+/// What the chip remembers from the bus between phases.
+#[derive(Clone, Copy, Default)]
+struct BusLatch {
+    src: bool,               // CM-RAM at X2 without an I/O instruction, X3 carries the character.
+    selected: bool,          // The last SRC on this chip's CM-RAM line chose it.
+    register: usize,
+    character: usize,
+    io: bool,                // CM-RAM at M2, an I/O instruction.
+    opa: u8,
 }
 
 impl Intel4002 {
@@ -12,6 +27,56 @@ impl Intel4002 {
             ram: [0x00; 64],
             status: [0x00; 16],
             output: 0x00,
+            bus: BusLatch::default(),
+        }
+    }
+
+    /// Drive D0-D3 at X2 of a read from the selected character: RDM, ADM, SBM or RD0-RD3.
+    pub fn bus_drive(&mut self, signals: &mut Signals) {
+        if signals.phase != Phase::X2 || !self.bus.io || !self.bus.selected {
+            return;
+        }
+        match self.bus.opa {
+            0x8 | 0x9 | 0xB => signals.data = self.ram[self.bus.register * 16 + self.bus.character],
+            0xC..=0xF => signals.data = self.status[self.bus.register * 4 + (self.bus.opa - 0xC) as usize],
+            _ => {}
+        }
+    }
+
+    /// Latch what chip `number` needs from the lines at the end of a phase. Chips 4 * n to 4 * n + 3 listen to
+    /// CM-RAMn and the chip number is the low 2 bits. Only chips on the CM-RAM lines asserted by SRC update their
+    /// selection, the others keep the one they had.
+    pub fn bus_sample(&mut self, number: usize, signals: &Signals, peripheral: Option<&mut dyn Peripheral>) {
+        let cm_ram = signals.cm_ram & (1 << (number / 4)) != 0;
+        let chip = (number % 4) as u8;
+
+        match signals.phase {
+            Phase::A1 => {
+                self.bus.src = false;
+                self.bus.io = false;
+            }
+            Phase::M2 => {
+                self.bus.io = cm_ram;
+                self.bus.opa = signals.data;
+            }
+            Phase::X2 if cm_ram && !self.bus.io => {                                      // SRC
+                self.bus.src = true;
+                self.bus.selected = signals.data >> 2 == chip;
+                self.bus.register = (signals.data & 0x03) as usize;
+            }
+            Phase::X2 if self.bus.io && self.bus.selected => match self.bus.opa {
+                0x0 => self.ram[self.bus.register * 16 + self.bus.character] = signals.data,
+                0x1 => {
+                    self.output = signals.data;
+                    if let Some(peripheral) = peripheral {
+                        peripheral.ram_port_write(number, signals.data);
+                    }
+                }
+                0x4..=0x7 => self.status[self.bus.register * 4 + (self.bus.opa - 0x4) as usize] = signals.data,
+                _ => {}
+            },
+            Phase::X3 if self.bus.src => self.bus.character = signals.data as usize,
+            _ => {}
         }
     }
-}
\ No newline at end of file
+}
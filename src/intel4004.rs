@@ -1,82 +1,784 @@
 use super::intel4001::Intel4001;
 use super::intel4002::Intel4002;
+#[cfg(feature = "std")]
+use super::profiler::Profiler;
+#[cfg(feature = "std")]
+use super::coverage::Coverage;
+#[cfg(feature = "std")]
+use super::strict::{Strict, StrictMode};
+#[cfg(feature = "std")]
+use super::sanitizer::Sanitizer;
+use super::opcodes::{Model, Op};
+#[cfg(feature = "std")]
+use super::bus::{Bus, Phase, Signals};
+#[cfg(feature = "std")]
+use super::vcd::Vcd;
+use super::peripherals::Peripheral;
+
+#[cfg(feature = "std")]
+use std::io;
 
 use arbitrary_int::{u4};
 
+pub const ROM_CHIPS: usize = 16;                                    // 16 x 256 bytes fill the 12-bit address space.
+pub const ROM_BANKS: usize = 2;                                     // The 4040 selects a second 4 KiB bank with DB1.
+pub const RAM_CHIPS: usize = 16;                                    // 4 banks selected by DCL with 4 chips each.
+pub const MAX_STACK_LEVELS: usize = 7;                              // 4040, the 4004 uses the first 3.
+
 // Stack
 
-struct Stack{                                                        // 3 x 12 bits array
-    addrs: [u16; 3],
+struct Stack{                                                        // 7 x 12 bits array
+    addrs: [u16; MAX_STACK_LEVELS],
+    levels: u8,
     sp: u8,                                                          // Stack Pointer
 }
 
 impl Stack {
 
-    pub fn new() -> Self {
+    pub fn new(levels: u8) -> Self {
         Stack{
-            addrs: [0x00; 3],
+            addrs: [0x00; MAX_STACK_LEVELS],
+            levels,
             sp: 0x00,
         }
     }
 
+    /// The levels work as a circular buffer, like the real chip a push past the last level overwrites the oldest
+    /// address.
     pub fn push(&mut self, addr: u16) {
         self.addrs[self.sp as usize] = addr;
-
-        if self.sp < 3 {
-            self.sp += 1;
-        }
+        self.sp = (self.sp + 1) % self.levels;
     }
 
     pub fn pop(&mut self) -> u16 {
+        self.sp = (self.sp + self.levels - 1) % self.levels;         // Down 1 level.
+
         let addr = self.addrs[self.sp as usize];
         self.addrs[self.sp as usize] = 0x00;                                  // Reset stack level.
 
-        if self.sp > 0 {
-            self.sp -= 1;                                            // Down 1 level if the sp is not on level 0.
+        addr
+    }
+
+}
+
+/// What executing an undefined opcode does. Every policy counts them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UndefinedPolicy {
+    #[default]
+    Nop,
+    Trap,                                                           // Stop on the opcode without executing it.
+    Silicon,                                                        // What the chip does, see `Intel4004::silicon`.
+}
+
+impl UndefinedPolicy {
+    /// Parse "nop", "trap" or "silicon".
+    pub fn parse(text: &str) -> Option<UndefinedPolicy> {
+        match text {
+            "nop" => Some(UndefinedPolicy::Nop),
+            "trap" => Some(UndefinedPolicy::Trap),
+            "silicon" => Some(UndefinedPolicy::Silicon),
+            _ => None,
         }
+    }
+}
 
-        addr
+/// Power-on contents of the index registers, RAM and port latches, see `Intel4004::fill`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fill {
+    #[default]
+    Zero,
+    Pattern(u8),                                                    // The same nibble everywhere.
+    Random(u64),                                                    // Pseudo-random nibbles from a seed.
+}
+
+impl Fill {
+    /// Parse "zero", "pattern:n" or "random[:seed]", numbers in decimal or 0x hex.
+    pub fn parse(text: &str) -> Option<Fill> {
+        let number = |text: &str| match text.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        };
+        match text.split_once(':') {
+            None if text == "zero" => Some(Fill::Zero),
+            None if text == "random" => Some(Fill::Random(0)),
+            Some(("pattern", value)) => number(value).filter(|v| *v < 16).map(|v| Fill::Pattern(v as u8)),
+            Some(("random", seed)) => number(seed).map(Fill::Random),
+            _ => None,
+        }
     }
+}
 
+/// Lend a peripheral for one call, `as_deref_mut` would keep it borrowed for the caller's whole lifetime.
+#[cfg(feature = "std")]
+fn lend<'a>(peripheral: &'a mut Option<&mut dyn Peripheral>) -> Option<&'a mut dyn Peripheral> {
+    peripheral.as_mut().map(|p| &mut **p as &mut dyn Peripheral)
 }
 
 // Intel 4004(CPU)
 
+/// A 4004, or a 4040 when built with `with_model`.
 pub struct Intel4004 {
+    model: Model,
     pc:    u16,
     carry: bool, 
     acc:   u4,
     index: [u4; 16],                                                 // Dynamic RAM cell array of 16 x 4 bits.
+    index_bank: [u4; 8],                                             // R0-R7 of the register bank not selected by SB0/SB1.
+    register_bank: usize,
     stack: Stack,     
-    signal: bool,
+    signal: bool,                                                    // TEST pin level, JCN TZ jumps while it is low.
     command_control: u4,
     ram_addrs: u8,
-    pub rom: Intel4001,     
-    pub ram: Intel4002,                                             // For now it will only work with one RAM chip           
+    src_save: u8,                                                    // SRC restored by BBS.
+    rom_bank: usize,                                                 // Selected by DB0/DB1.
+    interrupts_enabled: bool,
+    interrupt: bool,                                                 // INT pin level, 4040 only like STOP.
+    interrupt_ack: bool,                                             // INTA, set when an interrupt is taken until BBS.
+    stop: bool,
+    stopped: bool,                                                   // STOP ACK.
+    halted: bool,
+    undefined_policy: UndefinedPolicy,
+    undefined_count: u64,
+    instruction_count: u64,
+    trap: Option<u16>,                                               // Address of the undefined opcode that trapped.
+    from_bus: Option<u8>,                                            // Second word or I/O read delivered by `clock_bus`.
+    pub rom: [Intel4001; ROM_CHIPS * ROM_BANKS],                    // Chip n holds addresses n * 256 to n * 256 + 255 of bank n / 16.
+    pub ram: [Intel4002; RAM_CHIPS],                                // Chip 4 * bank + number selected by SRC.
+    rom_chips: usize,                                               // Populated chips, the rest read as 0.
+    ram_chips: usize,
+    #[cfg(feature = "std")]
+    peripheral: Option<Box<dyn Peripheral>>,
+    #[cfg(feature = "std")]
+    profiler: Option<Profiler>,
+    #[cfg(feature = "std")]
+    coverage: Option<Coverage>,
+    #[cfg(feature = "std")]
+    strict: Option<Strict>,
+    #[cfg(feature = "std")]
+    sanitizer: Option<Sanitizer>,
+    #[cfg(feature = "std")]
+    bus: Option<Bus>,
+    #[cfg(feature = "std")]
+    vcd: Option<Vcd>,
 }
 
 impl Intel4004 {
+    /// CPU with every ROM and RAM chip populated.
     pub fn new() -> Self {
+        Self::with_chips(ROM_CHIPS, RAM_CHIPS)
+    }
+
+    /// CPU with the first `rom_chips` ROM and `ram_chips` RAM chips populated, between 1 and 16 of each.
+    pub fn with_chips(rom_chips: usize, ram_chips: usize) -> Self {
+        Self::with_model(Model::I4004, rom_chips, ram_chips)
+    }
+
+    /// CPU of the given model. A 4040 takes up to 32 ROM chips, chips 16-31 form bank 1.
+    pub fn with_model(model: Model, rom_chips: usize, ram_chips: usize) -> Self {
+        let banks = match model {
+            Model::I4004 => 1,
+            Model::I4040 => ROM_BANKS,
+        };
+
         Intel4004 {
+            model,
             pc: 0x00,
             carry: false,
             acc: u4::new(0x0),
             index: [u4::new(0x0); 16],
-            stack: Stack::new(),
+            index_bank: [u4::new(0x0); 8],
+            register_bank: 0,
+            stack: Stack::new(model.stack_levels() as u8),
             signal: false, 
             command_control: u4::new(0x0),
             ram_addrs: 0x00,
-            rom: Intel4001::new(),
-            ram: Intel4002::new(),
+            src_save: 0x00,
+            rom_bank: 0,
+            interrupts_enabled: false,
+            interrupt: false,
+            interrupt_ack: false,
+            stop: false,
+            stopped: false,
+            halted: false,
+            undefined_policy: UndefinedPolicy::Nop,
+            undefined_count: 0,
+            instruction_count: 0,
+            trap: None,
+            from_bus: None,
+            rom: core::array::from_fn(|_| Intel4001::new()),
+            ram: core::array::from_fn(|_| Intel4002::new()),
+            rom_chips: rom_chips.clamp(1, ROM_CHIPS * banks),
+            ram_chips: ram_chips.clamp(1, RAM_CHIPS),
+            #[cfg(feature = "std")]
+            peripheral: None,
+            #[cfg(feature = "std")]
+            profiler: None,
+            #[cfg(feature = "std")]
+            coverage: None,
+            #[cfg(feature = "std")]
+            strict: None,
+            #[cfg(feature = "std")]
+            sanitizer: None,
+            #[cfg(feature = "std")]
+            bus: None,
+            #[cfg(feature = "std")]
+            vcd: None,
         }
     }
   
+    /// Run the instruction at the PC, port accesses go to the attached peripheral. With `enable_bus` the instruction
+    /// runs phase by phase on the bus, see `clock_bus`.
     pub fn clock(&mut self) {
-        self.decode_op(self.rom.fetch_u8(self.pc.into()));
+        #[cfg(feature = "std")]
+        if let Some(mut bus) = self.bus.take() {
+            self.clock_bus(&mut bus);
+            self.bus = Some(bus);
+            return;
+        }
+        self.with_attached(|cpu, peripheral| cpu.step(peripheral));
+    }
+
+    /// Run the instruction at the PC with `peripheral` on the ports instead of the attached one. Without std there is
+    /// nothing to attach, so this is how firmware connects its I/O.
+    pub fn clock_with(&mut self, peripheral: &mut dyn Peripheral) {
+        self.step(Some(peripheral));
+    }
+
+    fn step(&mut self, peripheral: Option<&mut dyn Peripheral>) {
+        if !self.ready() {
+            return;
+        }
+
+        let pc = self.pc;
+        let op_code = self.fetch_u8(pc);
+
+        #[cfg(feature = "std")]
+        self.check(pc, op_code);
+        self.execute(op_code, peripheral);
+        self.retire(pc, op_code);
+
+        #[cfg(feature = "std")]
+        if let Some(mut vcd) = self.vcd.take() {
+            vcd.instruction(self, pc, self.model.decode(op_code).size as u64);
+            self.vcd = Some(vcd);
+        }
+    }
+
+    /// Handle STOP and interrupts between instructions, false if no instruction runs this clock.
+    fn ready(&mut self) -> bool {
+        if self.model == Model::I4040 {
+            // STOP is checked between instructions, and releases HLT like on the real chip.
+            self.stopped = self.stop;
+            if self.stopped {
+                self.halted = false;
+                return false;
+            }
+            if self.interrupt && self.interrupts_enabled && !self.interrupt_ack {
+                self.take_interrupt();
+                return false;
+            }
+        }
+        if self.halted || self.trap.is_some() {
+            return false;
+        }
+
+        #[cfg(feature = "std")]
+        if self.strict.as_ref().is_some_and(|strict| strict.is_halted())
+            || self.sanitizer.as_ref().is_some_and(|sanitizer| sanitizer.is_halted()) {
+            return false;
+        }
+        true
+    }
+
+    /// Strict mode and sanitizer checks of the instruction about to run.
+    #[cfg(feature = "std")]
+    fn check(&mut self, pc: u16, op_code: u8) {
+        if let Some(mut strict) = self.strict.take() {
+            strict.check(self, pc, op_code);
+            self.strict = Some(strict);
+        }
+        if let Some(mut sanitizer) = self.sanitizer.take() {
+            sanitizer.check(self, pc, op_code);
+            self.sanitizer = Some(sanitizer);
+        }
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn retire(&mut self, pc: u16, op_code: u8) {
+        self.pc &= 0xFFF;                                           // 12-bit program counter.
+
+        if self.trap.is_some() {                                    // A trapped opcode did not execute.
+            return;
+        }
+        self.instruction_count += 1;
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(profiler) = self.profiler.as_mut() {
+                profiler.record(pc, op_code, self.pc);
+            }
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(pc, op_code, self.pc);
+            }
+        }
+    }
+
+    // --- Memory ---
+
+    /// Read program memory in the selected ROM bank, addresses in unpopulated chips read as 0.
+    pub fn fetch_u8(&self, addr: u16) -> u8 {
+        let chip = self.rom_bank * ROM_CHIPS + ((addr as usize >> 8) & 0x0F);
+
+        if chip < self.rom_chips {
+            return self.rom[chip].fetch_u8(addr as usize & 0xFF);
+        }
+        0x00
+    }
+
+    /// Second word of an instruction, or the byte FIN reads. `clock_bus` fetched it over the bus already.
+    fn fetch_operand(&self, addr: u16) -> u8 {
+        self.from_bus.unwrap_or_else(|| self.fetch_u8(addr))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn load_rom(&mut self, filename: &str) -> io::Result<()> {
+        let bytes = std::fs::read(filename)?;
+        self.load_bytes(&bytes);
+
+        Ok(())
+    }
+
+    /// Copy a ROM image into the populated chips starting at address 0, anything past them is ignored.
+    pub fn load_bytes(&mut self, bytes: &[u8]) {
+        for (chip, page) in self.rom[..self.rom_chips].iter_mut().zip(bytes.chunks(256)) {
+            chip.load_bytes(page);
+        }
+    }
+
+    /// Contents of the populated ROM chips as one image.
+    #[cfg(feature = "std")]
+    pub fn rom_image(&self) -> Vec<u8> {
+        self.rom[..self.rom_chips].iter().flat_map(|chip| chip.rom).collect()
+    }
+
+    /// RAM bank selected by the last DCL. Codes that enable several CM-RAM lines select the lowest one.
+    pub fn ram_bank(&self) -> usize {
+        match self.command_control.value() & 0x07 {
+            0 => 0,
+            2 | 6 => 2,
+            4 => 3,
+            _ => 1,
+        }
+    }
+
+    /// Number of the RAM chip selected by DCL and the last SRC, the chip number is in bits 7-6 of the SRC address.
+    pub fn selected_ram_chip(&self) -> usize {
+        self.ram_bank() * 4 + (self.ram_addrs >> 6) as usize
+    }
+
+    /// Selected RAM chip, None if it is not populated.
+    pub fn selected_ram(&self) -> Option<&Intel4002> {
+        self.ram[..self.ram_chips].get(self.selected_ram_chip())
+    }
+
+    fn selected_ram_mut(&mut self) -> Option<&mut Intel4002> {
+        let chip = self.selected_ram_chip();
+        self.ram[..self.ram_chips].get_mut(chip)
+    }
+
+    /// ROM chip whose I/O port was selected by the last SRC, in the selected ROM bank.
+    pub fn selected_rom_port(&self) -> usize {
+        self.rom_bank * ROM_CHIPS + (self.ram_addrs >> 4) as usize
+    }
+
+    fn selected_character(&self) -> usize {
+        (self.ram_addrs & 0x3F) as usize                             // Register in bits 5-4, character in bits 3-0.
+    }
+
+    fn selected_status(&self, character: usize) -> usize {
+        (((self.ram_addrs >> 4) & 0x03) * 4) as usize + character
+    }
+
+    // --- Peripherals ---
+
+    /// Connect a device to the I/O ports, replacing the previous one.
+    #[cfg(feature = "std")]
+    pub fn attach_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.peripheral = Some(peripheral);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn take_peripheral(&mut self) -> Option<Box<dyn Peripheral>> {
+        self.peripheral.take()
+    }
+
+    /// Call `f` with the attached peripheral moved out of the CPU, so instructions can borrow both.
+    #[cfg(feature = "std")]
+    fn with_attached(&mut self, f: impl FnOnce(&mut Self, Option<&mut dyn Peripheral>)) {
+        let mut peripheral = self.peripheral.take();
+        f(self, peripheral.as_deref_mut().map(|p| p as &mut dyn Peripheral));
+        self.peripheral = peripheral;
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn with_attached(&mut self, f: impl FnOnce(&mut Self, Option<&mut dyn Peripheral>)) {
+        f(self, None);
+    }
+
+    // --- Bus ---
+
+    /// Make `clock` run every instruction phase by phase on a bus, see `clock_bus`.
+    #[cfg(feature = "std")]
+    pub fn enable_bus(&mut self, bus: Bus) {
+        self.bus = Some(bus);
+    }
+
+    #[cfg(feature = "std")]
+    pub fn bus(&self) -> Option<&Bus> {
+        self.bus.as_ref()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn bus_mut(&mut self) -> Option<&mut Bus> {
+        self.bus.as_mut()
+    }
+
+    /// Go back to running instructions directly and return the bus.
+    #[cfg(feature = "std")]
+    pub fn take_bus(&mut self) -> Option<Bus> {
+        self.bus.take()
+    }
+
+    /// Run the instruction at the PC one clock phase at a time. Every phase drives D0-D3, SYNC, CM-ROM and CM-RAM0-3
+    /// on `bus`, and the populated 4001s and 4002s see nothing but those lines: they latch the address, SRC and I/O
+    /// commands and answer them like the real chips. Devices attached to the bus take part in every phase.
+    #[cfg(feature = "std")]
+    pub fn clock_bus(&mut self, bus: &mut Bus) {
+        self.with_attached(|cpu, mut peripheral| {
+            let pc = cpu.pc;
+            let acknowledged = cpu.interrupt_ack;
+            if !cpu.ready() {
+                if cpu.interrupt_ack && !acknowledged {
+                    cpu.bus_acknowledge(bus, pc, &mut peripheral);
+                }
+                return;
+            }
+
+            let op_code = cpu.bus_fetch(bus, pc, true, &mut peripheral);
+
+            cpu.check(pc, op_code);
+            cpu.bus_execute(op_code, bus, &mut peripheral);
+            cpu.retire(pc, op_code);
+        });
+    }
+
+    /// CM-RAM lines selected by DCL, bit n for CM-RAMn.
+    pub fn cm_ram_lines(&self) -> u8 {
+        match self.command_control.value() & 0x07 {
+            0 => 0x1,
+            lines => lines << 1,
+        }
+    }
+
+    /// One clock phase: the CPU drives its lines, then the memory chips and the bus devices drive theirs and everyone
+    /// samples the result. Returns D0-D3 as the CPU sees them.
+    #[cfg(feature = "std")]
+    fn bus_phase(&mut self, bus: &mut Bus, phase: Phase, data: Option<u8>, cm: bool,
+        peripheral: &mut Option<&mut dyn Peripheral>) -> u8 {
+        bus.signals = Signals {
+            phase,
+            data: data.unwrap_or(0) & 0x0F,
+            sync: phase == Phase::X3,
+            cm_rom: if cm { 1 << self.rom_bank } else { 0 },
+            cm_ram: if cm { self.cm_ram_lines() } else { 0 },
+            time: bus.signals.time,
+        };
+
+        for (number, chip) in self.rom[..self.rom_chips].iter_mut().enumerate() {
+            chip.bus_drive(number, &mut bus.signals, lend(peripheral));
+        }
+        for chip in self.ram[..self.ram_chips].iter_mut() {
+            chip.bus_drive(&mut bus.signals);
+        }
+        bus.drive_devices();
+
+        for (number, chip) in self.rom[..self.rom_chips].iter_mut().enumerate() {
+            chip.bus_sample(number, &bus.signals, lend(peripheral));
+        }
+        for (number, chip) in self.ram[..self.ram_chips].iter_mut().enumerate() {
+            chip.bus_sample(number, &bus.signals, lend(peripheral));
+        }
+        bus.sample_devices();
+
+        if let Some(mut vcd) = self.vcd.take() {
+            vcd.phase(self, &bus.signals);
+            self.vcd = Some(vcd);
+        }
+        bus.signals.time += 1;
+        bus.signals.data
+    }
+
+    /// A1-A3 send `addr` with CM-ROM at A3, M1-M2 read the byte the ROM sends. `io` asserts the CM lines at M2 when
+    /// the byte is an I/O instruction, which the chips latch as the command for X2.
+    #[cfg(feature = "std")]
+    fn bus_fetch(&mut self, bus: &mut Bus, addr: u16, io: bool, peripheral: &mut Option<&mut dyn Peripheral>) -> u8 {
+        self.bus_phase(bus, Phase::A1, Some(addr as u8 & 0x0F), false, peripheral);
+        self.bus_phase(bus, Phase::A2, Some((addr >> 4) as u8 & 0x0F), false, peripheral);
+        self.bus_phase(bus, Phase::A3, Some((addr >> 8) as u8 & 0x0F), true, peripheral);
+
+        let opr = self.bus_phase(bus, Phase::M1, None, false, peripheral);
+        let opa = self.bus_phase(bus, Phase::M2, None, io && opr == 0xE, peripheral);
+        (opr << 4) | opa
+    }
+
+    /// The cycle that takes an interrupt: the instruction at `pc` is fetched but not executed, without the CM lines
+    /// at M2 so no chip latches an I/O command.
+    #[cfg(feature = "std")]
+    fn bus_acknowledge(&mut self, bus: &mut Bus, pc: u16, peripheral: &mut Option<&mut dyn Peripheral>) {
+        self.bus_fetch(bus, pc, false, peripheral);
+        for phase in [Phase::X1, Phase::X2, Phase::X3] {
+            self.bus_phase(bus, phase, None, false, peripheral);
+        }
+    }
+
+    /// X1-X3 of the instruction, and the second cycle of two cycle instructions.
+    #[cfg(feature = "std")]
+    fn bus_execute(&mut self, op_code: u8, bus: &mut Bus, peripheral: &mut Option<&mut dyn Peripheral>) {
+        let instruction = self.model.decode(op_code);
+
+        if matches!(instruction.op, Op::Src | Op::Bbs) {
+            // BBS sends the SRC register it restored like SRC does, so the chips point where they did before.
+            self.execute(op_code, None);
+            self.bus_phase(bus, Phase::X1, None, false, peripheral);
+            self.bus_phase(bus, Phase::X2, Some(self.ram_addrs >> 4), true, peripheral);
+            self.bus_phase(bus, Phase::X3, Some(self.ram_addrs & 0x0F), false, peripheral);
+        } else if op_code >> 4 == 0xE {
+            // The chips latched the command at M2, the data moves at X2.
+            let write = matches!(instruction.op, Op::Wrm | Op::Wmp | Op::Wrr | Op::Wpm | Op::Wr0 | Op::Wr1 | Op::Wr2
+                | Op::Wr3);
+            self.bus_phase(bus, Phase::X1, None, false, peripheral);
+            let data = self.bus_phase(bus, Phase::X2, write.then_some(self.acc.value()), false, peripheral);
+            self.bus_phase(bus, Phase::X3, None, false, peripheral);
+
+            if write {
+                self.pc += 1;
+            } else {
+                self.from_bus = Some(data);
+                self.execute(op_code, None);
+                self.from_bus = None;
+            }
+        } else {
+            for phase in [Phase::X1, Phase::X2, Phase::X3] {
+                self.bus_phase(bus, phase, None, false, peripheral);
+            }
+            if instruction.size == 2 || instruction.op == Op::Fin {
+                let addr = match instruction.op {
+                    Op::Fin => ((self.pc + 1) & 0xF00) | self.get_reg_pair(0) as u16,
+                    _ => (self.pc + 1) & 0xFFF,
+                };
+                self.from_bus = Some(self.bus_fetch(bus, addr, false, peripheral));
+                for phase in [Phase::X1, Phase::X2, Phase::X3] {
+                    self.bus_phase(bus, phase, None, false, peripheral);
+                }
+            }
+            self.execute(op_code, None);
+            self.from_bus = None;
+        }
+    }
+
+    // --- Waveforms ---
+
+    /// Record the signals of every following clock to a VCD file, see `Vcd`. Enable the bus first to get the bus
+    /// lines too.
+    #[cfg(feature = "std")]
+    pub fn enable_vcd(&mut self, vcd: Vcd) {
+        self.vcd = Some(vcd);
+    }
+
+    /// Stop recording, call `Vcd::finish` on the result to complete the file.
+    #[cfg(feature = "std")]
+    pub fn take_vcd(&mut self) -> Option<Vcd> {
+        self.vcd.take()
+    }
+
+    // --- Profiling and coverage ---
+
+    /// Start counting executions and cycles of every instruction run by `clock`.
+    #[cfg(feature = "std")]
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    #[cfg(feature = "std")]
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Stop profiling and return the results.
+    #[cfg(feature = "std")]
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Start recording executed addresses and branch outcomes.
+    #[cfg(feature = "std")]
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    #[cfg(feature = "std")]
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stop recording coverage and return the results.
+    #[cfg(feature = "std")]
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // --- Strict mode ---
+
+    /// Check every instruction run by `clock` for misuse the real hardware would not tolerate. In halt mode the CPU
+    /// stops after the offending instruction until `Strict::resume`.
+    #[cfg(feature = "std")]
+    pub fn enable_strict(&mut self, mode: StrictMode) {
+        self.strict = Some(Strict::new(mode));
+    }
+
+    #[cfg(feature = "std")]
+    pub fn strict(&self) -> Option<&Strict> {
+        self.strict.as_ref()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn strict_mut(&mut self) -> Option<&mut Strict> {
+        self.strict.as_mut()
+    }
+
+    /// Stop checking and return the diagnostics.
+    #[cfg(feature = "std")]
+    pub fn take_strict(&mut self) -> Option<Strict> {
+        self.strict.take()
+    }
+
+    // --- Reset ---
+
+    /// Assert RESET. Like the real chips this clears the PC, the stack, the accumulator, carry, the index registers,
+    /// SRC and DCL, and the 4002 RAM, status characters and output lines and the 4001 I/O lines. ROM contents, the
+    /// TEST, INT and STOP pins and the instrumentation are kept, the attached peripheral is told.
+    pub fn reset(&mut self) {
+        self.pc = 0x000;
+        self.carry = false;
+        self.acc = u4::new(0x0);
+        self.index = [u4::new(0x0); 16];
+        self.index_bank = [u4::new(0x0); 8];
+        self.register_bank = 0;
+        self.stack = Stack::new(self.stack.levels);
+        self.command_control = u4::new(0x0);
+        self.ram_addrs = 0x00;
+        self.src_save = 0x00;
+        self.rom_bank = 0;
+        self.interrupts_enabled = false;
+        self.interrupt_ack = false;
+        self.halted = false;
+        self.trap = None;
+
+        for chip in self.ram.iter_mut() {
+            *chip = Intel4002::new();
+        }
+        self.rom.iter_mut().for_each(|chip| chip.reset());
+
+        #[cfg(feature = "std")]
+        {
+            if let Some(peripheral) = self.peripheral.as_mut() {
+                peripheral.reset();
+            }
+            if let Some(strict) = self.strict.as_mut() {
+                strict.reset();
+            }
+            if let Some(sanitizer) = self.sanitizer.as_mut() {
+                sanitizer.define_all();                             // RESET wrote zeros everywhere.
+            }
+        }
+    }
+
+    /// Power the machine up: RESET, then `fill` the registers, RAM and ports that real chips leave holding whatever
+    /// their cells settled to. `Fill::Zero` is the same as `reset`, except that the sanitizer counts everything as
+    /// undefined again.
+    pub fn power_on(&mut self, fill: Fill) {
+        self.reset();
+        self.fill(fill);
+
+        #[cfg(feature = "std")]
+        if let Some(sanitizer) = self.sanitizer.as_mut() {
+            sanitizer.undefine_all();
+        }
+    }
+
+    /// `reset` and tell `peripheral` as well, for firmware that connects its I/O with `clock_with`.
+    pub fn reset_with(&mut self, peripheral: &mut dyn Peripheral) {
+        self.reset();
+        peripheral.reset();
+    }
+
+    // --- Uninitialized reads ---
+
+    /// Track which index registers, RAM characters and port latches were written, and report instructions that
+    /// consume one that was not. Enable before running, storage written earlier counts as undefined.
+    #[cfg(feature = "std")]
+    pub fn enable_sanitizer(&mut self, mode: StrictMode) {
+        self.sanitizer = Some(Sanitizer::new(mode));
+    }
+
+    #[cfg(feature = "std")]
+    pub fn sanitizer(&self) -> Option<&Sanitizer> {
+        self.sanitizer.as_ref()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn sanitizer_mut(&mut self) -> Option<&mut Sanitizer> {
+        self.sanitizer.as_mut()
+    }
+
+    /// Stop tracking and return the diagnostics.
+    #[cfg(feature = "std")]
+    pub fn take_sanitizer(&mut self) -> Option<Sanitizer> {
+        self.sanitizer.take()
+    }
+
+    /// Overwrite the index registers of both banks, the RAM characters and the port latches, as they are at power-on.
+    /// The real chips come up with whatever their cells hold, zeros hide firmware that forgets to initialize them.
+    pub fn fill(&mut self, fill: Fill) {
+        let mut state = match fill {
+            Fill::Random(seed) => seed ^ 0x9E37_79B9_7F4A_7C15,      // Xorshift sticks at 0, mix the seed.
+            _ => 0,
+        };
+        let mut next = || match fill {
+            Fill::Zero => u4::new(0x0),
+            Fill::Pattern(value) => u4::new(value & 0x0F),
+            Fill::Random(_) => {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                u4::new((state >> 60) as u8)
+            }
+        };
+
+        self.index.iter_mut().chain(self.index_bank.iter_mut()).for_each(|r| *r = next());
+        for chip in self.ram.iter_mut() {
+            chip.ram.iter_mut().chain(chip.status.iter_mut()).for_each(|c| *c = next().value());
+            chip.output = next().value();
+        }
+        self.rom.iter_mut().for_each(|chip| chip.io = next());
     }
 
     // --- Getters and setters ---
 
+    pub fn get_model(&self) -> Model {
+        self.model
+    }
+
     pub fn get_pc(&self) -> u16 {
         self.pc
     }
@@ -97,8 +799,9 @@ impl Intel4004 {
         (self.index[rp].value() * 16) + self.index[rp + 1].value()
     }
 
-    pub fn get_stack(&self) -> &[u16; 3] {
-        &self.stack.addrs
+    /// Every stack level, 3 on the 4004 and 7 on the 4040.
+    pub fn get_stack(&self) -> &[u16] {
+        &self.stack.addrs[..self.stack.levels as usize]
     }
 
     pub fn get_cc(&self) -> u8 {
@@ -109,6 +812,71 @@ impl Intel4004 {
         self.ram_addrs
     }
 
+    pub fn get_test(&self) -> bool {
+        self.signal
+    }
+
+    pub fn get_rom_chips(&self) -> usize {
+        self.rom_chips
+    }
+
+    pub fn get_ram_chips(&self) -> usize {
+        self.ram_chips
+    }
+
+    /// Index register bank selected by SB0/SB1, always 0 on the 4004.
+    pub fn get_register_bank(&self) -> usize {
+        self.register_bank
+    }
+
+    /// R0-R7 of the bank that is not selected.
+    pub fn get_other_bank(&self) -> &[u4; 8] {
+        &self.index_bank
+    }
+
+    /// ROM bank selected by DB0/DB1, always 0 on the 4004.
+    pub fn get_rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    pub fn get_interrupts_enabled(&self) -> bool {
+        self.interrupts_enabled
+    }
+
+    /// Stopped by HLT.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn get_undefined_policy(&self) -> UndefinedPolicy {
+        self.undefined_policy
+    }
+
+    /// Undefined opcodes executed, or trapped on, since the CPU was built.
+    pub fn get_undefined_count(&self) -> u64 {
+        self.undefined_count
+    }
+
+    /// Instructions executed since the CPU was built. Clocks spent halted, stopped or trapped do not count.
+    pub fn get_instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
+    /// Address of the undefined opcode the CPU trapped on. `clock` does nothing until the trap is cleared.
+    pub fn get_trap(&self) -> Option<u16> {
+        self.trap
+    }
+
+    /// INTA output, high from an accepted interrupt until the BBS that ends it.
+    pub fn get_interrupt_ack(&self) -> bool {
+        self.interrupt_ack
+    }
+
+    /// STOP ACK output, high while the CPU is held by the STOP pin.
+    pub fn get_stop_ack(&self) -> bool {
+        self.stopped
+    }
+
     pub fn set_pc(&mut self, pc: u16) {
         self.pc = pc;
     }
@@ -133,8 +901,10 @@ impl Intel4004 {
         self.index[rp + 1] = val2;
     }
 
-    pub fn set_stack(&mut self, stack: [u16; 3]) {
-        self.stack.addrs = stack;
+    /// Set the stack levels from the first, extra values are ignored.
+    pub fn set_stack(&mut self, stack: &[u16]) {
+        let levels = stack.len().min(self.stack.levels as usize);
+        self.stack.addrs[..levels].copy_from_slice(&stack[..levels]);
     }
 
     pub fn set_cc(&mut self, cc: u8) {
@@ -145,85 +915,155 @@ impl Intel4004 {
         self.ram_addrs = ram_addrs;
     }
 
+    pub fn set_test(&mut self, test: bool) {
+        self.signal = test;
+    }
+
+    /// Swap R0-R7 with the other bank if `bank` is not the selected one.
+    pub fn set_register_bank(&mut self, bank: usize) {
+        if bank != self.register_bank {
+            for (active, other) in self.index[..8].iter_mut().zip(self.index_bank.iter_mut()) {
+                core::mem::swap(active, other);
+            }
+            self.register_bank = bank;
+        }
+    }
+
+    pub fn set_rom_bank(&mut self, bank: usize) {
+        self.rom_bank = bank % ROM_BANKS;
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    pub fn set_undefined_policy(&mut self, policy: UndefinedPolicy) {
+        self.undefined_policy = policy;
+    }
+
+    /// Resume after a trap. The PC still points at the undefined opcode, move it first or it traps again.
+    pub fn clear_trap(&mut self) {
+        self.trap = None;
+    }
+
+    /// Drive the INT pin. While it is high and EIN enabled interrupts, the next `clock` takes the interrupt instead of
+    /// executing an instruction. The pin is level triggered and ignored on the 4004.
+    pub fn set_interrupt(&mut self, interrupt: bool) {
+        self.interrupt = interrupt;
+    }
+
+    /// Drive the STOP pin. The CPU stops before the next instruction and stays stopped until the pin is released,
+    /// ignored on the 4004.
+    pub fn set_stop(&mut self, stop: bool) {
+        self.stop = stop;
+    }
+
     // --- Instructions ---
 
-    /// 1-word instructions take 1 instruction cycle while 2-word intructions take 2.
+    /// 1-word instructions take 1 instruction cycle while 2-word intructions take 2. The opcode is looked up in the
+    /// precomputed decode table of the model, undefined opcodes run as NOP.
     pub fn decode_op(&mut self, op_code: u8) {
-        match op_code & 0xF0{
+        self.with_attached(|cpu, peripheral| cpu.execute(op_code, peripheral));
+    }
+
+    fn execute(&mut self, op_code: u8, peripheral: Option<&mut dyn Peripheral>) {
+        let instruction = self.model.decode(op_code);
+        let opa = instruction.opa;
+
+        match instruction.op {
             // Machine instructions
-            0x00 => self.nop()       ,
-            0x10 => self.jcn(op_code & 0x0F),  // 2-word instruction
-            0x20 => {                          // Check last 4 bit to see which instruction to call.
-                if (op_code & 0x0F) % 2 == 0 {
-                    self.fim(op_code & 0x0F);
-                } else {
-                    self.src(op_code & 0x0F);
-                }
-            }, 
-            0x30 => {                          // Check last 4 bit to see which instruction to call. 
-                if (op_code & 0x0F) % 2 == 0 {
-                    self.fin(op_code & 0x0F);
-                } else {
-                    self.jin(op_code & 0x0F);
-                }
-            },
-            0x40 => self.jun(op_code & 0x0F), // 2-word instruction
-            0x50 => self.jms(op_code & 0x0F), // 2-word instruction
-            0x60 => self.inc(op_code & 0x0F),
-            0x70 => self.isz(op_code & 0x0F), // 2-word instruction
-            0x80 => self.add(op_code & 0x0F),
-            0x90 => self.sub(op_code & 0x0F),
-            0xA0 => self.ld(op_code & 0x0F),
-            0xB0 => self.xch(op_code & 0x0F),
-            0xC0 => self.bbl(op_code & 0x0F),
-            0xD0 => self.ldm(op_code & 0x0F),
+            Op::Nop => self.nop(),
+            Op::Jcn => self.jcn(opa),         // 2-word instruction
+            Op::Fim => self.fim(opa),         // 2-word instruction
+            Op::Src => self.src(opa),
+            Op::Fin => self.fin(opa),
+            Op::Jin => self.jin(opa),
+            Op::Jun => self.jun(opa),         // 2-word instruction
+            Op::Jms => self.jms(opa),         // 2-word instruction
+            Op::Inc => self.inc(opa),
+            Op::Isz => self.isz(opa),         // 2-word instruction
+            Op::Add => self.add(opa),
+            Op::Sub => self.sub(opa),
+            Op::Ld => self.ld(opa),
+            Op::Xch => self.xch(opa),
+            Op::Bbl => self.bbl(opa),
+            Op::Ldm => self.ldm(opa),
 
             // Input/Output and RAM instructions
-            0xE0 => match op_code & 0x0F {
-                0x00 => self.wrm(),
-                0x01 => self.wmp(),
-                0x02 => self.wrr(),
-                0x03 => self.wpm(),
-                0x04 => self.wr0(),
-                0x05 => self.wr1(),
-                0x06 => self.wr2(),
-                0x07 => self.wr3(),
-                0x08 => self.sbm(),
-                0x09 => self.rdm(),
-                0x0A => self.rdr(),
-                0x0B => self.adm(),
-                0x0C => self.rd0(),
-                0x0D => self.rd1(),
-                0x0E => self.rd2(),
-                0x0F => self.rd3(),
-                _ => self.nop()
-            },
+            Op::Wrm => self.wrm(),
+            Op::Wmp => self.wmp(peripheral),
+            Op::Wrr => self.wrr(peripheral),
+            Op::Wpm => self.wpm(),
+            Op::Wr0 => self.wr0(),
+            Op::Wr1 => self.wr1(),
+            Op::Wr2 => self.wr2(),
+            Op::Wr3 => self.wr3(),
+            Op::Sbm => self.sbm(),
+            Op::Rdm => self.rdm(),
+            Op::Rdr => self.rdr(peripheral),
+            Op::Adm => self.adm(),
+            Op::Rd0 => self.rd0(),
+            Op::Rd1 => self.rd1(),
+            Op::Rd2 => self.rd2(),
+            Op::Rd3 => self.rd3(),
 
             // Accumulator group instructions
-            0xF0 => match op_code & 0x0F {
-                0x00 => self.clb(),
-                0x01 => self.clc(),
-                0x02 => self.iac(),
-                0x03 => self.cmc(),
-                0x04 => self.cma(),
-                0x05 => self.ral(),
-                0x06 => self.rar(),
-                0x07 => self.tcc(),
-                0x08 => self.dac(),
-                0x09 => self.tcs(),
-                0x0A => self.stc(),
-                0x0B => self.daa(),
-                0x0C => self.kbp(),
-                0x0D => self.dcl(),
-                _ => self.nop()
-            },
-            _ => self.nop()
+            Op::Clb => self.clb(),
+            Op::Clc => self.clc(),
+            Op::Iac => self.iac(),
+            Op::Cmc => self.cmc(),
+            Op::Cma => self.cma(),
+            Op::Ral => self.ral(),
+            Op::Rar => self.rar(),
+            Op::Tcc => self.tcc(),
+            Op::Dac => self.dac(),
+            Op::Tcs => self.tcs(),
+            Op::Stc => self.stc(),
+            Op::Daa => self.daa(),
+            Op::Kbp => self.kbp(),
+            Op::Dcl => self.dcl(),
+
+            // 4040 instructions
+            Op::Hlt => self.hlt(),
+            Op::Bbs => self.bbs(),
+            Op::Lcr => self.lcr(),
+            Op::Or4 => self.or(4),
+            Op::Or5 => self.or(5),
+            Op::An6 => self.an(6),
+            Op::An7 => self.an(7),
+            Op::Db0 => self.db(0),
+            Op::Db1 => self.db(1),
+            Op::Sb0 => self.sb(0),
+            Op::Sb1 => self.sb(1),
+            Op::Ein => self.ein(),
+            Op::Din => self.din(),
+            Op::Rpm => self.rpm(),
+
+            Op::Undefined => self.undefined(op_code),
         }
     }
 
     // A1, A2, A3 cycles are used to request data from the ROM, then M1 and M2 cycles are used to send the data to the CPU. 
     // Finally, the X1, X2 and X3 cycles are used to interpret and execute the instruction.
 
+    // --- Undefined opcodes ---
+
+    fn undefined(&mut self, op_code: u8) {
+        self.undefined_count += 1;
+
+        match self.undefined_policy {
+            UndefinedPolicy::Nop => self.nop(),
+            UndefinedPolicy::Trap => self.trap = Some(self.pc),
+            UndefinedPolicy::Silicon => self.silicon(op_code),
+        }
+    }
+
+    /// 0x01-0x0F are in the NOP group, which the 4004 decodes from OPR alone, so they run as NOP. 0xFE and 0xFF have
+    /// no accumulator group function selected and are reported to only advance the PC as well.
+    fn silicon(&mut self, _op_code: u8) {
+        self.nop();
+    }
+
     // --- Machine instructions ---
 
     /// No operation.
@@ -242,8 +1082,9 @@ impl Intel4004 {
 
         self.pc += 1;
 
-        if c1 != 1 && ((self.acc.value() == 0 && c2 == 1) || (self.carry && c3 == 1) || (self.signal && c4 == 1)) {
-            self.pc = self.rom.fetch_u8(self.pc.into()) as u16;
+        let test = (self.acc.value() == 0 && c2 == 1) || (self.carry && c3 == 1) || (!self.signal && c4 == 1);
+        if test != (c1 == 1) {                                      // C1 inverts the condition.
+            self.pc = ((self.pc + 1) & 0xF00) | self.fetch_operand(self.pc) as u16;   // Jump inside the page of the next instruction.
         } else {
             self.pc += 1;
         }
@@ -255,7 +1096,7 @@ impl Intel4004 {
         self.pc += 1;
 
         let rp = ((opa >> 1) * 2) as usize;
-        let value = self.rom.fetch_u8(self.pc.into());
+        let value = self.fetch_operand(self.pc);
         self.set_reg_pair(rp, value);
 
         self.pc += 1;
@@ -273,29 +1114,29 @@ impl Intel4004 {
         self.pc += 1;
 
         let rp = (opa >> 1) as usize;
-        let val = self.rom.fetch_u8(self.get_reg_pair(0) as usize);
+        let val = self.fetch_operand((self.pc & 0xF00) | self.get_reg_pair(0) as u16);    // Same page as the next instruction.
         self.set_reg_pair(rp, val);
     }
 
     /// Jump indirect. Send contents of register pair RRR out as an address at A1 and A2 time (ROM fetch cycles).
     fn jin(&mut self, opa: u8) {
         let rp = (opa >> 1) as usize;
-        self.pc = self.get_reg_pair(rp) as u16;
+        self.pc = ((self.pc + 1) & 0xF00) | self.get_reg_pair(rp) as u16;
     }
 
     /// Jump unconditional. To specified address.
     fn jun(&mut self, opa: u8) {
         self.pc += 1;
 
-        self.pc = ((opa & 0x0F) as u16 * 256) + (self.rom.fetch_u8(self.pc.into()) as u16);          // Join the last 4 bits of OPA with the next 8 bits.
+        self.pc = ((opa & 0x0F) as u16 * 256) + (self.fetch_operand(self.pc) as u16);     // Join the last 4 bits of OPA with the next 8 bits.
     }
 
     /// Jump to subroutine of specified ROM address, save on address(Up 1 level in stack).
     fn jms(&mut self, opa: u8) {
         self.pc += 1;
         
-        self.stack.push(self.pc);
-        self.pc = ((opa & 0x0F) as u16 * 256) + (self.rom.fetch_u8(self.pc.into()) as u16);          // Join the last 4 bits of OPA with the next 8 bits.
+        self.stack.push(self.pc + 1);                                 // Return to the instruction after the JMS.
+        self.pc = ((opa & 0x0F) as u16 * 256) + (self.fetch_operand(self.pc) as u16);     // Join the last 4 bits of OPA with the next 8 bits.
     }
 
     /// Increment contect of specified register.
@@ -310,12 +1151,10 @@ impl Intel4004 {
     fn isz(&mut self, opa: u8) {
         self.pc += 1;
 
-        let rom_addr = self.rom.fetch_u8(self.pc.into()) as u16;
+        let rom_addr = ((self.pc + 1) & 0xF00) | self.fetch_operand(self.pc) as u16;   // Inside the page of the next instruction.
         let reg_addr =(opa & 0x0F) as usize;
 
-        if self.index[reg_addr].value() != 0x0F {
-            self.index[reg_addr] += u4::new(1);
-        }
+        self.index[reg_addr] = u4::new((self.index[reg_addr].value() + 1) & 0x0F);
         if  self.index[reg_addr].value() != 0 {
             self.pc = rom_addr;
         } else {
@@ -392,25 +1231,65 @@ impl Intel4004 {
 
     // --- Input/Output and RAM instructions ---
 
+    fn read_character(&self) -> u8 {
+        if let Some(value) = self.from_bus {
+            return value;
+        }
+        self.selected_ram().map_or(0, |chip| chip.ram[self.selected_character()])
+    }
+
+    fn write_character(&mut self, value: u8) {
+        let character = self.selected_character();
+        if let Some(chip) = self.selected_ram_mut() {
+            chip.ram[character] = value;
+        }
+    }
+
+    /// Each register has 16 main memory characters and 4 status characters, status character `n` of register r is
+    /// at r * 4 + n.
+    fn read_status(&self, n: usize) -> u8 {
+        if let Some(value) = self.from_bus {
+            return value;
+        }
+        self.selected_ram().map_or(0, |chip| chip.status[self.selected_status(n)])
+    }
+
+    fn write_status(&mut self, n: usize, value: u8) {
+        let status = self.selected_status(n);
+        if let Some(chip) = self.selected_ram_mut() {
+            chip.status[status] = value;
+        }
+    }
+
     /// Write contents of the accumulator into the previously selected RAM main memory character.
     fn wrm(&mut self) {
         self.pc += 1;
 
-        self.ram.ram[self.ram_addrs as usize] = self.acc.value(); // TODO: Find better way to access RAM.
+        self.write_character(self.acc.value());
     }
 
     /// Write contents of the accumulator into the previously selected RAM output port(output lines).
-    fn wmp(&mut self) {
+    fn wmp(&mut self, peripheral: Option<&mut dyn Peripheral>) {
         self.pc += 1;
 
-        self.ram.output = self.acc.value();
+        let (chip, value) = (self.selected_ram_chip(), self.acc.value());
+        if let Some(ram) = self.selected_ram_mut() {
+            ram.output = value;
+        }
+        if let Some(peripheral) = peripheral {
+            peripheral.ram_port_write(chip, value);
+        }
     }
 
     /// Write contents of the accumulator into the previously selected ROM output port(I/O lines).
-    fn wrr(&mut self) {
+    fn wrr(&mut self, peripheral: Option<&mut dyn Peripheral>) {
         self.pc += 1;
 
-        self.rom.io = self.acc;
+        let chip = self.selected_rom_port();
+        self.rom[chip].io = self.acc;
+        if let Some(peripheral) = peripheral {
+            peripheral.rom_port_write(chip, self.acc.value());
+        }
     }
 
     /// Write the contents of the accumulator into the previously selected half byte of read/write program memory (for use with the 4008/4009 only).
@@ -422,32 +1301,28 @@ impl Intel4004 {
     fn wr0(&mut self) {
         self.pc += 1;
 
-       let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03;                // Each register has 16 main memory characters and 4 status characters
-       self.ram.status[(ram_register * 4) as usize] = self.acc.value();         // 0 - 4 - 8 - C <- possible status index
+        self.write_status(0, self.acc.value());                      // 0 - 4 - 8 - C <- possible status index
     }
 
     /// Write the contents of the accumulator into the previously selected RAM status character 1.
     fn wr1(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        self.ram.status[((ram_register * 4) + 1) as usize] = self.acc.value();  // 1 - 5 - 9 - D
+        self.write_status(1, self.acc.value());                      // 1 - 5 - 9 - D
     }
 
     /// Write the contents of the accumulator into the previously selected RAM status character 2.
     fn wr2(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        self.ram.status[((ram_register * 4) + 2) as usize] = self.acc.value();  // 2 - 6 - A - E
+        self.write_status(2, self.acc.value());                      // 2 - 6 - A - E
     }
 
     /// Write the contents of the accumulator into the previously selected RAM status character 3.
     fn wr3(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        self.ram.status[((ram_register * 4) + 3) as usize] = self.acc.value();  // 3 - 7 - B - F
+        self.write_status(3, self.acc.value());                      // 3 - 7 - B - F
     }
 
     /// Subtract the previous selected RAM main memory characted from accumulator with borrow.
@@ -455,11 +1330,11 @@ impl Intel4004 {
         self.pc += 1;
 
         let mut val = self.acc.value();
-        val -= self.ram.ram[self.ram_addrs as usize] + self.carry as u8;
+        val += (!self.read_character() & 0x0F) + (!self.carry) as u8;
         self.carry = false;
 
         if val & 0xF0 != 0 {             
-            val &= 0xF;                 
+            val &= 0x0F;                 
             self.carry = true;
         }
         self.acc = u4::new(val);
@@ -469,15 +1344,24 @@ impl Intel4004 {
     fn rdm(&mut self) {
         self.pc += 1;
 
-        let val = self.ram.ram[self.ram_addrs as usize];
+        let val = self.read_character();
         self.acc = u4::new(val);
     }
 
-    /// Read the contents of the previous selected ROM input port into the accumulator(I/O lines).
-    fn rdr(&mut self) {
+    /// Read the contents of the previous selected ROM input port into the accumulator(I/O lines). A peripheral
+    /// driving the port takes precedence over the last value written.
+    fn rdr(&mut self, peripheral: Option<&mut dyn Peripheral>) {
         self.pc += 1;
 
-        self.acc = self.rom.io;
+        let chip = self.selected_rom_port();
+        let input = self.from_bus.or_else(|| peripheral.and_then(|p| p.rom_port_read(chip)));
+
+        #[cfg(feature = "std")]
+        if let Some(mut sanitizer) = self.sanitizer.take_if(|_| input.is_none()) {
+            sanitizer.check_port_read(self, self.pc - 1);
+            self.sanitizer = Some(sanitizer);
+        }
+        self.acc = input.map_or(self.rom[chip].io, |value| u4::new(value & 0x0F));
     }
 
     /// Add the previous selected RAM main memory character to accumulator with carry.
@@ -486,7 +1370,7 @@ impl Intel4004 {
 
         let mut val = self.acc.value();
 
-        val += self.ram.ram[self.ram_addrs as usize] + self.carry as u8;
+        val += self.read_character() + self.carry as u8;
         self.carry = false;
 
         if val & 0xF0 != 0 {             
@@ -500,8 +1384,7 @@ impl Intel4004 {
     fn rd0(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        let val = self.ram.status[(ram_register * 4) as usize];
+        let val = self.read_status(0);
         self.acc = u4::new(val);
     }
 
@@ -509,8 +1392,7 @@ impl Intel4004 {
     fn rd1(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        let val = self.ram.status[((ram_register * 4) + 1) as usize];
+        let val = self.read_status(1);
         self.acc = u4::new(val);
     }
 
@@ -518,8 +1400,7 @@ impl Intel4004 {
     fn rd2(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        let val = self.ram.status[((ram_register * 4) + 2) as usize];
+        let val = self.read_status(2);
         self.acc = u4::new(val);
     }
 
@@ -527,8 +1408,7 @@ impl Intel4004 {
     fn rd3(&mut self) {
         self.pc += 1;
 
-        let ram_register = ((self.ram_addrs & 0xF0) >> 4) & 0x03; 
-        let val = self.ram.status[((ram_register * 4) + 3) as usize];
+        let val = self.read_status(3);
         self.acc = u4::new(val);
     }
 
@@ -674,4 +1554,86 @@ impl Intel4004 {
 
         self.command_control = self.acc;
     }
+
+    // --- 4040 instructions ---
+
+    /// Interrupt acknowledge. Save SRC for BBS and JMS to address 3 of page 0 in place of the next instruction, this
+    /// also ends a HLT. Further interrupts wait for the BBS.
+    fn take_interrupt(&mut self) {
+        self.interrupt_ack = true;
+        self.halted = false;
+        self.src_save = self.ram_addrs;
+
+        self.stack.push(self.pc);
+        self.pc = 0x003;
+    }
+
+    /// Halt. Stop executing until an interrupt or the STOP pin releases the CPU.
+    fn hlt(&mut self) {
+        self.pc += 1;
+
+        self.halted = true;
+    }
+
+    /// Branch back from an interrupt (down 1 level in stack), restore the SRC register saved when it was taken and
+    /// clear INTA.
+    fn bbs(&mut self) {
+        self.pc = self.stack.pop();
+        self.ram_addrs = self.src_save;
+        self.interrupt_ack = false;
+    }
+
+    /// Load the command register (set by DCL) into the accumulator.
+    fn lcr(&mut self) {
+        self.pc += 1;
+
+        self.acc = self.command_control;
+    }
+
+    /// Logical OR of the accumulator with register 4 or 5.
+    fn or(&mut self, reg_addr: usize) {
+        self.pc += 1;
+
+        self.acc |= self.index[reg_addr];
+    }
+
+    /// Logical AND of the accumulator with register 6 or 7.
+    fn an(&mut self, reg_addr: usize) {
+        self.pc += 1;
+
+        self.acc &= self.index[reg_addr];
+    }
+
+    /// Designate ROM bank. Instructions are fetched from the new bank from the next one on.
+    fn db(&mut self, bank: usize) {
+        self.pc += 1;
+
+        self.rom_bank = bank;
+    }
+
+    /// Select index register bank, R0-R7 are switched and R8-R15 are shared.
+    fn sb(&mut self, bank: usize) {
+        self.pc += 1;
+
+        self.set_register_bank(bank);
+    }
+
+    /// Enable interrupts.
+    fn ein(&mut self) {
+        self.pc += 1;
+
+        self.interrupts_enabled = true;
+    }
+
+    /// Disable interrupts.
+    fn din(&mut self) {
+        self.pc += 1;
+
+        self.interrupts_enabled = false;
+    }
+
+    /// Read program memory, the counterpart of WPM (for use with the 4289 only).
+    fn rpm(&mut self) {
+        self.pc += 1; // Do nothing as there is no 4289 implemented
+    }
 }
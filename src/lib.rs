@@ -1,7 +1,54 @@
 #![crate_type = "lib"]
 #![crate_name = "intel4004_emu"]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+// The cdylib needs a panic handler, so hosted targets still link std. Nothing uses it, the prelude stays core only.
+#[cfg(all(not(feature = "std"), not(target_os = "none")))]
+extern crate std;
+
+pub mod bus;
 #[macro_use]
 pub mod intel4001;
 pub mod intel4002;
 pub mod intel4004;
-pub mod disassembler;
\ No newline at end of file
+pub mod opcodes;
+pub mod peripherals;
+
+// Everything below allocates or touches files and the terminal, the core above runs without std or a heap.
+
+#[cfg(feature = "std")]
+pub mod disassembler;
+#[cfg(feature = "std")]
+pub mod assembler;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod profiler;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod strict;
+#[cfg(feature = "std")]
+pub mod sanitizer;
+#[cfg(feature = "std")]
+pub mod vcd;
+#[cfg(feature = "std")]
+pub mod runner;
+#[cfg(feature = "std")]
+pub mod gdb;
+
+#[cfg(feature = "tui")]
+pub mod tui;
+
+#[cfg(feature = "scripting")]
+pub mod script;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "python")]
+pub mod python;
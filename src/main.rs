@@ -1,33 +1,533 @@
-use std::io;
+use std::{env, fs, io, process};
+use std::io::{BufRead, Write};
+use std::path::Path;
 use std::{thread, time};
 
-use intel4004_emu::intel4004::Intel4004;
-use intel4004_emu::disassembler::*;
+use intel4004_emu::analysis::check_stack_for;
+use intel4004_emu::assembler::{Assembler, Severity};
+use intel4004_emu::bus::Bus;
+use intel4004_emu::debugger::{memory_text, Debugger};
+use intel4004_emu::disassembler::{disassemble_for, disassemble_source_for, selected_bank};
+use intel4004_emu::gdb::GdbStub;
+use intel4004_emu::intel4004::{Fill, Intel4004, UndefinedPolicy, RAM_CHIPS, ROM_BANKS, ROM_CHIPS};
+use intel4004_emu::opcodes::Model;
+use intel4004_emu::peripherals::{profile, PROFILES};
+use intel4004_emu::runner::{run, ExitReason, Port, RunLimits, RunResult, Watch};
+use intel4004_emu::strict::StrictMode;
+#[cfg(feature = "scripting")]
+use intel4004_emu::script::Script;
+use intel4004_emu::symbols::SymbolTable;
+use intel4004_emu::vcd::Vcd;
+#[cfg(feature = "tui")]
+use intel4004_emu::tui::Tui;
+
+const DEFAULT_CYCLES: u64 = 10_000_000;                             // Budget when an unthrottled run is given no limit.
+const REAL_SPEED: u64 = 92_593;                                     // Instruction cycles per second, one every 10.8 us.
+
+const USAGE: &str = "usage: intel4004_emu <command> [options]
+
+commands:
+  run <rom>              run until a stop condition, print why it stopped and the registers
+  dump <rom>             like run, also print RAM and the I/O ports
+  debug <rom>            interactive debugger, type 'help' at the prompt
+  tui <rom>              full-screen debugger with the same commands
+  gdb <rom>              wait for a gdb connection and serve the remote protocol
+  script <file>...       run Rhai test scripts, exits with 1 if one fails
+  asm <src> -o <rom>     assemble
+  disasm <rom>           disassemble to source that assembles back to the same image
+  stack <rom>            report JMS chains that nest deeper than the stack
+
+machine options (run, dump, debug, tui, gdb):
+  --cpu 4004|4040        CPU model (default 4004)
+  --rom-chips n          populated 4001 chips, 1-16, or 1-32 in 2 banks on the 4040 (default all)
+  --ram-chips n          populated 4002 chips, 1-16 (default 16)
+  --profile name         peripheral on the I/O ports: none, console or leds (default none)
+  --undefined policy     undefined opcodes: nop, trap (stop on them) or silicon (default nop)
+  --strict log|halt      report misuse the hardware would not tolerate, halt also stops on it
+  --sanitize log|halt    report reads of registers, RAM and ports that were never written
+  --fill zero|pattern:n|random[:seed]
+                         power-on contents of registers, RAM and ports (default zero)
+  --bus                  run every instruction phase by phase on the modelled MCS-4 bus
+  --symbols file         symbol file written by asm -s
+
+run and dump options:
+  --speed n|real         instruction cycles per second (default unthrottled)
+  --trace                print every instruction
+  --cycles n             stop after n instruction cycles
+  --instructions n       stop after n instructions
+  --until addr           stop when the PC reaches a label or address
+  --port rom|ram[:chip]=value
+                         stop when an output port holds value
+  --watch ram|status|reg:index
+                         stop when a RAM character, status character or register changes
+  --no-halt              don't stop on a JUN to itself
+  --vcd file             write PC, clock phases, ports and TEST as a waveform, with --bus also the bus lines
+
+gdb options:
+  --port n               TCP port on localhost, 1-65535 (default 1234)
+
+asm options:
+  --cpu 4004|4040        instruction set (default 4004), also taken by disasm and stack
+  -o rom                 output image (required)
+  -l listing             write a listing
+  -s symbols             write a symbol file
+  -I dir                 add an include directory
+  -D name=value          define a constant
+  --pad                  move instructions that would cross a page to the next page
+
+disasm options:
+  -o file                output file (default stdout)
+  --symbols file         label the output
+  --entry addr           additional entry point, the reset vector is always one
+
+stack options:
+  --symbols file         name the subroutines in the report";
 
 fn main() -> io::Result<()>{
 
-    let mut cpu = Intel4004::new();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let rest = args.get(1..).unwrap_or_default();
+
+    match args.first().map(String::as_str) {
+        Some("run") => run_command(rest, false),
+        Some("dump") => run_command(rest, true),
+        Some("debug") => debug_command(rest),
+        Some("tui") => tui_command(rest),
+        Some("gdb") => gdb_command(rest),
+        Some("script") => script_command(rest),
+        Some("asm") => asm_command(rest),
+        Some("disasm") => disasm_command(rest),
+        Some("stack") => stack(rest),
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => usage(&format!("unknown command '{}'", command)),
+        None => usage("missing command"),
+    }
+}
+// TODO: check correct functionallity
+
+fn usage(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// --- Arguments ---
+
+/// Command line of a subcommand split into positional arguments, flags and options with a value.
+struct Args {
+    positional: Vec<String>,
+    flags: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+impl Args {
+    /// Exits with the usage on anything not in `flags` or `options`.
+    fn parse(args: &[String], flags: &[&str], options: &[&str]) -> Self {
+        let mut parsed = Args { positional: Vec::new(), flags: Vec::new(), options: Vec::new() };
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if flags.contains(&arg.as_str()) {
+                parsed.flags.push(arg.clone());
+            } else if options.contains(&arg.as_str()) {
+                match args.next() {
+                    Some(value) => parsed.options.push((arg.clone(), value.clone())),
+                    None => usage(&format!("missing value for {}", arg)),
+                }
+            } else if arg.starts_with('-') && arg.len() > 1 {
+                usage(&format!("unknown option '{}'", arg));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+        parsed
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|f| f == name)
+    }
+
+    /// Last value given for an option.
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options.iter().rev().find(|(option, _)| option == name).map(|(_, value)| value.as_str())
+    }
+
+    fn values<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.options.iter().filter(move |(option, _)| option == name).map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, name: &str) -> Option<u64> {
+        self.value(name).map(|value| parse_number(value).unwrap_or_else(|| usage(&format!("bad number '{}' for {}", value, name))))
+    }
+
+    /// The only positional argument, named `what` in the error.
+    fn file(&self, what: &str) -> &str {
+        match self.positional.as_slice() {
+            [file] => file,
+            [] => usage(&format!("missing {}", what)),
+            _ => usage(&format!("unexpected argument '{}'", self.positional[1])),
+        }
+    }
+}
+
+const MACHINE_OPTIONS: [&str; 9] = ["--cpu", "--rom-chips", "--ram-chips", "--profile", "--undefined", "--strict",
+    "--sanitize", "--fill", "--symbols"];
+const MACHINE_FLAGS: [&str; 1] = ["--bus"];
+const RUN_OPTIONS: [&str; 7] = ["--speed", "--cycles", "--instructions", "--until", "--port", "--watch", "--vcd"];
+
+fn symbols(args: &Args) -> io::Result<SymbolTable> {
+    match args.value("--symbols") {
+        Some(filename) => SymbolTable::load(filename),
+        None => Ok(SymbolTable::new()),
+    }
+}
+
+fn model(args: &Args) -> Model {
+    let name = args.value("--cpu").unwrap_or("4004");
+    Model::parse(name).unwrap_or_else(|| usage(&format!("unknown cpu '{}', expected 4004 or 4040", name)))
+}
+
+/// CPU with the chips and peripheral chosen on the command line and the ROM loaded.
+fn machine(args: &Args) -> io::Result<Intel4004> {
+    let model = model(args);
+    let max_rom_chips = if model == Model::I4040 { ROM_CHIPS * ROM_BANKS } else { ROM_CHIPS };
+    let rom_chips = args.number("--rom-chips").unwrap_or(max_rom_chips as u64) as usize;
+    let ram_chips = args.number("--ram-chips").unwrap_or(RAM_CHIPS as u64) as usize;
+    if !(1..=max_rom_chips).contains(&rom_chips) || !(1..=RAM_CHIPS).contains(&ram_chips) {
+        usage(&format!("chip counts must be between 1 and {} for ROM and 1 and 16 for RAM", max_rom_chips));
+    }
+
+    let mut cpu = Intel4004::with_model(model, rom_chips, ram_chips);
+    let policy = args.value("--undefined").unwrap_or("nop");
+    cpu.set_undefined_policy(UndefinedPolicy::parse(policy)
+        .unwrap_or_else(|| usage(&format!("unknown policy '{}', expected nop, trap or silicon", policy))));
+    if let Some(mode) = args.value("--strict") {
+        cpu.enable_strict(StrictMode::parse(mode)
+            .unwrap_or_else(|| usage(&format!("unknown strict mode '{}', expected log or halt", mode))));
+    }
+    if let Some(mode) = args.value("--sanitize") {
+        cpu.enable_sanitizer(StrictMode::parse(mode)
+            .unwrap_or_else(|| usage(&format!("unknown sanitizer mode '{}', expected log or halt", mode))));
+    }
+    let fill = args.value("--fill").unwrap_or("zero");
+    cpu.fill(Fill::parse(fill).unwrap_or_else(|| usage(&format!("bad fill '{}'", fill))));
+    if args.flag("--bus") {
+        cpu.enable_bus(Bus::new());
+    }
+    cpu.load_rom(args.file("rom"))?;
+
+    let name = args.value("--profile").unwrap_or("none");
+    match profile(name) {
+        Some(peripheral) => cpu.attach_peripheral(peripheral),
+        None => usage(&format!("unknown profile '{}', expected one of {}", name, PROFILES.join(", "))),
+    }
+    Ok(cpu)
+}
+
+// --- run and dump ---
+
+fn limits(args: &Args, symbols: &SymbolTable) -> RunLimits {
+    let mut limits = RunLimits::new();
+
+    limits.halt_on_self_jump(!args.flag("--no-halt"));
+    for value in args.values("--until") {
+        limits.stop_at(symbols.resolve(value).unwrap_or_else(|| usage(&format!("unknown address '{}'", value))));
+    }
+    for value in args.values("--port") {
+        let (port, v) = Port::parse_condition(value).unwrap_or_else(|| usage(&format!("bad port '{}'", value)));
+        limits.stop_on_port(port, v);
+    }
+    for value in args.values("--watch") {
+        limits.watch(Watch::parse(value).unwrap_or_else(|| usage(&format!("bad watch '{}'", value))));
+    }
+    limits
+}
+
+/// `run` in slices of at most `slice` cycles, `before` gets the cycles run so far ahead of each slice so the caller
+/// can trace or throttle.
+fn run_sliced(cpu: &mut Intel4004, limits: &RunLimits, budget: (Option<u64>, Option<u64>), slice: u64,
+    mut before: impl FnMut(&Intel4004, u64)) -> RunResult {
+    let (max_cycles, max_instructions) = budget;
+    let (mut cycles, mut instructions, mut undefined) = (0, 0, 0);
+
+    loop {
+        let mut slice_limits = limits.clone();
+        slice_limits.max_cycles(max_cycles.map_or(slice, |max| slice.min(max - cycles)));
+        if let Some(max) = max_instructions {
+            slice_limits.max_instructions(max - instructions);
+        }
+
+        before(cpu, cycles);
+        let mut result = run(cpu, &slice_limits);
+        cycles += result.cycles;
+        instructions += result.instructions;
+        undefined += result.undefined;
 
-    cpu.rom.load_rom("rom/ram_test")?;
+        let budget_left = max_cycles.is_none_or(|max| cycles < max);
+        if result.reason != ExitReason::CycleLimit || !budget_left {
+            result.cycles = cycles;
+            result.instructions = instructions;
+            result.undefined = undefined;
+            return result;
+        }
+    }
+}
+
+/// `run <rom>` and `dump <rom>`: execute until a stop condition. Exits with 1 if the budget ran out first, an
+/// undefined opcode trapped or a strict mode or sanitizer check halted the run.
+fn run_command(args: &[String], dump: bool) -> io::Result<()> {
+    let flags: Vec<&str> = ["--trace", "--no-halt"].iter().chain(MACHINE_FLAGS.iter()).copied().collect();
+    let options: Vec<&str> = MACHINE_OPTIONS.iter().chain(RUN_OPTIONS.iter()).copied().collect();
+    let args = Args::parse(args, &flags, &options);
+
+    let symbols = symbols(&args)?;
+    let mut cpu = machine(&args)?;
+    let mut limits = limits(&args, &symbols);
+    if let Some(path) = args.value("--vcd") {
+        let vcd = Vcd::new(Box::new(io::BufWriter::new(fs::File::create(path)?)), &cpu)?;
+        cpu.enable_vcd(vcd);
+    }
+
+    let speed = args.value("--speed").map(|speed| match speed {
+        "real" => REAL_SPEED,
+        _ => parse_number(speed).filter(|s| *s > 0).unwrap_or_else(|| usage(&format!("bad speed '{}'", speed))),
+    });
+    let trace = args.flag("--trace");
+    let mut budget = (args.number("--cycles"), args.number("--instructions"));
+    if budget == (None, None) && speed.is_none() {
+        budget.0 = Some(DEFAULT_CYCLES);
+    }
+
+    let result = if speed.is_none() && !trace {
+        if let Some(max) = budget.0 { limits.max_cycles(max) }
+        if let Some(max) = budget.1 { limits.max_instructions(max) }
+        run(&mut cpu, &limits)
+    } else {
+        // Tracing runs one instruction per slice, throttling sleeps after each 10 ms worth of cycles.
+        let slice = if trace { 1 } else { (speed.unwrap() / 100).max(1) };
+        let rom = cpu.rom_image();
+        let started = time::Instant::now();
+
+        run_sliced(&mut cpu, &limits, budget, slice, |cpu, cycles| {
+            if let Some(speed) = speed {
+                let due = time::Duration::from_micros(cycles * 1_000_000 / speed);
+                if let Some(wait) = due.checked_sub(started.elapsed()) {
+                    thread::sleep(wait);
+                }
+            }
+            if trace {
+                let pc = cpu.get_pc();
+                let (text, _) = disassemble_for(cpu.get_model(), selected_bank(cpu, &rom), pc, Some(&symbols));
+                let label = symbols.label_at(pc).map(|l| format!("{}:", l)).unwrap_or_default();
+                println!("{:03X}  {:<12} {}", pc, label, text);
+            }
+        })
+    };
+
+    if let Some(vcd) = cpu.take_vcd() {
+        vcd.finish()?;
+    }
+    print!("{}", result.to_text());
+    if dump {
+        print!("{}", memory_text(&cpu));
+    }
+    if let Some(strict) = cpu.strict() {
+        for diagnostic in strict.diagnostics() {
+            eprintln!("strict: {}", diagnostic);
+        }
+    }
+    if let Some(sanitizer) = cpu.sanitizer() {
+        for diagnostic in sanitizer.diagnostics() {
+            eprintln!("uninitialized: {}", diagnostic);
+        }
+    }
+
+    if result.reason.is_limit() || matches!(result.reason,
+        ExitReason::Undefined(..) | ExitReason::Strict(_) | ExitReason::Uninitialized(_)) {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// --- debug ---
 
-    let mut i = 0;
-    
-    let delay = time::Duration::from_millis(10);
-    let mut now = time::Instant::now();
+/// `debug <rom>`: read debugger commands from standard input.
+fn debug_command(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &MACHINE_FLAGS, &MACHINE_OPTIONS);
 
-    while i < 5000 {
-        now = time::Instant::now();
+    let mut debugger = Debugger::new(machine(&args)?);
+    debugger.set_symbols(symbols(&args)?);
 
-        cpu.clock();
+    let stdin = io::stdin();
+    println!("{}", debugger.trace_line(debugger.cpu.get_pc()));
+    print!("(4004) ");
+    io::stdout().flush()?;
 
-        print_rom(&cpu.rom);
-        print_ram(&cpu.ram);
-        print_cpu_state(&cpu);
+    for line in stdin.lock().lines() {
+        match debugger.execute(&line?) {
+            Some(output) if output.is_empty() => {}
+            Some(output) => println!("{}", output),
+            None => return Ok(()),
+        }
 
-        thread::sleep(delay);
-        i += 1;
+        println!("{}", debugger.trace_line(debugger.cpu.get_pc()));
+        print!("(4004) ");
+        io::stdout().flush()?;
     }
+    println!();
+    Ok(())
+}
 
+/// `tui <rom>`: the debugger with panes for the code, registers, RAM and ports.
+#[cfg(feature = "tui")]
+fn tui_command(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &MACHINE_FLAGS, &MACHINE_OPTIONS);
+
+    let mut debugger = Debugger::new(machine(&args)?);
+    debugger.set_symbols(symbols(&args)?);
+    Tui::new(debugger).run()
+}
+
+#[cfg(not(feature = "tui"))]
+fn tui_command(_args: &[String]) -> io::Result<()> {
+    usage("built without the tui feature")
+}
+
+/// `gdb <rom>`: serve one gdb session, ROM at 0x000, RAM characters at 0x1000 and status characters at 0x2000.
+fn gdb_command(args: &[String]) -> io::Result<()> {
+    let options: Vec<&str> = MACHINE_OPTIONS.iter().chain(["--port"].iter()).copied().collect();
+    let args = Args::parse(args, &MACHINE_FLAGS, &options);
+    let port = match args.number("--port") {
+        Some(port) => u16::try_from(port).ok().filter(|port| *port > 0)
+            .unwrap_or_else(|| usage(&format!("bad port {} for --port, expected 1-65535", port))),
+        None => 1234,
+    };
+
+    let mut stub = GdbStub::new(machine(&args)?);
+    println!("waiting for gdb on 127.0.0.1:{}", port);
+    stub.listen(("127.0.0.1", port))
+}
+
+/// `script <file>...`: run every script, report each failure and exit with 1 if there was one.
+#[cfg(feature = "scripting")]
+fn script_command(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &[], &[]);
+    if args.positional.is_empty() {
+        usage("missing script");
+    }
+
+    let script = Script::new();
+    let mut failed = 0;
+    for file in &args.positional {
+        match script.run_file(Path::new(file)) {
+            Ok(()) => println!("{}: ok", file),
+            Err(e) => {
+                println!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        println!("{} of {} script(s) failed", failed, args.positional.len());
+        process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "scripting"))]
+fn script_command(_args: &[String]) -> io::Result<()> {
+    usage("built without the scripting feature")
+}
+
+// --- asm, disasm and stack ---
+
+/// `asm <src> -o <rom>`: exits with 1 if there are errors, warnings are printed either way.
+fn asm_command(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &["--pad"], &["--cpu", "-o", "-l", "-s", "-I", "-D"]);
+    let source = args.file("source");
+    let output = args.value("-o").unwrap_or_else(|| usage("missing -o"));
+
+    let mut assembler = Assembler::new();
+    assembler.auto_pad(args.flag("--pad"));
+    assembler.model(model(&args));
+    for dir in args.values("-I") {
+        assembler.include_dir(Path::new(dir));
+    }
+    for definition in args.values("-D") {
+        let (name, value) = definition.split_once('=').unwrap_or((definition, "1"));
+        match parse_number(value) {
+            Some(value) => assembler.define(name, value as i64),
+            None => usage(&format!("bad definition '{}'", definition)),
+        }
+    }
+
+    let asm = match assembler.assemble_file(Path::new(source)) {
+        Ok(asm) => asm,
+        Err(errors) => {
+            errors.iter().for_each(|e| eprintln!("{}", e));
+            let count = errors.iter().filter(|e| e.severity == Severity::Error).count();
+            eprintln!("{} error(s)", count);
+            process::exit(1);
+        }
+    };
+    asm.warnings.iter().for_each(|w| eprintln!("{}", w));
+
+    fs::write(output, &asm.image)?;
+    if let Some(listing) = args.value("-l") {
+        asm.save_listing(listing)?;
+    }
+    if let Some(symbols) = args.value("-s") {
+        asm.save_symbols(symbols)?;
+    }
+    Ok(())
+}
+
+/// `disasm <rom>`: source that assembles back to the same image.
+fn disasm_command(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &[], &["--cpu", "-o", "--symbols", "--entry"]);
+    let rom = fs::read(args.file("rom"))?;
+    let symbols = args.value("--symbols").map(SymbolTable::load).transpose()?;
+
+    let mut entries = vec![0x000];
+    for entry in args.values("--entry") {
+        let resolved = match &symbols {
+            Some(symbols) => symbols.resolve(entry),
+            None => SymbolTable::new().resolve(entry),
+        };
+        entries.push(resolved.unwrap_or_else(|| usage(&format!("unknown address '{}'", entry))));
+    }
+
+    let source = disassemble_source_for(model(&args), &rom, &entries, symbols.as_ref());
+    match args.value("-o") {
+        Some(output) => fs::write(output, source),
+        None => {
+            print!("{}", source);
+            Ok(())
+        }
+    }
+}
+
+/// `stack <rom>`: report JMS chains that nest deeper than the stack of `--cpu`, exits with 1 if any.
+fn stack(args: &[String]) -> io::Result<()> {
+    let args = Args::parse(args, &[], &["--cpu", "--symbols"]);
+    let rom = fs::read(args.file("rom"))?;
+    let symbols = args.value("--symbols").map(SymbolTable::load).transpose()?;
+
+    let report = check_stack_for(model(&args), &rom, &[0x000]);
+    print!("{}", report.to_text(symbols.as_ref()));
+
+    if !report.is_ok() {
+        process::exit(1);
+    }
     Ok(())
 }
-// TODO: check correct functionallity
\ No newline at end of file
@@ -0,0 +1,190 @@
+// Opcode table
+
+/// Operand format of an instruction, as written in assembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operands {
+    None,
+    Reg,                                                            // Register in OPA.
+    Pair,                                                           // Register pair in bits 3-1 of OPA.
+    Data4,                                                          // 4-bit immediate in OPA.
+    PairData8,                                                      // FIM: register pair and 8-bit immediate.
+    CondAddr8,                                                      // JCN: condition and address in the page.
+    RegAddr8,                                                       // ISZ: register and address in the page.
+    Addr12,                                                         // JUN and JMS: 12-bit address.
+}
+
+impl Operands {
+    /// Words taken by an instruction with these operands.
+    pub const fn size(self) -> u16 {
+        match self {
+            Operands::PairData8 | Operands::CondAddr8 | Operands::RegAddr8 | Operands::Addr12 => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Nop, Jcn, Fim, Src, Fin, Jin, Jun, Jms, Inc, Isz, Add, Sub, Ld, Xch, Bbl, Ldm,
+    Wrm, Wmp, Wrr, Wpm, Wr0, Wr1, Wr2, Wr3, Sbm, Rdm, Rdr, Adm, Rd0, Rd1, Rd2, Rd3,
+    Clb, Clc, Iac, Cmc, Cma, Ral, Rar, Tcc, Dac, Tcs, Stc, Daa, Kbp, Dcl,
+    Hlt, Bbs, Lcr, Or4, Or5, An6, An7, Db0, Db1, Sb0, Sb1, Ein, Din, Rpm,          // 4040 only.
+    Undefined,
+}
+
+impl Op {
+    /// Assembly mnemonic, empty for undefined opcodes.
+    pub const fn mnemonic(self) -> &'static str {
+        MNEMONICS[self as usize]
+    }
+}
+
+const MNEMONICS: [&str; 61] = [
+    "NOP", "JCN", "FIM", "SRC", "FIN", "JIN", "JUN", "JMS", "INC", "ISZ", "ADD", "SUB", "LD", "XCH", "BBL", "LDM",
+    "WRM", "WMP", "WRR", "WPM", "WR0", "WR1", "WR2", "WR3", "SBM", "RDM", "RDR", "ADM", "RD0", "RD1", "RD2", "RD3",
+    "CLB", "CLC", "IAC", "CMC", "CMA", "RAL", "RAR", "TCC", "DAC", "TCS", "STC", "DAA", "KBP", "DCL",
+    "HLT", "BBS", "LCR", "OR4", "OR5", "AN6", "AN7", "DB0", "DB1", "SB0", "SB1", "EIN", "DIN", "RPM",
+    "",
+];
+
+/// CPU the opcodes are decoded for. The 4040 runs every 4004 program and adds instructions in opcodes 0x01-0x0E.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    #[default]
+    I4004,
+    I4040,
+}
+
+impl Model {
+    /// Parse "4004" or "4040".
+    pub fn parse(text: &str) -> Option<Model> {
+        match text {
+            "4004" => Some(Model::I4004),
+            "4040" => Some(Model::I4040),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Model::I4004 => "4004",
+            Model::I4040 => "4040",
+        }
+    }
+
+    /// Return addresses the stack holds, a deeper JMS overwrites the oldest one.
+    pub const fn stack_levels(self) -> usize {
+        match self {
+            Model::I4004 => 3,
+            Model::I4040 => 7,
+        }
+    }
+
+    pub fn table(self) -> &'static [Instruction; 256] {
+        match self {
+            Model::I4004 => &DECODE_TABLE,
+            Model::I4040 => &DECODE_TABLE_4040,
+        }
+    }
+
+    pub fn decode(self, op_code: u8) -> &'static Instruction {
+        &self.table()[op_code as usize]
+    }
+
+    pub fn is_undefined(self, op_code: u8) -> bool {
+        self.decode(op_code).op == Op::Undefined
+    }
+
+    /// First opcode of a mnemonic on this CPU and its operand format.
+    pub fn lookup(self, mnemonic: &str) -> Option<(u8, Operands)> {
+        let table = self.table();
+        table.iter().position(|i| i.op != Op::Undefined && i.op.mnemonic() == mnemonic)
+            .map(|op_code| (op_code as u8, table[op_code].operands))
+    }
+}
+
+/// Everything known about an opcode without executing it, kept small so the whole table stays in cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub op: Op,
+    pub operands: Operands,
+    pub opa: u8,                                                    // Low 4 bits of the opcode.
+    pub size: u16,                                                  // Words, also the instruction cycles it takes.
+}
+
+const IO_GROUP: [Op; 16] = [
+    Op::Wrm, Op::Wmp, Op::Wrr, Op::Wpm, Op::Wr0, Op::Wr1, Op::Wr2, Op::Wr3,
+    Op::Sbm, Op::Rdm, Op::Rdr, Op::Adm, Op::Rd0, Op::Rd1, Op::Rd2, Op::Rd3,
+];
+
+const ACC_GROUP: [Op; 14] = [
+    Op::Clb, Op::Clc, Op::Iac, Op::Cmc, Op::Cma, Op::Ral, Op::Rar, Op::Tcc, Op::Dac, Op::Tcs, Op::Stc, Op::Daa, Op::Kbp, Op::Dcl,
+];
+
+const EXTENDED_GROUP: [Op; 14] = [
+    Op::Hlt, Op::Bbs, Op::Lcr, Op::Or4, Op::Or5, Op::An6, Op::An7, Op::Db0, Op::Db1, Op::Sb0, Op::Sb1, Op::Ein, Op::Din, Op::Rpm,
+];
+
+const fn describe(op_code: u8, model: Model) -> Instruction {
+    let opa = op_code & 0x0F;
+    let even = opa & 1 == 0;
+    let extended = matches!(model, Model::I4040);
+
+    let (op, operands) = match op_code & 0xF0 {
+        0x00 if op_code == 0x00 => (Op::Nop, Operands::None),
+        0x00 if extended && opa <= 0x0E => (EXTENDED_GROUP[opa as usize - 1], Operands::None),
+        0x10 => (Op::Jcn, Operands::CondAddr8),
+        0x20 if even => (Op::Fim, Operands::PairData8),
+        0x20 => (Op::Src, Operands::Pair),
+        0x30 if even => (Op::Fin, Operands::Pair),
+        0x30 => (Op::Jin, Operands::Pair),
+        0x40 => (Op::Jun, Operands::Addr12),
+        0x50 => (Op::Jms, Operands::Addr12),
+        0x60 => (Op::Inc, Operands::Reg),
+        0x70 => (Op::Isz, Operands::RegAddr8),
+        0x80 => (Op::Add, Operands::Reg),
+        0x90 => (Op::Sub, Operands::Reg),
+        0xA0 => (Op::Ld, Operands::Reg),
+        0xB0 => (Op::Xch, Operands::Reg),
+        0xC0 => (Op::Bbl, Operands::Data4),
+        0xD0 => (Op::Ldm, Operands::Data4),
+        0xE0 => (IO_GROUP[opa as usize], Operands::None),
+        0xF0 if (opa as usize) < ACC_GROUP.len() => (ACC_GROUP[opa as usize], Operands::None),
+        _ => (Op::Undefined, Operands::None),
+    };
+    Instruction { op, operands, opa, size: operands.size() }
+}
+
+const fn build_table(model: Model) -> [Instruction; 256] {
+    let mut table = [describe(0x00, model); 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = describe(i as u8, model);
+        i += 1;
+    }
+    table
+}
+
+/// All 256 opcodes decoded at compile time, shared by the CPU, the disassembler and the assembler.
+pub static DECODE_TABLE: [Instruction; 256] = build_table(Model::I4004);
+
+/// Same for the 4040.
+pub static DECODE_TABLE_4040: [Instruction; 256] = build_table(Model::I4040);
+
+pub fn decode(op_code: u8) -> &'static Instruction {
+    &DECODE_TABLE[op_code as usize]
+}
+
+/// Opcodes the 4004 does not define.
+pub fn is_undefined(op_code: u8) -> bool {
+    DECODE_TABLE[op_code as usize].op == Op::Undefined
+}
+
+pub fn instruction_size(op_code: u8) -> u16 {
+    DECODE_TABLE[op_code as usize].size
+}
+
+/// First opcode of a mnemonic, the one with all operand bits clear, and its operand format.
+pub fn lookup(mnemonic: &str) -> Option<(u8, Operands)> {
+    Model::I4004.lookup(mnemonic)
+}
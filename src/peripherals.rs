@@ -0,0 +1,106 @@
+// Peripherals
+
+/// Device wired to the ROM I/O lines and RAM output lines. Every method has a default that ignores the access, so a
+/// device only implements the ports it uses.
+pub trait Peripheral {
+    /// WRR wrote `value` to the I/O lines of ROM chip `chip`.
+    fn rom_port_write(&mut self, _chip: usize, _value: u8) {}
+
+    /// RDR reads the I/O lines of ROM chip `chip`, None reads back the last value written.
+    fn rom_port_read(&mut self, _chip: usize) -> Option<u8> {
+        None
+    }
+
+    /// WMP wrote `value` to the output lines of RAM chip `chip`.
+    fn ram_port_write(&mut self, _chip: usize, _value: u8) {}
+
+    /// RESET was asserted, every port now outputs 0.
+    fn reset(&mut self) {}
+}
+
+/// Nothing connected, the ports only latch what is written.
+pub struct Unconnected;
+
+impl Peripheral for Unconnected {}
+
+/// Prints every port write on its own line.
+#[cfg(feature = "std")]
+pub struct Console;
+
+#[cfg(feature = "std")]
+impl Peripheral for Console {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        println!("ROM {:X} <- {:X}", chip, value);
+    }
+
+    fn ram_port_write(&mut self, chip: usize, value: u8) {
+        println!("RAM {:X} <- {:X}", chip, value);
+    }
+
+    fn reset(&mut self) {
+        println!("RESET");
+    }
+}
+
+/// Shows each output port as 4 LEDs, most significant line first, printed whenever a port changes.
+#[cfg(feature = "std")]
+pub struct Leds {
+    rom: [u8; 32],                                                  // Both ROM banks of a 4040.
+    ram: [u8; 16],
+}
+
+#[cfg(feature = "std")]
+impl Leds {
+    pub fn new() -> Self {
+        Leds { rom: [0; 32], ram: [0; 16] }
+    }
+
+    /// LED row of a 4-bit value.
+    pub fn row(value: u8) -> String {
+        (0..4).rev().map(|bit| if value & (1 << bit) != 0 { '*' } else { '.' }).collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Peripheral for Leds {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        if self.rom[chip] != value {
+            self.rom[chip] = value;
+            println!("ROM {:X} {}", chip, Self::row(value));
+        }
+    }
+
+    fn ram_port_write(&mut self, chip: usize, value: u8) {
+        if self.ram[chip] != value {
+            self.ram[chip] = value;
+            println!("RAM {:X} {}", chip, Self::row(value));
+        }
+    }
+
+    fn reset(&mut self) {
+        (0..self.rom.len()).for_each(|chip| self.rom_port_write(chip, 0));
+        (0..self.ram.len()).for_each(|chip| self.ram_port_write(chip, 0));
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Leds {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Names accepted by `profile`.
+#[cfg(feature = "std")]
+pub const PROFILES: [&str; 3] = ["none", "console", "leds"];
+
+/// Peripheral for a profile name.
+#[cfg(feature = "std")]
+pub fn profile(name: &str) -> Option<Box<dyn Peripheral>> {
+    match name {
+        "none" => Some(Box::new(Unconnected)),
+        "console" => Some(Box::new(Console)),
+        "leds" => Some(Box::new(Leds::new())),
+        _ => None,
+    }
+}
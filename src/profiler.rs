@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use super::disassembler::{disassemble, instruction_size};
+use super::symbols::SymbolTable;
+
+// Profiler
+
+const ROM_SIZE: usize = 4096;
+const CYCLE_TIME_US: f64 = 10.8;                                    // 8 clock periods at 740 kHz.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubroutineProfile {
+    pub calls: u64,
+    pub inclusive: u64,                                             // Cycles including the subroutines it calls.
+    pub exclusive: u64,                                             // Cycles spent in its own instructions.
+}
+
+/// Execution counts and instruction cycles per ROM address, with cycles per JMS subroutine. 1-word instructions take
+/// 1 instruction cycle and 2-word instructions take 2.
+pub struct Profiler {
+    executions: Vec<u64>,
+    cycles: Vec<u64>,
+    subroutines: BTreeMap<u16, SubroutineProfile>,
+    call_stack: Vec<u16>,                                           // Entry of every active subroutine, root first.
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            executions: vec![0; ROM_SIZE],
+            cycles: vec![0; ROM_SIZE],
+            subroutines: BTreeMap::new(),
+            call_stack: Vec::new(),
+            total_cycles: 0,
+        }
+    }
+
+    /// Record one executed instruction, `next_pc` is the PC after it ran.
+    pub fn record(&mut self, pc: u16, op_code: u8, next_pc: u16) {
+        let addr = pc as usize % ROM_SIZE;
+        let cycles = instruction_size(op_code) as u64;
+
+        self.executions[addr] += 1;
+        self.cycles[addr] += cycles;
+        self.total_cycles += cycles;
+
+        // The code running when profiling starts is the root.
+        if self.call_stack.is_empty() {
+            self.call_stack.push(pc);
+            self.subroutines.entry(pc).or_default().calls += 1;
+        }
+
+        let current = *self.call_stack.last().unwrap();
+        self.subroutines.entry(current).or_default().exclusive += cycles;
+
+        let mut counted: Vec<u16> = Vec::with_capacity(self.call_stack.len());
+        for entry in &self.call_stack {
+            if !counted.contains(entry) {                               // Count recursive subroutines once.
+                self.subroutines.entry(*entry).or_default().inclusive += cycles;
+                counted.push(*entry);
+            }
+        }
+
+        match op_code & 0xF0 {
+            0x50 => {
+                self.call_stack.push(next_pc);
+                self.subroutines.entry(next_pc).or_default().calls += 1;
+            }
+            0xC0 if self.call_stack.len() > 1 => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn executions(&self, addr: u16) -> u64 {
+        self.executions[addr as usize % ROM_SIZE]
+    }
+
+    pub fn cycles(&self, addr: u16) -> u64 {
+        self.cycles[addr as usize % ROM_SIZE]
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn subroutine(&self, entry: u16) -> Option<&SubroutineProfile> {
+        self.subroutines.get(&entry)
+    }
+
+    /// Subroutines sorted by inclusive cycles, most expensive first.
+    pub fn subroutines(&self) -> Vec<(u16, SubroutineProfile)> {
+        let mut subroutines: Vec<(u16, SubroutineProfile)> = self.subroutines.iter().map(|(a, p)| (*a, *p)).collect();
+        subroutines.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        subroutines
+    }
+
+    /// Executed addresses sorted by cycles, most expensive first.
+    pub fn hot_spots(&self) -> Vec<(u16, u64, u64)> {
+        let mut addrs: Vec<(u16, u64, u64)> = (0..ROM_SIZE)
+            .filter(|a| self.executions[*a] > 0)
+            .map(|a| (a as u16, self.executions[a], self.cycles[a]))
+            .collect();
+        addrs.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        addrs
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / self.total_cycles as f64 }
+    }
+
+    /// Report of subroutines and the `top` most expensive addresses.
+    pub fn report(&self, rom: &[u8], symbols: Option<&SymbolTable>, top: usize) -> String {
+        let name = |addr: u16| match symbols {
+            Some(symbols) => symbols.format_address(addr),
+            None => format!("{:03X}", addr),
+        };
+
+        let mut text = format!("Total: {} cycles ({:.1} us)\n\n", self.total_cycles, self.total_cycles as f64 * CYCLE_TIME_US);
+
+        text.push_str("Subroutine            Calls   Inclusive       %   Exclusive       %\n");
+        for (entry, profile) in self.subroutines() {
+            text.push_str(&format!("{:<20} {:>6} {:>11} {:>6.1}% {:>10} {:>6.1}%\n", name(entry), profile.calls,
+                profile.inclusive, self.percent(profile.inclusive), profile.exclusive, self.percent(profile.exclusive)));
+        }
+
+        text.push_str("\nAddr  Executions    Cycles       %  Instruction\n");
+        for (addr, executions, cycles) in self.hot_spots().into_iter().take(top) {
+            let (instruction, _) = disassemble(rom, addr, symbols);
+            text.push_str(&format!("{:03X}  {:>10} {:>9} {:>6.1}%  {}\n", addr, executions, cycles, self.percent(cycles), instruction));
+        }
+        text
+    }
+
+    /// Disassembly of every executed instruction with its execution count and cycles.
+    pub fn annotated_disassembly(&self, rom: &[u8], symbols: Option<&SymbolTable>) -> String {
+        let mut text = String::new();
+        let mut addr = 0;
+
+        while (addr as usize) < rom.len().min(ROM_SIZE) {
+            let (instruction, size) = disassemble(rom, addr, symbols);
+
+            if self.executions(addr) > 0 {
+                if let Some(label) = symbols.and_then(|s| s.label_at(addr)) {
+                    text.push_str(&format!("{:>28}{}:\n", "", label));
+                }
+                text.push_str(&format!("{:>10} {:>9} {:>5.1}%  {:03X}  {}\n", self.executions(addr), self.cycles(addr),
+                    self.percent(self.cycles(addr)), addr, instruction));
+                addr += size;
+            } else {
+                addr += 1;
+            }
+        }
+        text
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,355 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use arbitrary_int::u4;
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use super::assembler;
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_CHIPS};
+use super::peripherals::Peripheral;
+use super::runner::{run, Port, RunLimits, Watch};
+
+// Python bindings
+
+const DEFAULT_CYCLES: u64 = 10_000_000;                             // Budget of `run` when no limit is given.
+
+type Error = Rc<RefCell<Option<PyErr>>>;                            // First exception raised by a port callback.
+
+/// Calls the Python callables given to `set_io`. Exceptions can't unwind through the CPU, the first one is kept and
+/// raised when `step` or `run` returns.
+struct Callbacks {
+    rom_write: Option<PyObject>,
+    rom_read: Option<PyObject>,
+    ram_write: Option<PyObject>,
+    error: Error,
+}
+
+impl Callbacks {
+    fn call(&self, callback: &Option<PyObject>, chip: usize, value: Option<u8>) -> Option<Option<u8>> {
+        let callback = callback.as_ref()?;
+
+        Python::with_gil(|py| {
+            let result = match value {
+                Some(value) => callback.call1(py, (chip, value)),
+                None => callback.call1(py, (chip,)),
+            };
+            match result.and_then(|r| r.extract::<Option<u8>>(py)) {
+                Ok(value) => Some(value.map(|v| v & 0xF)),
+                Err(e) => {
+                    self.error.borrow_mut().get_or_insert(e);
+                    None
+                }
+            }
+        })
+    }
+}
+
+impl Peripheral for Callbacks {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        self.call(&self.rom_write, chip, Some(value));
+    }
+
+    fn rom_port_read(&mut self, chip: usize) -> Option<u8> {
+        self.call(&self.rom_read, chip, None).flatten()
+    }
+
+    fn ram_port_write(&mut self, chip: usize, value: u8) {
+        self.call(&self.ram_write, chip, Some(value));
+    }
+}
+
+fn index(value: usize, count: usize, what: &str) -> PyResult<usize> {
+    match value < count {
+        true => Ok(value),
+        false => Err(PyIndexError::new_err(format!("{} {} out of range 0-{}", what, value, count - 1))),
+    }
+}
+
+fn nibble(value: u8) -> PyResult<u4> {
+    match value < 16 {
+        true => Ok(u4::new(value)),
+        false => Err(PyValueError::new_err(format!("{} does not fit in 4 bits", value))),
+    }
+}
+
+/// A 4004 with its 4001 ROM and 4002 RAM chips.
+#[pyclass(unsendable, name = "Intel4004", module = "intel4004_emu")]
+pub struct PyIntel4004 {
+    cpu: Intel4004,
+    error: Error,
+}
+
+impl PyIntel4004 {
+    fn check(&self) -> PyResult<()> {
+        match self.error.borrow_mut().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[pymethods]
+impl PyIntel4004 {
+    #[new]
+    #[pyo3(signature = (rom_chips = ROM_CHIPS, ram_chips = RAM_CHIPS))]
+    fn new(rom_chips: usize, ram_chips: usize) -> PyResult<Self> {
+        if !(1..=ROM_CHIPS).contains(&rom_chips) || !(1..=RAM_CHIPS).contains(&ram_chips) {
+            return Err(PyValueError::new_err("chip counts must be 1-16"));
+        }
+        Ok(PyIntel4004 { cpu: Intel4004::with_chips(rom_chips, ram_chips), error: Rc::default() })
+    }
+
+    // --- Memory ---
+
+    /// Copy an image to the ROM chips starting at address 0.
+    fn load_rom(&mut self, image: &[u8]) -> PyResult<()> {
+        if image.len() > self.cpu.get_rom_chips() * 256 {
+            return Err(PyValueError::new_err(format!("{} bytes don't fit in {} ROM chips", image.len(),
+                self.cpu.get_rom_chips())));
+        }
+        self.cpu.load_bytes(image);
+        Ok(())
+    }
+
+    fn rom_image<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.cpu.rom_image())
+    }
+
+    fn read_ram(&self, chip: usize, character: usize) -> PyResult<u8> {
+        Ok(self.cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].ram[index(character, 64, "character")?])
+    }
+
+    fn write_ram(&mut self, chip: usize, character: usize, value: u8) -> PyResult<()> {
+        self.cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].ram[index(character, 64, "character")?] = nibble(value)?.value();
+        Ok(())
+    }
+
+    fn read_status(&self, chip: usize, character: usize) -> PyResult<u8> {
+        Ok(self.cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].status[index(character, 16, "status character")?])
+    }
+
+    fn write_status(&mut self, chip: usize, character: usize, value: u8) -> PyResult<()> {
+        let chip = index(chip, RAM_CHIPS, "RAM chip")?;
+        self.cpu.ram[chip].status[index(character, 16, "status character")?] = nibble(value)?.value();
+        Ok(())
+    }
+
+    /// All 64 characters of a RAM chip, as bytes.
+    fn ram_characters(&self, chip: usize) -> PyResult<Vec<u8>> {
+        Ok(self.cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].ram.to_vec())
+    }
+
+    /// Output lines of a RAM chip, written by WMP.
+    fn ram_port(&self, chip: usize) -> PyResult<u8> {
+        Ok(self.cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].output)
+    }
+
+    /// I/O lines of a ROM chip, written by WRR.
+    fn rom_port(&self, chip: usize) -> PyResult<u8> {
+        Ok(self.cpu.rom[index(chip, ROM_CHIPS, "ROM chip")?].io.value())
+    }
+
+    /// Drive the I/O lines of a ROM chip, read by RDR when no `rom_read` callback answers.
+    fn set_rom_port(&mut self, chip: usize, value: u8) -> PyResult<()> {
+        self.cpu.rom[index(chip, ROM_CHIPS, "ROM chip")?].io = nibble(value)?;
+        Ok(())
+    }
+
+    /// Call `rom_write(chip, value)` after WRR, `ram_write(chip, value)` after WMP and `rom_read(chip)` on RDR, which
+    /// returns the value on the lines or None. Replaces the callbacks set before.
+    #[pyo3(signature = (rom_write = None, rom_read = None, ram_write = None))]
+    fn set_io(&mut self, rom_write: Option<PyObject>, rom_read: Option<PyObject>, ram_write: Option<PyObject>) {
+        let error = self.error.clone();
+        self.cpu.attach_peripheral(Box::new(Callbacks { rom_write, rom_read, ram_write, error }));
+    }
+
+    // --- Execution ---
+
+    /// Assert RESET: clears the CPU registers, RAM and ports, the ROM is kept.
+    fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    #[pyo3(signature = (count = 1))]
+    fn step(&mut self, count: u64) -> PyResult<()> {
+        for _ in 0..count {
+            self.cpu.clock();
+            self.check()?;
+        }
+        Ok(())
+    }
+
+    /// Run until a stop condition like the `run` command, a JUN to itself stops unless `halt` is false. `stop_at` is
+    /// an address or a list of them, `port` and `watch` use the command's syntax. Returns a dict with the `reason`,
+    /// whether a `limit` ended the run, and the `instructions` and `cycles` executed.
+    #[pyo3(signature = (*, max_cycles = None, max_instructions = None, stop_at = None, halt = true, port = None,
+        watch = None))]
+    #[allow(clippy::too_many_arguments)]
+    fn run<'py>(&mut self, py: Python<'py>, max_cycles: Option<u64>, max_instructions: Option<u64>,
+        stop_at: Option<Bound<'py, PyAny>>, halt: bool, port: Option<&str>, watch: Option<&str>)
+        -> PyResult<Bound<'py, PyDict>> {
+        let mut limits = RunLimits::new();
+        limits.halt_on_self_jump(halt);
+        match (max_cycles, max_instructions) {
+            (None, None) => limits.max_cycles(DEFAULT_CYCLES),
+            (cycles, instructions) => {
+                cycles.into_iter().for_each(|c| limits.max_cycles(c));
+                instructions.into_iter().for_each(|i| limits.max_instructions(i));
+            }
+        }
+        if let Some(stop_at) = stop_at {
+            let addresses = match stop_at.extract::<u16>() {
+                Ok(addr) => vec![addr],
+                Err(_) => stop_at.extract::<Vec<u16>>()?,
+            };
+            addresses.into_iter().for_each(|addr| limits.stop_at(addr & 0xFFF));
+        }
+        if let Some(port) = port {
+            let (port, value) = Port::parse_condition(port)
+                .ok_or_else(|| PyValueError::new_err(format!("bad port '{}'", port)))?;
+            limits.stop_on_port(port, value);
+        }
+        if let Some(watch) = watch {
+            limits.watch(Watch::parse(watch).ok_or_else(|| PyValueError::new_err(format!("bad watch '{}'", watch)))?);
+        }
+
+        let result = run(&mut self.cpu, &limits);
+        self.check()?;
+
+        let dict = PyDict::new(py);
+        dict.set_item("reason", result.reason.to_string())?;
+        dict.set_item("limit", result.reason.is_limit())?;
+        dict.set_item("instructions", result.instructions)?;
+        dict.set_item("cycles", result.cycles)?;
+        Ok(dict)
+    }
+
+    // --- Registers ---
+
+    #[getter]
+    fn get_pc(&self) -> u16 {
+        self.cpu.get_pc()
+    }
+
+    #[setter]
+    fn set_pc(&mut self, pc: u16) {
+        self.cpu.set_pc(pc & 0xFFF);
+    }
+
+    #[getter]
+    fn get_acc(&self) -> u8 {
+        self.cpu.get_acc()
+    }
+
+    #[setter]
+    fn set_acc(&mut self, acc: u8) -> PyResult<()> {
+        self.cpu.set_acc(nibble(acc)?.value());
+        Ok(())
+    }
+
+    #[getter]
+    fn get_carry(&self) -> bool {
+        self.cpu.get_carry()
+    }
+
+    #[setter]
+    fn set_carry(&mut self, carry: bool) {
+        self.cpu.set_carry(carry);
+    }
+
+    /// Level of the TEST pin.
+    #[getter]
+    fn get_test(&self) -> bool {
+        self.cpu.get_test()
+    }
+
+    #[setter]
+    fn set_test(&mut self, test: bool) {
+        self.cpu.set_test(test);
+    }
+
+    /// Address sent by the last SRC.
+    #[getter]
+    fn get_src(&self) -> u8 {
+        self.cpu.get_ram_addrs()
+    }
+
+    #[setter]
+    fn set_src(&mut self, src: u8) {
+        self.cpu.set_ram_addrs(src);
+    }
+
+    #[getter]
+    fn get_dcl(&self) -> u8 {
+        self.cpu.get_cc()
+    }
+
+    #[setter]
+    fn set_dcl(&mut self, dcl: u8) -> PyResult<()> {
+        self.cpu.set_cc(nibble(dcl)?.value() & 0x7);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_stack(&self) -> (u16, u16, u16) {
+        let stack = self.cpu.get_stack();
+        (stack[0], stack[1], stack[2])
+    }
+
+    /// R0-R15, as bytes.
+    #[getter]
+    fn get_registers(&self) -> Vec<u8> {
+        self.cpu.get_index().iter().map(|r| r.value()).collect()
+    }
+
+    #[getter]
+    fn get_rom_chips(&self) -> usize {
+        self.cpu.get_rom_chips()
+    }
+
+    #[getter]
+    fn get_ram_chips(&self) -> usize {
+        self.cpu.get_ram_chips()
+    }
+
+    fn get_reg(&self, r: usize) -> PyResult<u8> {
+        Ok(self.cpu.get_index()[index(r, 16, "register")?].value())
+    }
+
+    fn set_reg(&mut self, r: usize, value: u8) -> PyResult<()> {
+        self.cpu.set_index(index(r, 16, "register")?, nibble(value)?);
+        Ok(())
+    }
+
+    /// Pair P0-P7, the even register is the high nibble.
+    fn get_pair(&self, p: usize) -> PyResult<u8> {
+        let p = index(p, 8, "pair")?;
+        let index = self.cpu.get_index();
+        Ok(index[p * 2].value() << 4 | index[p * 2 + 1].value())
+    }
+
+    fn set_pair(&mut self, p: usize, value: u8) -> PyResult<()> {
+        let p = index(p, 8, "pair")?;
+        self.cpu.set_index(p * 2, u4::new(value >> 4));
+        self.cpu.set_index(p * 2 + 1, u4::new(value & 0xF));
+        Ok(())
+    }
+}
+
+/// Assemble source to a ROM image, raises ValueError with every error.
+#[pyfunction]
+fn assemble<'py>(py: Python<'py>, source: &str) -> PyResult<Bound<'py, PyBytes>> {
+    match assembler::assemble(source) {
+        Ok(asm) => Ok(PyBytes::new(py, &asm.image)),
+        Err(errors) => Err(PyValueError::new_err(errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n"))),
+    }
+}
+
+#[pymodule]
+pub fn intel4004_emu(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyIntel4004>()?;
+    module.add_function(wrap_pyfunction!(assemble, module)?)?;
+    Ok(())
+}
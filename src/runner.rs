@@ -0,0 +1,314 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_BANKS, ROM_CHIPS};
+use super::opcodes::{Model, Op};
+
+// Batch runner
+
+/// Output lines that can stop a run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    Rom(usize),                                                     // I/O lines of a 4001, written by WRR.
+    Ram(usize),                                                     // Output lines of a 4002, written by WMP.
+}
+
+/// Storage whose value is compared after every instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    Ram(usize),                                                     // Main memory character, chip * 64 + character.
+    Status(usize),                                                  // Status character, chip * 16 + character.
+    Register(usize),                                                // Index register.
+}
+
+impl Port {
+    /// Parse `rom|ram[:chip]=value`, the port and the value that stops a run. ROM chips 16-31 are the second bank of
+    /// the 4040. None if the chip does not exist or the value does not fit the 4 port lines.
+    pub fn parse_condition(text: &str) -> Option<(Port, u8)> {
+        let (port, value) = text.split_once('=')?;
+        let (kind, chip) = match port.split_once(':') {
+            Some((kind, chip)) => (kind, parse_number(chip)?),
+            None => (port, 0),
+        };
+        let port = match kind {
+            "rom" if chip < ROM_CHIPS * ROM_BANKS => Port::Rom(chip),
+            "ram" if chip < RAM_CHIPS => Port::Ram(chip),
+            _ => return None,
+        };
+        match parse_number(value)? {
+            value @ 0..=15 => Some((port, value as u8)),
+            _ => None,
+        }
+    }
+}
+
+impl Watch {
+    /// Parse `ram|status|reg:index`.
+    pub fn parse(text: &str) -> Option<Watch> {
+        let (kind, index) = text.split_once(':')?;
+        let index = parse_number(index)?;
+
+        match kind {
+            "ram" if index < RAM_CHIPS * 64 => Some(Watch::Ram(index)),
+            "status" if index < RAM_CHIPS * 16 => Some(Watch::Status(index)),
+            "reg" if index < 16 => Some(Watch::Register(index)),
+            _ => None,
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+impl fmt::Display for Port {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Port::Rom(chip) => write!(f, "ROM {} port", chip),
+            Port::Ram(chip) => write!(f, "RAM {} port", chip),
+        }
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watch::Ram(i) => write!(f, "RAM character {}", i),
+            Watch::Status(i) => write!(f, "status character {}", i),
+            Watch::Register(i) => write!(f, "R{}", i),
+        }
+    }
+}
+
+/// Why `run` returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    CycleLimit,
+    InstructionLimit,
+    Address(u16),                                                   // PC reached a stop address.
+    Halt(u16),                                                      // JUN to itself, the usual way to stop a 4004, or HLT.
+    Port(Port, u8),
+    Watchpoint(Watch, u8, u8),                                      // Old and new value.
+    Undefined(u16, u8),                                             // Trapped on an undefined opcode, see `UndefinedPolicy`.
+    Strict(u16),                                                    // A strict mode check failed in halt mode.
+    Uninitialized(u16),                                             // The sanitizer caught an undefined read in halt mode.
+}
+
+impl ExitReason {
+    /// The run was cut short by a budget instead of reaching one of its stop conditions.
+    pub fn is_limit(&self) -> bool {
+        matches!(self, ExitReason::CycleLimit | ExitReason::InstructionLimit)
+    }
+}
+
+impl fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExitReason::CycleLimit => write!(f, "cycle limit"),
+            ExitReason::InstructionLimit => write!(f, "instruction limit"),
+            ExitReason::Address(addr) => write!(f, "reached {:03X}", addr),
+            ExitReason::Halt(addr) => write!(f, "halted at {:03X}", addr),
+            ExitReason::Port(port, value) => write!(f, "{} = {:X}", port, value),
+            ExitReason::Watchpoint(watch, old, new) => write!(f, "{} changed {:X} -> {:X}", watch, old, new),
+            ExitReason::Undefined(addr, op_code) => write!(f, "undefined opcode {:02X} at {:03X}", op_code, addr),
+            ExitReason::Strict(addr) => write!(f, "strict mode check failed at {:03X}", addr),
+            ExitReason::Uninitialized(addr) => write!(f, "uninitialized read at {:03X}", addr),
+        }
+    }
+}
+
+/// Stop conditions for `run`. Without any of them a run never returns.
+#[derive(Debug, Clone, Default)]
+pub struct RunLimits {
+    max_cycles: Option<u64>,
+    max_instructions: Option<u64>,
+    stop_addresses: BTreeSet<u16>,
+    halt_on_self_jump: bool,
+    ports: Vec<(Port, u8)>,
+    watches: Vec<Watch>,
+}
+
+impl RunLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_cycles(&mut self, cycles: u64) {
+        self.max_cycles = Some(cycles);
+    }
+
+    pub fn max_instructions(&mut self, instructions: u64) {
+        self.max_instructions = Some(instructions);
+    }
+
+    /// Stop when the PC reaches `addr`, the instruction there is not executed.
+    pub fn stop_at(&mut self, addr: u16) {
+        self.stop_addresses.insert(addr);
+    }
+
+    pub fn halt_on_self_jump(&mut self, halt: bool) {
+        self.halt_on_self_jump = halt;
+    }
+
+    /// Stop when an output port holds `value`.
+    pub fn stop_on_port(&mut self, port: Port, value: u8) {
+        self.ports.push((port, value));
+    }
+
+    /// Stop when the watched value changes.
+    pub fn watch(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+}
+
+/// Registers of the CPU when a run stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub model: Model,
+    pub pc: u16,
+    pub acc: u8,
+    pub carry: bool,
+    pub index: [u8; 16],
+    pub stack: Vec<u16>,                                            // 3 levels on the 4004, 7 on the 4040.
+    pub ram_addrs: u8,
+    pub command_control: u8,
+    pub register_bank: usize,                                       // 4040 only, with the other bank and the ROM bank.
+    pub other_bank: [u8; 8],
+    pub rom_bank: usize,
+    pub interrupts_enabled: bool,
+    pub interrupt_ack: bool,
+}
+
+impl CpuState {
+    pub fn capture(cpu: &Intel4004) -> Self {
+        CpuState {
+            model: cpu.get_model(),
+            pc: cpu.get_pc(),
+            acc: cpu.get_acc(),
+            carry: cpu.get_carry(),
+            index: cpu.get_index().map(|r| r.value()),
+            stack: cpu.get_stack().to_vec(),
+            ram_addrs: cpu.get_ram_addrs(),
+            command_control: cpu.get_cc(),
+            register_bank: cpu.get_register_bank(),
+            other_bank: cpu.get_other_bank().map(|r| r.value()),
+            rom_bank: cpu.get_rom_bank(),
+            interrupts_enabled: cpu.get_interrupts_enabled(),
+            interrupt_ack: cpu.get_interrupt_ack(),
+        }
+    }
+}
+
+impl fmt::Display for CpuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let index: Vec<String> = self.index.iter().map(|r| format!("{:X}", r)).collect();
+
+        writeln!(f, "PC={:03X} ACC={:X} CY={} SRC={:02X} DCL={:X}", self.pc, self.acc, self.carry as u8, self.ram_addrs,
+            self.command_control)?;
+        writeln!(f, "R0-R15={}", index.join(" "))?;
+        if self.model == Model::I4040 {
+            let other: Vec<String> = self.other_bank.iter().map(|r| format!("{:X}", r)).collect();
+            writeln!(f, "SB={} DB={} EI={} INTA={} R0-R7 of bank {}={}", self.register_bank, self.rom_bank,
+                self.interrupts_enabled as u8, self.interrupt_ack as u8, 1 - self.register_bank, other.join(" "))?;
+        }
+        let stack: Vec<String> = self.stack.iter().map(|addr| format!("{:03X}", addr)).collect();
+        write!(f, "STACK={}", stack.join(" "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunResult {
+    pub reason: ExitReason,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub undefined: u64,                                             // Undefined opcodes executed during the run.
+    pub state: CpuState,
+}
+
+impl RunResult {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("stopped: {}\ninstructions: {}\ncycles: {}\n", self.reason, self.instructions, self.cycles);
+        if self.undefined > 0 {
+            text.push_str(&format!("undefined opcodes: {}\n", self.undefined));
+        }
+        text.push_str(&format!("{}\n", self.state));
+        text
+    }
+}
+
+fn port_value(cpu: &Intel4004, port: Port) -> u8 {
+    match port {
+        Port::Rom(chip) => cpu.rom[chip].io.value(),
+        Port::Ram(chip) => cpu.ram[chip].output,
+    }
+}
+
+fn watch_value(cpu: &Intel4004, watch: Watch) -> u8 {
+    match watch {
+        Watch::Ram(i) => cpu.ram[i / 64].ram[i % 64],
+        Watch::Status(i) => cpu.ram[i / 16].status[i % 16],
+        Watch::Register(i) => cpu.get_index()[i].value(),
+    }
+}
+
+/// Execute without output or throttling until one of the stop conditions is met. Conditions are checked after every
+/// clock, so a run always clocks the CPU at least once. A clock that runs no instruction, because the CPU is halted or
+/// held by STOP, counts as one cycle and no instruction, so only the cycle limit ends a run that never resumes.
+pub fn run(cpu: &mut Intel4004, limits: &RunLimits) -> RunResult {
+    let mut watched: Vec<u8> = limits.watches.iter().map(|w| watch_value(cpu, *w)).collect();
+    let mut instructions = 0;
+    let mut cycles = 0;
+    let undefined = cpu.get_undefined_count();
+
+    let reason = loop {
+        let pc = cpu.get_pc();
+        let op_code = cpu.fetch_u8(pc);
+        let instruction = cpu.get_model().decode(op_code);
+
+        let executed = cpu.get_instruction_count();
+        cpu.clock();
+        if let Some(addr) = cpu.get_trap() {
+            break ExitReason::Undefined(addr, cpu.fetch_u8(addr));
+        }
+        if cpu.get_instruction_count() > executed {
+            instructions += 1;
+            cycles += instruction.size as u64;
+        } else {
+            cycles += 1;                                            // Idle, halted or stopped.
+        }
+
+        if cpu.strict().is_some_and(|strict| strict.is_halted()) {
+            break ExitReason::Strict(pc);
+        }
+        if cpu.sanitizer().is_some_and(|sanitizer| sanitizer.is_halted()) {
+            break ExitReason::Uninitialized(pc);
+        }
+        let next_pc = cpu.get_pc();
+        if limits.halt_on_self_jump && (instruction.op == Op::Jun && next_pc == pc || cpu.is_halted()) {
+            break ExitReason::Halt(pc);
+        }
+        if limits.stop_addresses.contains(&next_pc) {
+            break ExitReason::Address(next_pc);
+        }
+        if let Some((port, value)) = limits.ports.iter().find(|(p, v)| port_value(cpu, *p) == *v) {
+            break ExitReason::Port(*port, *value);
+        }
+        if let Some(i) = (0..watched.len()).find(|i| watch_value(cpu, limits.watches[*i]) != watched[*i]) {
+            let new = watch_value(cpu, limits.watches[i]);
+            let old = std::mem::replace(&mut watched[i], new);
+            break ExitReason::Watchpoint(limits.watches[i], old, new);
+        }
+        if limits.max_cycles.is_some_and(|max| cycles >= max) {
+            break ExitReason::CycleLimit;
+        }
+        if limits.max_instructions.is_some_and(|max| instructions >= max) {
+            break ExitReason::InstructionLimit;
+        }
+    };
+
+    let undefined = cpu.get_undefined_count() - undefined;
+    RunResult { reason, instructions, cycles, undefined, state: CpuState::capture(cpu) }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::disassembler::disassemble_cpu;
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_BANKS, ROM_CHIPS};
+use super::opcodes::Op;
+use super::strict::{Diagnostic, StrictMode};
+
+// Uninitialized read sanitizer
+
+/// Storage the sanitizer tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Storage {
+    Register(usize),                                                // R0-R15 of the selected bank.
+    Ram(usize),                                                     // Main memory character, chip * 64 + character.
+    Status(usize),                                                  // Status character, chip * 16 + character.
+    RamPort(usize),                                                 // Output latch of a 4002, written by WMP.
+    RomPort(usize),                                                 // I/O latch of a 4001, written by WRR.
+}
+
+impl fmt::Display for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Storage::Register(r) => write!(f, "R{}", r),
+            Storage::Ram(i) => write!(f, "RAM {} character {}", i / 64, i % 64),
+            Storage::Status(i) => write!(f, "RAM {} status character {}", i / 16, i % 16),
+            Storage::RamPort(chip) => write!(f, "RAM {} port", chip),
+            Storage::RomPort(chip) => write!(f, "ROM {} port", chip),
+        }
+    }
+}
+
+/// Shadow "defined" bits for the index registers, RAM and port latches, see `Intel4004::enable_sanitizer`. Everything
+/// starts undefined, writes by instructions define it. RDR is checked only when no peripheral drives the lines and the
+/// CPU reads the 4001 latch back, and not at all on the bus.
+pub struct Sanitizer {
+    mode: StrictMode,
+    registers: [bool; 24],                                          // R0-R7 of bank 0, R8-R15, R0-R7 of bank 1.
+    ram: Vec<bool>,
+    status: Vec<bool>,
+    ram_ports: [bool; RAM_CHIPS],
+    rom_ports: [bool; ROM_CHIPS * ROM_BANKS],
+    diagnostics: Vec<Diagnostic>,
+    reported: HashSet<(u16, Storage)>,                              // Loops report every read once.
+    halted: bool,
+}
+
+impl Sanitizer {
+    pub fn new(mode: StrictMode) -> Self {
+        Sanitizer {
+            mode,
+            registers: [false; 24],
+            ram: vec![false; RAM_CHIPS * 64],
+            status: vec![false; RAM_CHIPS * 16],
+            ram_ports: [false; RAM_CHIPS],
+            rom_ports: [false; ROM_CHIPS * ROM_BANKS],
+            diagnostics: Vec::new(),
+            reported: HashSet::new(),
+            halted: false,
+        }
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// An undefined read in halt mode, the CPU does not execute until `resume`.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    /// Storage was written since the sanitizer was enabled. Registers are looked up in the bank selected on `cpu`.
+    pub fn is_defined(&self, cpu: &Intel4004, storage: Storage) -> bool {
+        *self.shadow(cpu, storage)
+    }
+
+    /// Mark storage written by something other than an instruction, like a test or a debugger.
+    pub fn define(&mut self, cpu: &Intel4004, storage: Storage) {
+        *self.shadow_mut(cpu, storage) = true;
+    }
+
+    /// Every storage holds a known value, after RESET.
+    pub fn define_all(&mut self) {
+        self.set_all(true);
+    }
+
+    /// Every storage is undefined again, after power-on.
+    pub fn undefine_all(&mut self) {
+        self.set_all(false);
+    }
+
+    fn set_all(&mut self, defined: bool) {
+        self.registers.fill(defined);
+        self.ram.fill(defined);
+        self.status.fill(defined);
+        self.ram_ports.fill(defined);
+        self.rom_ports.fill(defined);
+    }
+
+    fn shadow(&self, cpu: &Intel4004, storage: Storage) -> &bool {
+        match storage {
+            Storage::Register(r) if r < 8 && cpu.get_register_bank() == 1 => &self.registers[16 + r],
+            Storage::Register(r) => &self.registers[r],
+            Storage::Ram(i) => &self.ram[i],
+            Storage::Status(i) => &self.status[i],
+            Storage::RamPort(chip) => &self.ram_ports[chip],
+            Storage::RomPort(chip) => &self.rom_ports[chip],
+        }
+    }
+
+    fn shadow_mut(&mut self, cpu: &Intel4004, storage: Storage) -> &mut bool {
+        match storage {
+            Storage::Register(r) if r < 8 && cpu.get_register_bank() == 1 => &mut self.registers[16 + r],
+            Storage::Register(r) => &mut self.registers[r],
+            Storage::Ram(i) => &mut self.ram[i],
+            Storage::Status(i) => &mut self.status[i],
+            Storage::RamPort(chip) => &mut self.ram_ports[chip],
+            Storage::RomPort(chip) => &mut self.rom_ports[chip],
+        }
+    }
+
+    /// Check what the instruction at `pc` reads and record what it writes, before it runs on `cpu`.
+    pub fn check(&mut self, cpu: &Intel4004, pc: u16, op_code: u8) {
+        let instruction = cpu.get_model().decode(op_code);
+        let register = Storage::Register(instruction.opa as usize);
+        let pair = (instruction.opa & 0x0E) as usize;
+        let ram = cpu.selected_ram_chip();
+        let character = Storage::Ram(ram * 64 + (cpu.get_ram_addrs() & 0x3F) as usize);
+        let status = |n: usize| Storage::Status(ram * 16 + ((cpu.get_ram_addrs() >> 4) & 0x03) as usize * 4 + n);
+
+        let reads = match instruction.op {
+            Op::Ld | Op::Add | Op::Sub | Op::Xch | Op::Inc | Op::Isz => [Some(register), None],
+            Op::Or4 | Op::Or5 | Op::An6 | Op::An7 => [Some(register), None],
+            Op::Src | Op::Jin => [Some(Storage::Register(pair)), Some(Storage::Register(pair + 1))],
+            Op::Fin => [Some(Storage::Register(0)), Some(Storage::Register(1))],    // The address is in P0.
+            Op::Rdm | Op::Adm | Op::Sbm => [Some(character), None],
+            Op::Rd0 | Op::Rd1 | Op::Rd2 | Op::Rd3 => [Some(status(instruction.opa as usize & 0x03)), None],
+            _ => [None, None],
+        };
+        let ram_populated = ram < cpu.get_ram_chips();
+        for storage in reads.into_iter().flatten().filter(|s| matches!(s, Storage::Register(_)) || ram_populated) {
+            self.read(cpu, pc, storage);
+        }
+
+        match instruction.op {
+            Op::Xch | Op::Inc | Op::Isz => self.define(cpu, register),
+            Op::Fim | Op::Fin => {
+                self.define(cpu, Storage::Register(pair));
+                self.define(cpu, Storage::Register(pair + 1));
+            }
+            Op::Wrm if ram_populated => self.define(cpu, character),
+            Op::Wr0 | Op::Wr1 | Op::Wr2 | Op::Wr3 if ram_populated => {
+                self.define(cpu, status(instruction.opa as usize & 0x03));
+            }
+            Op::Wmp if ram_populated => self.define(cpu, Storage::RamPort(ram)),
+            Op::Wrr if cpu.selected_rom_port() < cpu.get_rom_chips() => {
+                self.define(cpu, Storage::RomPort(cpu.selected_rom_port()));
+            }
+            _ => {}
+        }
+    }
+
+    /// The RDR at `pc` found no peripheral driving the lines and reads the latch of the selected 4001 back.
+    pub fn check_port_read(&mut self, cpu: &Intel4004, pc: u16) {
+        if cpu.selected_rom_port() < cpu.get_rom_chips() {
+            self.read(cpu, pc, Storage::RomPort(cpu.selected_rom_port()));
+        }
+    }
+
+    fn read(&mut self, cpu: &Intel4004, pc: u16, storage: Storage) {
+        if !self.is_defined(cpu, storage) && self.reported.insert((pc, storage)) {
+            let (text, _) = disassemble_cpu(cpu, pc, None);
+            let message = format!("reads {} before it was written", storage);
+            self.diagnostics.push(Diagnostic { pc, instruction: text, message });
+            self.halted = self.mode == StrictMode::Halt;
+        }
+    }
+}
@@ -0,0 +1,290 @@
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use arbitrary_int::u4;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map};
+
+use super::assembler::assemble;
+use super::intel4004::{Intel4004, RAM_CHIPS, ROM_CHIPS};
+use super::peripherals::profile;
+use super::runner::{run, Port, RunLimits, Watch};
+use super::symbols::SymbolTable;
+
+// Rhai scripting
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+const DEFAULT_CYCLES: u64 = 10_000_000;                             // Budget of `run` when the script sets none.
+
+struct State {
+    cpu: Intel4004,
+    symbols: SymbolTable,                                           // Labels of the last `assemble`.
+}
+
+/// A machine as seen by scripts. Copies share the same CPU.
+#[derive(Clone)]
+struct Machine(Rc<RefCell<State>>);
+
+/// Runs Rhai scripts that build machines, drive their pins and ports, and check the results:
+///
+/// - `machine()`, `machine(rom_chips, ram_chips)`
+/// - `m.load(path)`, `m.assemble(source)`, `m.address(label)`, `m.attach(profile)`
+/// - properties `pc`, `acc`, `carry`, `test`, `src`, `dcl` and the read only `stack`
+/// - `m.reg(r)`, `m.set_reg(r, v)`, `m.pair(p)`, `m.set_pair(p, v)`
+/// - `m.ram(chip, i)`, `m.set_ram(chip, i, v)`, `m.status(chip, i)`, `m.set_status(chip, i, v)`, `m.output(chip)`
+/// - `m.rom_port(chip)`, `m.set_rom_port(chip, v)`, `m.press(chip, key, instructions)`
+/// - `m.step()`, `m.step(n)`, `m.run(#{ cycles, instructions, stop_at, halt, port, watch })`
+/// - `assert(condition)`, `assert(condition, message)`, `assert_eq(a, b)`, `assert_eq(a, b, message)`
+pub struct Script {
+    engine: Engine,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        let mut engine = Engine::new();
+        engine.register_type_with_name::<Machine>("Machine");
+
+        register_machine(&mut engine);
+        register_registers(&mut engine);
+        register_memory(&mut engine);
+        register_execution(&mut engine);
+        register_asserts(&mut engine);
+
+        Script { engine }
+    }
+
+    /// Run a script, errors carry the line and column of the failing statement.
+    pub fn run(&self, source: &str) -> Result<(), String> {
+        self.engine.run(source).map_err(|e| e.to_string())
+    }
+
+    pub fn run_file(&self, path: &Path) -> Result<(), String> {
+        self.engine.run_file(path.to_path_buf()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn index(value: i64, count: usize, what: &str) -> ScriptResult<usize> {
+    match usize::try_from(value) {
+        Ok(i) if i < count => Ok(i),
+        _ => Err(format!("{} {} out of range 0-{}", what, value, count - 1).into()),
+    }
+}
+
+fn nibble(value: i64) -> ScriptResult<u8> {
+    match value {
+        0..=15 => Ok(value as u8),
+        _ => Err(format!("{} does not fit in 4 bits", value).into()),
+    }
+}
+
+// --- Functions ---
+
+fn register_machine(engine: &mut Engine) {
+    let new = |rom_chips: usize, ram_chips: usize| {
+        Machine(Rc::new(RefCell::new(State { cpu: Intel4004::with_chips(rom_chips, ram_chips), symbols: SymbolTable::new() })))
+    };
+    engine.register_fn("machine", move || new(ROM_CHIPS, RAM_CHIPS));
+    engine.register_fn("machine", move |rom_chips: i64, ram_chips: i64| -> ScriptResult<Machine> {
+        Ok(new(index(rom_chips - 1, ROM_CHIPS, "ROM chips")? + 1, index(ram_chips - 1, RAM_CHIPS, "RAM chips")? + 1))
+    });
+
+    engine.register_fn("load", |m: &mut Machine, path: &str| -> ScriptResult<()> {
+        m.0.borrow_mut().cpu.load_rom(path).map_err(|e| format!("{}: {}", path, e).into())
+    });
+    engine.register_fn("assemble", |m: &mut Machine, source: &str| -> ScriptResult<()> {
+        let asm = assemble(source)
+            .map_err(|errors| errors.iter().map(|e| e.to_string()).collect::<Vec<String>>().join("\n"))?;
+        let mut state = m.0.borrow_mut();
+        state.cpu.load_bytes(&asm.image);
+        state.symbols = asm.symbols();
+        Ok(())
+    });
+    engine.register_fn("address", |m: &mut Machine, label: &str| -> ScriptResult<i64> {
+        m.0.borrow().symbols.resolve(label).map(i64::from).ok_or_else(|| format!("unknown address '{}'", label).into())
+    });
+    engine.register_fn("attach", |m: &mut Machine, name: &str| -> ScriptResult<()> {
+        let peripheral = profile(name).ok_or_else(|| format!("unknown profile '{}'", name))?;
+        m.0.borrow_mut().cpu.attach_peripheral(peripheral);
+        Ok(())
+    });
+}
+
+fn register_registers(engine: &mut Engine) {
+    engine.register_get_set("pc",
+        |m: &mut Machine| m.0.borrow().cpu.get_pc() as i64,
+        |m: &mut Machine, pc: i64| m.0.borrow_mut().cpu.set_pc(pc as u16 & 0xFFF));
+    engine.register_get_set("acc",
+        |m: &mut Machine| m.0.borrow().cpu.get_acc() as i64,
+        |m: &mut Machine, acc: i64| -> ScriptResult<()> {
+            m.0.borrow_mut().cpu.set_acc(nibble(acc)?);
+            Ok(())
+        });
+    engine.register_get_set("carry",
+        |m: &mut Machine| m.0.borrow().cpu.get_carry(),
+        |m: &mut Machine, carry: bool| m.0.borrow_mut().cpu.set_carry(carry));
+    engine.register_get_set("test",
+        |m: &mut Machine| m.0.borrow().cpu.get_test(),
+        |m: &mut Machine, test: bool| m.0.borrow_mut().cpu.set_test(test));
+    engine.register_get_set("src",
+        |m: &mut Machine| m.0.borrow().cpu.get_ram_addrs() as i64,
+        |m: &mut Machine, src: i64| m.0.borrow_mut().cpu.set_ram_addrs(src as u8));
+    engine.register_get_set("dcl",
+        |m: &mut Machine| m.0.borrow().cpu.get_cc() as i64,
+        |m: &mut Machine, dcl: i64| -> ScriptResult<()> {
+            m.0.borrow_mut().cpu.set_cc(nibble(dcl)? & 0x7);
+            Ok(())
+        });
+    engine.register_get("stack", |m: &mut Machine| -> Array {
+        m.0.borrow().cpu.get_stack().iter().map(|addr| Dynamic::from(*addr as i64)).collect()
+    });
+
+    engine.register_fn("reg", |m: &mut Machine, r: i64| -> ScriptResult<i64> {
+        Ok(m.0.borrow().cpu.get_index()[index(r, 16, "register")?].value() as i64)
+    });
+    engine.register_fn("set_reg", |m: &mut Machine, r: i64, value: i64| -> ScriptResult<()> {
+        m.0.borrow_mut().cpu.set_index(index(r, 16, "register")?, u4::new(nibble(value)?));
+        Ok(())
+    });
+    engine.register_fn("pair", |m: &mut Machine, p: i64| -> ScriptResult<i64> {
+        let p = index(p, 8, "pair")?;
+        let state = m.0.borrow();
+        let index = state.cpu.get_index();
+        Ok((index[p * 2].value() as i64) << 4 | index[p * 2 + 1].value() as i64)
+    });
+    engine.register_fn("set_pair", |m: &mut Machine, p: i64, value: i64| -> ScriptResult<()> {
+        let p = index(p, 8, "pair")?;
+        let value = index(value, 256, "pair value")? as u8;
+        let mut state = m.0.borrow_mut();
+        state.cpu.set_index(p * 2, u4::new(value >> 4));
+        state.cpu.set_index(p * 2 + 1, u4::new(value & 0xF));
+        Ok(())
+    });
+}
+
+fn register_memory(engine: &mut Engine) {
+    engine.register_fn("ram", |m: &mut Machine, chip: i64, i: i64| -> ScriptResult<i64> {
+        Ok(m.0.borrow().cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].ram[index(i, 64, "character")?] as i64)
+    });
+    engine.register_fn("set_ram", |m: &mut Machine, chip: i64, i: i64, value: i64| -> ScriptResult<()> {
+        m.0.borrow_mut().cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].ram[index(i, 64, "character")?] = nibble(value)?;
+        Ok(())
+    });
+    engine.register_fn("status", |m: &mut Machine, chip: i64, i: i64| -> ScriptResult<i64> {
+        Ok(m.0.borrow().cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].status[index(i, 16, "status character")?] as i64)
+    });
+    engine.register_fn("set_status", |m: &mut Machine, chip: i64, i: i64, value: i64| -> ScriptResult<()> {
+        let chip = index(chip, RAM_CHIPS, "RAM chip")?;
+        m.0.borrow_mut().cpu.ram[chip].status[index(i, 16, "status character")?] = nibble(value)?;
+        Ok(())
+    });
+    engine.register_fn("output", |m: &mut Machine, chip: i64| -> ScriptResult<i64> {
+        Ok(m.0.borrow().cpu.ram[index(chip, RAM_CHIPS, "RAM chip")?].output as i64)
+    });
+
+    engine.register_fn("rom_port", |m: &mut Machine, chip: i64| -> ScriptResult<i64> {
+        Ok(m.0.borrow().cpu.rom[index(chip, ROM_CHIPS, "ROM chip")?].io.value() as i64)
+    });
+    engine.register_fn("set_rom_port", |m: &mut Machine, chip: i64, value: i64| -> ScriptResult<()> {
+        m.0.borrow_mut().cpu.rom[index(chip, ROM_CHIPS, "ROM chip")?].io = u4::new(nibble(value)?);
+        Ok(())
+    });
+}
+
+fn register_execution(engine: &mut Engine) {
+    engine.register_fn("step", |m: &mut Machine| m.0.borrow_mut().cpu.clock());
+    engine.register_fn("step", |m: &mut Machine, n: i64| {
+        let mut state = m.0.borrow_mut();
+        (0..n).for_each(|_| state.cpu.clock());
+    });
+
+    // Hold a key on a ROM input port for a number of instructions, then release it.
+    engine.register_fn("press", |m: &mut Machine, chip: i64, key: i64, instructions: i64| -> ScriptResult<()> {
+        let chip = index(chip, ROM_CHIPS, "ROM chip")?;
+        let mut state = m.0.borrow_mut();
+        state.cpu.rom[chip].io = u4::new(nibble(key)?);
+        (0..instructions).for_each(|_| state.cpu.clock());
+        state.cpu.rom[chip].io = u4::new(0);
+        Ok(())
+    });
+
+    engine.register_fn("run", |m: &mut Machine, options: Map| -> ScriptResult<Map> {
+        let mut state = m.0.borrow_mut();
+        let limits = run_limits(&options, &state.symbols)?;
+        let result = run(&mut state.cpu, &limits);
+
+        let mut map = Map::new();
+        map.insert("reason".into(), result.reason.to_string().into());
+        map.insert("limit".into(), result.reason.is_limit().into());
+        map.insert("instructions".into(), (result.instructions as i64).into());
+        map.insert("cycles".into(), (result.cycles as i64).into());
+        Ok(map)
+    });
+}
+
+/// Options of `run`, a JUN to itself stops unless `halt` is false.
+fn run_limits(options: &Map, symbols: &SymbolTable) -> ScriptResult<RunLimits> {
+    let mut limits = RunLimits::new();
+    let option = |name: &str| options.get(name).cloned();
+
+    limits.halt_on_self_jump(option("halt").map_or(Ok(true), |v| v.as_bool())?);
+    let cycles = option("cycles").map(|v| v.as_int()).transpose()?;
+    let instructions = option("instructions").map(|v| v.as_int()).transpose()?;
+    match (cycles, instructions) {
+        (None, None) => limits.max_cycles(DEFAULT_CYCLES),
+        (cycles, instructions) => {
+            cycles.into_iter().for_each(|c| limits.max_cycles(c as u64));
+            instructions.into_iter().for_each(|i| limits.max_instructions(i as u64));
+        }
+    }
+
+    for value in values(option("stop_at")) {
+        let addr = match value.as_int() {
+            Ok(addr) => Some(addr as u16 & 0xFFF),
+            Err(_) => symbols.resolve(&value.to_string()),
+        };
+        limits.stop_at(addr.ok_or_else(|| format!("unknown address '{}'", value))?);
+    }
+    for value in values(option("port")) {
+        let (port, v) = Port::parse_condition(&value.to_string()).ok_or_else(|| format!("bad port '{}'", value))?;
+        limits.stop_on_port(port, v);
+    }
+    for value in values(option("watch")) {
+        limits.watch(Watch::parse(&value.to_string()).ok_or_else(|| format!("bad watch '{}'", value))?);
+    }
+    Ok(limits)
+}
+
+/// A single option value or an array of them.
+fn values(option: Option<Dynamic>) -> Vec<Dynamic> {
+    match option {
+        Some(value) if value.is_array() => value.cast::<Array>(),
+        Some(value) => vec![value],
+        None => Vec::new(),
+    }
+}
+
+fn register_asserts(engine: &mut Engine) {
+    engine.register_fn("assert", |condition: bool| -> ScriptResult<()> {
+        if condition { Ok(()) } else { Err("assertion failed".into()) }
+    });
+    engine.register_fn("assert", |condition: bool, message: &str| -> ScriptResult<()> {
+        if condition { Ok(()) } else { Err(format!("assertion failed: {}", message).into()) }
+    });
+    engine.register_fn("assert_eq", |left: Dynamic, right: Dynamic| assert_eq(left, right, ""));
+    engine.register_fn("assert_eq", |left: Dynamic, right: Dynamic, message: &str| assert_eq(left, right, message));
+}
+
+fn assert_eq(left: Dynamic, right: Dynamic, message: &str) -> ScriptResult<()> {
+    if left.type_name() == right.type_name() && left.to_string() == right.to_string() {
+        return Ok(());
+    }
+    let message = if message.is_empty() { String::new() } else { format!(": {}", message) };
+    Err(format!("assertion failed{}\n  left: {}\n right: {}", message, left, right).into())
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+
+use super::disassembler::{disassemble_for, selected_bank};
+use super::intel4004::Intel4004;
+use super::opcodes::Op;
+
+// Strict mode
+
+/// What happens when a check fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StrictMode {
+    Log,                                                            // Record the diagnostic and carry on.
+    Halt,                                                           // Also stop after the instruction until `resume`.
+}
+
+impl StrictMode {
+    /// Parse "log" or "halt".
+    pub fn parse(text: &str) -> Option<StrictMode> {
+        match text {
+            "log" => Some(StrictMode::Log),
+            "halt" => Some(StrictMode::Halt),
+            _ => None,
+        }
+    }
+}
+
+/// An instruction the real hardware would not run as the emulator does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub pc: u16,
+    pub instruction: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:03X}  {:<16} {}", self.pc, self.instruction, self.message)
+    }
+}
+
+/// Checks every instruction before `Intel4004::clock` runs it, see `Intel4004::enable_strict`.
+pub struct Strict {
+    mode: StrictMode,
+    diagnostics: Vec<Diagnostic>,
+    src_seen: bool,
+    depth: usize,                                                   // JMS nesting, the stack only holds so many.
+    halted: bool,
+}
+
+impl Strict {
+    pub fn new(mode: StrictMode) -> Self {
+        Strict {
+            mode,
+            diagnostics: Vec::new(),
+            src_seen: false,
+            depth: 0,
+            halted: false,
+        }
+    }
+
+    pub fn mode(&self) -> StrictMode {
+        self.mode
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// A check failed in halt mode, the CPU does not execute until `resume`.
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn resume(&mut self) {
+        self.halted = false;
+    }
+
+    /// RESET cleared SRC and the stack, the diagnostics are kept.
+    pub fn reset(&mut self) {
+        self.src_seen = false;
+        self.depth = 0;
+    }
+
+    /// Check the instruction at `pc` against the state of `cpu` before it runs.
+    pub fn check(&mut self, cpu: &Intel4004, pc: u16, op_code: u8) {
+        let op = cpu.get_model().decode(op_code).op;
+        let mut problems = Vec::new();
+
+        let chip = (pc >> 8) as usize + cpu.get_rom_bank() * 16;
+        if chip >= cpu.get_rom_chips() {
+            problems.push(format!("executing from ROM chip {} which is not populated", chip));
+        }
+
+        let ram_op = matches!(op, Op::Wrm | Op::Wmp | Op::Wr0 | Op::Wr1 | Op::Wr2 | Op::Wr3 | Op::Sbm | Op::Rdm
+            | Op::Adm | Op::Rd0 | Op::Rd1 | Op::Rd2 | Op::Rd3);
+        let port_op = matches!(op, Op::Wrr | Op::Rdr);
+
+        if (ram_op || port_op) && !self.src_seen {
+            problems.push("RAM or port access before any SRC selected a chip".to_string());
+        }
+        if ram_op && cpu.selected_ram().is_none() {
+            problems.push(format!("RAM chip {} is not populated", cpu.selected_ram_chip()));
+        }
+        if port_op && cpu.selected_rom_port() >= cpu.get_rom_chips() {
+            problems.push(format!("ROM chip {} is not populated", cpu.selected_rom_port()));
+        }
+        if op == Op::Wrr {
+            let inputs = cpu.rom.get(cpu.selected_rom_port()).map_or(0, |chip| chip.inputs);
+            if inputs != 0 {
+                problems.push(format!("ROM chip {} lines {:04b} are configured as inputs", cpu.selected_rom_port(), inputs));
+            }
+        }
+        if matches!(op, Op::Wpm | Op::Rpm) {
+            problems.push("no 4008/4009 or 4289 program memory is connected".to_string());
+        }
+        if matches!(op, Op::Fin | Op::Jin) && pc & 0xFF == 0xFF {
+            problems.push(format!("{} at the end of page {:X} uses page {:X}", op.mnemonic(), pc >> 8, ((pc >> 8) + 1) & 0xF));
+        }
+
+        match op {
+            Op::Src => self.src_seen = true,
+            Op::Jms => {
+                self.depth += 1;
+                if self.depth > cpu.get_stack().len() {
+                    problems.push(format!("JMS nests {} deep, the stack has {} levels and loses a return address",
+                        self.depth, cpu.get_stack().len()));
+                }
+            }
+            Op::Bbl | Op::Bbs => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+
+        if problems.is_empty() {
+            return;
+        }
+
+        let rom = cpu.rom_image();
+        let (instruction, _) = disassemble_for(cpu.get_model(), selected_bank(cpu, &rom), pc, None);
+        for message in problems {
+            self.diagnostics.push(Diagnostic { pc, instruction: instruction.clone(), message });
+        }
+        self.halted = self.mode == StrictMode::Halt;
+    }
+}
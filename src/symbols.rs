@@ -0,0 +1,127 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io,
+};
+
+// Symbol table
+
+/// Labels and their 12-bit addresses, loaded from the symbol file written by the assembler.
+///
+/// The file has one symbol per line, the address in hex followed by the name: `0A3 loop`. Text after ';' is ignored.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    by_name: HashMap<String, u16>,
+    by_addr: BTreeMap<u16, String>,                                 // First label defined at each address.
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable {
+            by_name: HashMap::new(),
+            by_addr: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: &str, addr: u16) {
+        let addr = addr & 0xFFF;
+
+        self.by_name.insert(name.to_string(), addr);
+        self.by_addr.entry(addr).or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    /// Iterate over all symbols sorted by address.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        let mut symbols: Vec<(&str, u16)> = self.by_name.iter().map(|(name, addr)| (name.as_str(), *addr)).collect();
+        symbols.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        symbols.into_iter()
+    }
+
+    /// Address as `label`, `label+offset` from the closest label in the same page, or hex. The result is also a
+    /// valid assembler expression.
+    pub fn format_address(&self, addr: u16) -> String {
+        match self.by_addr.range(addr & 0xF00..=addr).next_back() {
+            Some((base, name)) if *base == addr => name.clone(),
+            Some((base, name)) if addr - base < 10 => format!("{}+{}", name, addr - base),
+            Some((base, name)) => format!("{}+0x{:X}", name, addr - base),
+            None => format!("0x{:03X}", addr),
+        }
+    }
+
+    /// Parse an address typed by the user: a label, `label+offset`, `0x` prefixed or plain hex. Offsets are decimal
+    /// unless prefixed with `0x`, like in the assembler.
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        let text = text.trim();
+
+        let (base, offset) = match text.split_once('+') {
+            Some((base, offset)) => {
+                let offset = offset.trim();
+                let offset = match offset.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok()?,
+                    None => offset.parse().ok()?,
+                };
+                (base.trim(), offset)
+            }
+            None => (text, 0),
+        };
+
+        let addr = self.address_of(base).or_else(|| parse_hex(base))?;
+        Some(addr.checked_add(offset)? & 0xFFF)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut table = SymbolTable::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let addr = fields.next().and_then(parse_hex);
+            let name = fields.next();
+
+            match (addr, name, fields.next()) {
+                (Some(addr), Some(name), None) if addr <= 0xFFF => table.insert(name, addr),
+                _ => return Err(format!("line {}: expected '<address> <name>'", i + 1)),
+            }
+        }
+
+        Ok(table)
+    }
+
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(filename)?;
+        Self::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn to_text(&self) -> String {
+        self.iter().map(|(name, addr)| format!("{:03X} {}\n", addr, name)).collect()
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        fs::write(filename, self.to_text())
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u16> {
+    let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
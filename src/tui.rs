@@ -0,0 +1,318 @@
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Position, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::Frame;
+
+use super::debugger::Debugger;
+use super::disassembler::{discover_for, selected_bank, ByteKind};
+use super::intel4004::Intel4004;
+use super::opcodes::Model;
+use super::runner::CpuState;
+
+// Full-screen debugger
+
+const OUTPUT_LINES: usize = 4;                                      // Command output kept below the panes.
+
+/// Everything shown in the panes, kept from before the last command to highlight what it changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    cpu: CpuState,
+    ram: Vec<[u8; 64]>,
+    status: Vec<[u8; 16]>,
+    outputs: Vec<u8>,
+    ports: Vec<u8>,
+}
+
+impl Snapshot {
+    fn capture(cpu: &Intel4004) -> Self {
+        let ram = &cpu.ram[..cpu.get_ram_chips()];
+
+        Snapshot {
+            cpu: CpuState::capture(cpu),
+            ram: ram.iter().map(|chip| chip.ram).collect(),
+            status: ram.iter().map(|chip| chip.status).collect(),
+            outputs: ram.iter().map(|chip| chip.output).collect(),
+            ports: cpu.rom[..cpu.get_rom_chips()].iter().map(|chip| chip.io.value()).collect(),
+        }
+    }
+}
+
+/// Instruction starts in the selected ROM bank.
+fn discover_code(cpu: &Intel4004, entries: &[u16]) -> Vec<ByteKind> {
+    let rom = cpu.rom_image();
+    discover_for(cpu.get_model(), selected_bank(cpu, &rom), entries, None).kinds
+}
+
+pub struct Tui {
+    pub debugger: Debugger,
+    code: Vec<ByteKind>,                                            // Instruction starts for the disassembly pane.
+    entries: Vec<u16>,
+    previous: Snapshot,
+    input: String,
+    output: Vec<String>,
+    bank: usize,                                                    // RAM bank shown, Tab cycles.
+    quit: bool,
+}
+
+impl Tui {
+    pub fn new(debugger: Debugger) -> Self {
+        let mut entries = vec![0x000];
+        entries.extend(debugger.symbols().iter().map(|(_, addr)| addr));
+        let code = discover_code(&debugger.cpu, &entries);
+        let previous = Snapshot::capture(&debugger.cpu);
+        let bank = debugger.cpu.ram_bank().min(banks(&debugger.cpu) - 1);
+
+        Tui {
+            debugger,
+            code,
+            entries,
+            previous,
+            input: String::new(),
+            output: vec!["type 'help' for commands, Enter on an empty line steps".to_string()],
+            bank,
+            quit: false,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.quit
+    }
+
+    pub fn get_bank(&self) -> usize {
+        self.bank
+    }
+
+    /// Run a debugger command, values it changes are highlighted until the next one. An empty line steps.
+    pub fn execute(&mut self, line: &str) {
+        let line = if line.trim().is_empty() { "s" } else { line };
+        if matches!(line.trim(), "t" | "trace") {
+            self.show("tracing would draw over the panes, use the debug command for traces");
+            return;
+        }
+
+        self.previous = Snapshot::capture(&self.debugger.cpu);
+        let rom_bank = self.debugger.cpu.get_rom_bank();
+        match self.debugger.execute(line) {
+            Some(output) => output.lines().for_each(|l| self.show(l)),
+            None => self.quit = true,
+        }
+
+        let pc = self.debugger.cpu.get_pc();
+        if self.code.get(pc as usize) != Some(&ByteKind::Code) || self.debugger.cpu.get_rom_bank() != rom_bank {
+            self.entries.push(pc);
+            self.code = discover_code(&self.debugger.cpu, &self.entries);
+        }
+        if self.debugger.cpu.get_ram_chips() > self.debugger.cpu.ram_bank() * 4 {
+            self.bank = self.debugger.cpu.ram_bank();
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                let line = std::mem::take(&mut self.input);
+                self.execute(&line);
+            }
+            KeyCode::Esc => self.input.clear(),
+            KeyCode::Tab => self.bank = (self.bank + 1) % banks(&self.debugger.cpu),
+            KeyCode::BackTab => self.bank = (self.bank + banks(&self.debugger.cpu) - 1) % banks(&self.debugger.cpu),
+            KeyCode::F(5) => self.execute("c"),
+            KeyCode::F(10) => self.execute("s"),
+            _ => {}
+        }
+    }
+
+    /// Take over the terminal until the user quits.
+    pub fn run(mut self) -> io::Result<()> {
+        let mut terminal = ratatui::init();
+
+        let result = (|| {
+            while !self.quit {
+                terminal.draw(|frame| self.draw(frame))?;
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+            Ok(())
+        })();
+
+        ratatui::restore();
+        result
+    }
+
+    fn show(&mut self, line: &str) {
+        self.output.push(line.to_string());
+        let excess = self.output.len().saturating_sub(OUTPUT_LINES);
+        self.output.drain(..excess);
+    }
+
+    // --- Panes ---
+
+    pub fn draw(&self, frame: &mut Frame) {
+        let [main, output, command] = Layout::vertical([
+            Constraint::Min(10),
+            Constraint::Length(OUTPUT_LINES as u16 + 2),
+            Constraint::Length(3),
+        ]).areas(frame.area());
+        let [code, state] = Layout::horizontal([Constraint::Length(40), Constraint::Min(0)]).areas(main);
+        let stack_rows = self.debugger.cpu.get_stack().len().max(4) as u16;
+        let [top, ram, io] = Layout::vertical([
+            Constraint::Length(stack_rows + 2),
+            Constraint::Min(0),
+            Constraint::Length(4),
+        ]).areas(state);
+        let [registers, stack] = Layout::horizontal([Constraint::Min(0), Constraint::Length(14)]).areas(top);
+
+        self.draw_code(frame, code);
+        self.draw_registers(frame, registers);
+        self.draw_stack(frame, stack);
+        self.draw_ram(frame, ram);
+        self.draw_io(frame, io);
+
+        let lines: Vec<Line> = self.output.iter().map(|l| Line::raw(l.as_str())).collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Output")), output);
+
+        frame.render_widget(Paragraph::new(format!("> {}", self.input)).block(Block::bordered().title("Command")), command);
+        frame.set_cursor_position(Position::new(command.x + 3 + self.input.len() as u16, command.y + 1));
+    }
+
+    /// A few instructions before the PC, then as many after it as fit.
+    fn draw_code(&self, frame: &mut Frame, area: Rect) {
+        let rows = area.height.saturating_sub(2) as usize;
+        let pc = self.debugger.cpu.get_pc();
+        let breakpoints: Vec<u16> = self.debugger.breakpoints().collect();
+
+        let mut addr = pc;
+        if self.code.get(pc as usize) == Some(&ByteKind::Code) {
+            let before = (0..pc).rev().filter(|a| self.code[*a as usize] == ByteKind::Code).take(rows / 3);
+            addr = before.last().unwrap_or(pc);
+        }
+
+        let mut lines = Vec::new();
+        while lines.len() < rows && addr <= 0xFFF {
+            let marker = if breakpoints.contains(&addr) { '*' } else { ' ' };
+            let text = format!("{}{}", marker, self.debugger.trace_line(addr));
+            lines.push(match addr == pc {
+                true => Line::styled(text, Style::new().add_modifier(Modifier::REVERSED)),
+                false => Line::raw(text),
+            });
+            let cpu = &self.debugger.cpu;
+            addr += cpu.get_model().decode(cpu.fetch_u8(addr)).size;
+        }
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Code")), area);
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let now = CpuState::capture(&self.debugger.cpu);
+        let old = &self.previous.cpu;
+
+        let mut lines = vec![
+            Line::from(vec![
+                Span::raw("ACC "), value(format!("{:X}", now.acc), now.acc != old.acc),
+                Span::raw("  CY "), value(format!("{}", now.carry as u8), now.carry != old.carry),
+                Span::raw("  PC "), value(format!("{:03X}", now.pc), now.pc != old.pc),
+            ]),
+            Line::from(vec![
+                Span::raw("SRC "), value(format!("{:02X}", now.ram_addrs), now.ram_addrs != old.ram_addrs),
+                Span::raw("  DCL "), value(format!("{:X}", now.command_control), now.command_control != old.command_control),
+            ]),
+        ];
+        if now.model == Model::I4040 {
+            lines[1].spans.extend([
+                Span::raw("  SB "), value(format!("{}", now.register_bank), now.register_bank != old.register_bank),
+                Span::raw("  DB "), value(format!("{}", now.rom_bank), now.rom_bank != old.rom_bank),
+            ]);
+        }
+        for row in 0..2 {
+            let mut spans = Vec::new();
+            for pair in row * 4..row * 4 + 4 {
+                spans.push(Span::raw(format!("P{} ", pair)));
+                for i in [pair * 2, pair * 2 + 1] {
+                    spans.push(value(format!("{:X}", now.index[i]), now.index[i] != old.index[i]));
+                }
+                spans.push(Span::raw("  "));
+            }
+            lines.push(Line::from(spans));
+        }
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Registers")), area);
+    }
+
+    fn draw_stack(&self, frame: &mut Frame, area: Rect) {
+        let now = self.debugger.cpu.get_stack();
+        let old = &self.previous.cpu.stack;
+
+        let lines: Vec<Line> = (0..now.len())
+            .map(|i| Line::from(vec![Span::raw(format!("{}: ", i)), value(format!("{:03X}", now[i]), now[i] != old[i])]))
+            .collect();
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("Stack")), area);
+    }
+
+    /// The four chips of the shown bank: a line per register with its characters and status characters. The
+    /// register selected by SRC is marked, the output ports are in the I/O pane.
+    fn draw_ram(&self, frame: &mut Frame, area: Rect) {
+        let cpu = &self.debugger.cpu;
+        let mut lines = Vec::new();
+
+        for chip in self.bank * 4..(self.bank * 4 + 4).min(cpu.get_ram_chips()) {
+            let ram = &cpu.ram[chip];
+
+            for register in 0..4 {
+                let selected = chip == cpu.selected_ram_chip() && register == ((cpu.get_ram_addrs() >> 4) & 3) as usize;
+                let name = if register == 0 { format!("chip {}", chip % 4) } else { String::new() };
+                let mut spans = vec![Span::raw(format!("{:<6} {}{}: ", name, if selected { '>' } else { ' ' }, register))];
+                for i in register * 16..register * 16 + 16 {
+                    spans.push(value(format!("{:X}", ram.ram[i]), ram.ram[i] != self.previous.ram[chip][i]));
+                }
+                spans.push(Span::raw(" "));
+                for i in register * 4..register * 4 + 4 {
+                    spans.push(value(format!("{:X}", ram.status[i]), ram.status[i] != self.previous.status[chip][i]));
+                }
+                lines.push(Line::from(spans));
+            }
+        }
+
+        let title = format!("RAM bank {} of {} (Tab)", self.bank, banks(cpu));
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), area);
+    }
+
+    fn draw_io(&self, frame: &mut Frame, area: Rect) {
+        let cpu = &self.debugger.cpu;
+
+        let mut rom = vec![Span::raw("ROM ")];
+        for (chip, old) in self.previous.ports.iter().enumerate() {
+            let now = cpu.rom[chip].io.value();
+            rom.push(value(format!("{:X}", now), now != *old));
+            rom.push(Span::raw(" "));
+        }
+        let mut ram = vec![Span::raw("RAM ")];
+        for (chip, old) in self.previous.outputs.iter().enumerate() {
+            ram.push(value(format!("{:X}", cpu.ram[chip].output), cpu.ram[chip].output != *old));
+            ram.push(Span::raw(" "));
+        }
+
+        let lines = vec![Line::from(rom), Line::from(ram)];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title("I/O ports")), area);
+    }
+}
+
+fn banks(cpu: &Intel4004) -> usize {
+    cpu.get_ram_chips().div_ceil(4)
+}
+
+fn value(text: String, changed: bool) -> Span<'static> {
+    match changed {
+        true => Span::styled(text, Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)),
+        false => Span::raw(text),
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use super::bus::{Phase, Signals, PHASES, PHASE_NS};
+use super::intel4004::Intel4004;
+
+// Value Change Dump
+
+struct Var {
+    name: String,
+    width: u8,
+    id: String,
+    value: Option<u64>,                                             // Last value written, None before the first.
+}
+
+/// Writes the PC, the clock phase, SYNC, the CM lines and D0-D3, the 4001 I/O lines, the 4002 output lines and the TEST
+/// pin as a VCD file for a waveform viewer, see `Intel4004::enable_vcd`. The time unit is 1 ns of emulated time, one
+/// clock phase lasts `PHASE_NS`. The bus lines are only recorded in bus-accurate mode, without it the phases are
+/// generated from the instruction cycles.
+pub struct Vcd {
+    out: Box<dyn Write>,
+    vars: Vec<Var>,
+    bus: bool,
+    rom_chips: usize,
+    time: u64,                                                      // Clock phases.
+    written: Option<u64>,                                           // Last timestamp written.
+    error: Option<io::Error>,
+}
+
+const PC: usize = 0;
+const PHASE: usize = 1;
+const SYNC: usize = 2;
+const TEST: usize = 3;
+const DATA: usize = 4;                                              // Then CM-ROM and CM-RAM, in bus-accurate mode.
+
+impl Vcd {
+    /// Write the header for the chips populated on `cpu` and its PC and ports at time 0. The bus lines are declared if
+    /// `cpu` has a bus enabled.
+    pub fn new(mut out: Box<dyn Write>, cpu: &Intel4004) -> io::Result<Self> {
+        let bus = cpu.bus().is_some();
+        let mut vars = vec![("pc", 12), ("phase", 3), ("sync", 1), ("test", 1)];
+        if bus {
+            vars.extend([("data", 4), ("cm_rom", 2), ("cm_ram", 4)]);
+        }
+        let mut vars: Vec<(String, u8)> = vars.into_iter().map(|(name, width)| (name.to_string(), width)).collect();
+        vars.extend((0..cpu.get_rom_chips()).map(|chip| (format!("rom{}_io", chip), 4)));
+        vars.extend((0..cpu.get_ram_chips()).map(|chip| (format!("ram{}_out", chip), 4)));
+
+        writeln!(out, "$version intel4004_emu $end")?;
+        writeln!(out, "$timescale 1ns $end")?;
+        writeln!(out, "$scope module mcs4 $end")?;
+        let vars: Vec<Var> = vars.into_iter().enumerate().map(|(i, (name, width))| {
+            Var { name, width, id: identifier(i), value: None }
+        }).collect();
+        for var in &vars {
+            writeln!(out, "$var wire {} {} {} $end", var.width, var.id, var.name)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        let mut vcd = Vcd { out, vars, bus, rom_chips: cpu.get_rom_chips(), time: 0, written: None, error: None };
+        vcd.change(PC, cpu.get_pc() as u64);
+        vcd.ports(cpu);
+        match vcd.error.take() {
+            Some(error) => Err(error),
+            None => Ok(vcd),
+        }
+    }
+
+    /// One phase of the bus-accurate mode, called by `Intel4004::clock_bus`.
+    pub fn phase(&mut self, cpu: &Intel4004, signals: &Signals) {
+        self.time = signals.time;
+        self.change(PHASE, signals.phase as u64);
+        self.change(SYNC, signals.sync as u64);
+        if self.bus {
+            self.change(DATA, signals.data as u64);
+            self.change(DATA + 1, signals.cm_rom as u64);
+            self.change(DATA + 2, signals.cm_ram as u64);
+        }
+        self.change(PC, cpu.get_pc() as u64);
+        self.ports(cpu);
+    }
+
+    /// An instruction at `pc` ran without the bus and took `cycles` instruction cycles. The PC shows at A1, the
+    /// ports as they are after the instruction from X2 of the last cycle.
+    pub fn instruction(&mut self, cpu: &Intel4004, pc: u16, cycles: u64) {
+        for cycle in 0..cycles {
+            for phase in PHASES {
+                if cycle == 0 && phase == Phase::A1 {
+                    self.change(PC, pc as u64);
+                }
+                self.change(PHASE, phase as u64);
+                self.change(SYNC, (phase == Phase::X3) as u64);
+                if cycle == cycles - 1 && phase == Phase::X2 {
+                    self.ports(cpu);
+                }
+                self.time += 1;
+            }
+        }
+    }
+
+    /// Write the final timestamp and flush, returns the first error of the recording.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        writeln!(self.out, "#{}", self.time * PHASE_NS)?;
+        self.out.flush()
+    }
+
+    fn ports(&mut self, cpu: &Intel4004) {
+        self.change(TEST, cpu.get_test() as u64);
+
+        let first = if self.bus { DATA + 3 } else { DATA };
+        for chip in 0..self.rom_chips {
+            self.change(first + chip, cpu.rom[chip].io.value() as u64);
+        }
+        for chip in 0..self.vars.len() - first - self.rom_chips {
+            self.change(first + self.rom_chips + chip, cpu.ram[chip].output as u64);
+        }
+    }
+
+    fn change(&mut self, var: usize, value: u64) {
+        if self.vars[var].value == Some(value) || self.error.is_some() {
+            return;
+        }
+        self.vars[var].value = Some(value);
+        if let Err(error) = self.write(var, value) {
+            self.error = Some(error);
+        }
+    }
+
+    fn write(&mut self, var: usize, value: u64) -> io::Result<()> {
+        if self.written != Some(self.time) {
+            writeln!(self.out, "#{}", self.time * PHASE_NS)?;
+            self.written = Some(self.time);
+        }
+        let var = &self.vars[var];
+        if var.width == 1 {
+            writeln!(self.out, "{}{}", value, var.id)
+        } else {
+            writeln!(self.out, "b{:b} {}", value, var.id)
+        }
+    }
+}
+
+/// Short identifier code of variable `n`, from the printable characters '!' to '~'.
+fn identifier(mut n: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (n % 94) as u8) as char);
+        n /= 94;
+        if n == 0 {
+            return id;
+        }
+        n -= 1;
+    }
+}
@@ -0,0 +1,153 @@
+#[cfg(test)]
+use intel4004_emu::analysis::{check_stack, check_stack_for, CallGraph, ControlFlowGraph, Exit};
+use intel4004_emu::assembler::{assemble, Assembler};
+use intel4004_emu::opcodes::Model;
+
+const PROGRAM: &str = "
+main:   JMS outer
+        FIM P0, 0x20
+        FIN P1
+loop:   JCN Z, main
+        JUN loop
+outer:  JMS inner
+        JMS leaf
+        BBL 0
+inner:  JMS leaf
+        JIN P1
+leaf:   BBL 1
+";
+
+#[test]
+fn test_basic_blocks() {
+    let asm = assemble(PROGRAM).unwrap();
+    let cfg = ControlFlowGraph::build(&asm.image, &[0x000]);
+
+    let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+    assert_eq!(starts, vec![0x000, 0x002, 0x005, 0x007, 0x009, 0x00B, 0x00D, 0x00E, 0x010, 0x011]);
+
+    assert_eq!(cfg.blocks[&0x000].exit, Exit::Call(0x009));
+    assert_eq!(cfg.blocks[&0x002].exit, Exit::Fallthrough);
+    assert!(cfg.blocks[&0x002].uses_fin);
+    assert_eq!(cfg.blocks[&0x005].exit, Exit::Branch);
+    assert_eq!(cfg.blocks[&0x005].successors, vec![0x000, 0x007]);
+    assert_eq!(cfg.blocks[&0x010].exit, Exit::Indirect);
+    assert_eq!(cfg.block_at(0x004).unwrap().start, 0x002);
+}
+
+#[test]
+fn test_call_graph() {
+    let asm = assemble(PROGRAM).unwrap();
+    let cfg = ControlFlowGraph::build(&asm.image, &[0x000]);
+    let calls = CallGraph::build(&cfg);
+
+    let entries: Vec<u16> = calls.functions.keys().copied().collect();
+    assert_eq!(entries, vec![0x000, 0x009, 0x00E, 0x011]);
+    assert_eq!(calls.functions[&0x009].calls, vec![(0x009, 0x00E), (0x00B, 0x011)]);
+    assert!(calls.functions[&0x011].returns);
+
+    assert_eq!(calls.max_depth(0x000), Some(3));
+    assert_eq!(calls.max_depth(0x00E), Some(1));
+    assert_eq!(calls.max_depth(0x011), Some(0));
+}
+
+#[test]
+fn test_recursion() {
+    let asm = assemble("
+        JMS sub
+sub:    JMS sub
+        BBL 0
+").unwrap();
+    let calls = CallGraph::build(&ControlFlowGraph::build(&asm.image, &[0x000]));
+
+    assert_eq!(calls.max_depth(0x000), None);
+}
+
+#[test]
+fn test_dot() {
+    let asm = assemble(PROGRAM).unwrap();
+    let symbols = asm.symbols();
+    let cfg = ControlFlowGraph::build(&asm.image, &[0x000]);
+
+    let dot = cfg.to_dot(&asm.image, Some(&symbols));
+    assert!(dot.starts_with("digraph cfg {\n"));
+    assert!(dot.contains("    b000 [label=\"main:\\l000  JMS outer\\l\"];\n"));
+    assert!(dot.contains("    b000 -> b009 [style=dashed];\n"));
+    assert!(dot.contains("    b005 -> b000;\n"));
+
+    let dot = CallGraph::build(&cfg).to_dot(Some(&symbols));
+    assert!(dot.contains("    f000 [label=\"main\\ndepth 3\", shape=doubleoctagon];\n"));
+    assert!(dot.contains("    f009 -> f00E;\n"));
+    assert!(dot.ends_with("}\n"));
+}
+
+#[test]
+fn test_stack_check() {
+    let asm = assemble(PROGRAM).unwrap();
+    let report = check_stack(&asm.image, &[0x000]);
+
+    assert!(report.is_ok());
+    assert_eq!(report.max_depth, Some(3));
+
+    let asm = assemble("
+main:   JMS a
+        JMS c
+a:      JMS b
+        BBL 0
+b:      JMS c
+        BBL 0
+c:      JMS d
+        BBL 0
+d:      BBL 0
+").unwrap();
+    let report = check_stack(&asm.image, &[0x000]);
+
+    assert!(!report.is_ok());
+    assert_eq!(report.max_depth, Some(4));
+    assert_eq!(report.overflows, vec![(0x000, vec![(0x000, 0x004), (0x004, 0x007), (0x007, 0x00A), (0x00A, 0x00D)])]);
+    assert_eq!(report.to_text(Some(&asm.symbols())),
+        "worst-case call depth: 4 of 3 levels\noverflow: main -> a (JMS at 000) -> b (JMS at 004) -> c (JMS at 007) -> d (JMS at 00A)\n");
+}
+
+#[test]
+fn test_stack_check_recursion() {
+    let asm = assemble("
+        JMS sub
+sub:    JMS sub
+        BBL 0
+").unwrap();
+    let report = check_stack(&asm.image, &[0x000]);
+
+    assert_eq!(report.max_depth, None);
+    assert_eq!(report.recursions, vec![
+        (0x000, vec![(0x000, 0x002), (0x002, 0x002)]),
+        (0x000, vec![(0x002, 0x002), (0x002, 0x002)]),                // main falls through into sub.
+    ]);
+}
+
+#[test]
+fn test_stack_check_4040() {
+    let mut assembler = Assembler::new();
+    assembler.model(Model::I4040);
+    let asm = assembler.assemble_str("
+main:   JMS a
+done:   JUN done
+a:      JMS b
+        BBL 0
+b:      JMS c
+        BBL 0
+c:      JMS d
+        BBL 0
+d:      JMS e
+        BBS
+e:      BBL 0
+", "test").unwrap();
+
+    let cfg = ControlFlowGraph::build_for(Model::I4040, &asm.image, &[0x000]);
+    assert_eq!(cfg.block_at(0x00F).unwrap().exit, Exit::Return);
+    assert!(ControlFlowGraph::build(&asm.image, &[0x000]).block_at(0x00F).is_none());
+
+    let report = check_stack_for(Model::I4040, &asm.image, &[0x000]);
+    assert!(report.is_ok());
+    assert_eq!(report.to_text(None), "worst-case call depth: 5 of 7 levels\n");
+    assert_eq!(check_stack(&asm.image, &[0x000]).overflows.len(), 1);
+}
@@ -0,0 +1,241 @@
+#[cfg(test)]
+use intel4004_emu::assembler::{assemble, Assembler};
+use intel4004_emu::intel4004::Intel4004;
+
+use std::{env, fs};
+
+#[test]
+fn test_instructions() {
+    let asm = assemble("
+start:  FIM P1, 0x2A
+        SRC P1
+        LDM 7
+        WRM
+        JCN NZ, start
+        ISZ R3, start
+        JMS sub
+        JUN start
+sub:    BBL 0
+").unwrap();
+
+    assert_eq!(asm.image, vec![
+        0x22, 0x2A, 0x23, 0xD7, 0xE0, 0x1C, 0x00, 0x73, 0x00, 0x50, 0x0D, 0x40, 0x00, 0xC0,
+    ]);
+    assert_eq!(asm.labels["sub"], 0x0D);
+}
+
+#[test]
+fn test_org_and_db() {
+    let asm = assemble("
+        JUN main
+        ORG 0x10
+table:  DB 1, 2, 0xFF, 'A'
+        DATA \"hi\"
+main:   NOP
+").unwrap();
+
+    assert_eq!(asm.image[0..2], [0x40, 0x16]);
+    assert_eq!(asm.image[0x10..0x17], [0x01, 0x02, 0xFF, 0x41, b'h', b'i', 0x00]);
+    assert_eq!(asm.labels["table"], 0x10);
+}
+
+#[test]
+fn test_equ_and_set() {
+    let asm = assemble("
+COUNT   EQU END_VAL - 2
+END_VAL EQU 0x0C
+X       SET 1
+        LDM X
+X       SET X + 1
+        LDM X
+        LDM COUNT
+").unwrap();
+
+    assert_eq!(asm.image, vec![0xD1, 0xD2, 0xDA]);
+    assert_eq!(asm.constants["COUNT"], 10);
+}
+
+#[test]
+fn test_forward_equ_here() {
+    let asm = assemble("
+        ORG 0x20
+        NOP
+X       EQU $ + LATER
+LATER   EQU 3
+").unwrap();
+
+    assert_eq!(asm.constants["X"], 0x24);                          // $ is the PC of the EQU line, not 0.
+}
+
+#[test]
+fn test_expressions() {
+    let asm = assemble("
+ADDR    EQU 0x3A7
+        FIM P0, ADDR & 0xFF
+        LDM HIGH(0xA5)
+        LDM LOW(0xA5)
+        LDM PAGE(ADDR)
+        LDM (1 + 2) * 3 - 1 << 1 >> 1
+        JUN $
+").unwrap();
+
+    assert_eq!(asm.image, vec![0x20, 0xA7, 0xDA, 0xD5, 0xD3, 0xD8, 0x40, 0x06]);
+}
+
+#[test]
+fn test_expression_overflow() {
+    let asm = assemble("
+MIN     EQU -0x7FFFFFFFFFFFFFFF - 1
+QUOT    EQU MIN / -1
+REM     EQU MIN % -1
+NEG     EQU -MIN
+").unwrap();
+
+    assert_eq!((asm.constants["QUOT"], asm.constants["REM"], asm.constants["NEG"]), (i64::MIN, 0, i64::MIN));
+}
+
+#[test]
+fn test_conditional_assembly() {
+    let asm = assemble("
+DEBUG   EQU 1
+        IF DEBUG
+        LDM 1
+        IF DEBUG > 1
+        LDM 2
+        ELSE
+        LDM 3
+        ENDIF
+        ELSE
+        LDM 4
+        ENDIF
+").unwrap();
+
+    assert_eq!(asm.image, vec![0xD1, 0xD3]);
+}
+
+#[test]
+fn test_macros() {
+    let asm = assemble("
+load    MACRO reg, value
+        LDM value
+        XCH reg
+        ENDM
+
+wait    MACRO reg
+loop\\@: ISZ reg, loop\\@
+        ENDM
+
+        load R2, 5
+        load R3, 0xF
+        wait R4
+        wait R5
+").unwrap();
+
+    assert_eq!(asm.image, vec![0xD5, 0xB2, 0xDF, 0xB3, 0x74, 0x04, 0x75, 0x06]);
+}
+
+#[test]
+fn test_include() {
+    let dir = env::temp_dir().join("intel4004_asm_include");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("defs.inc"), "VALUE EQU 9\n").unwrap();
+    fs::write(dir.join("main.asm"), "        INCLUDE \"defs.inc\"\n        LDM VALUE\n").unwrap();
+
+    let asm = Assembler::new().assemble_file(&dir.join("main.asm")).unwrap();
+    assert_eq!(asm.image, vec![0xD9]);
+}
+
+#[test]
+fn test_errors() {
+    let errors = assemble("
+        LDM 16
+        JUN nowhere
+        FOO
+label:  NOP
+label:  NOP
+").unwrap_err();
+
+    let lines: Vec<usize> = errors.iter().map(|e| e.location.line).collect();
+    assert_eq!(lines, vec![4, 6, 2, 3]);
+}
+
+#[test]
+fn test_run_assembled() {
+    let asm = assemble("
+        FIM P0, 0x03
+        LDM 5
+        XCH R2
+loop:   INC R1
+        LD R2
+        DAC
+        XCH R2
+        LD R2
+        JCN Z, exit
+        JUN loop
+exit:   LD R1
+done:   JUN done
+").unwrap();
+
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+
+    for _ in 0..40 {
+        cpu.clock();
+    }
+
+    assert_eq!(cpu.get_acc(), 0x8);
+    assert_eq!(cpu.get_pc(), asm.labels["done"]);
+}
+
+#[test]
+fn test_page_errors() {
+    let errors = assemble("
+        ORG 0x0F0
+loop:   NOP
+        ORG 0x1FE
+        JCN Z, loop
+        ORG 0x230
+        ISZ R1, loop
+").unwrap_err();
+
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, vec![
+        "<source>:5: error: target 0x0F0 is not in page 2, the instruction ends page 1 so it jumps inside page 2",
+        "<source>:7: error: target 0x0F0 is not in page 2 of the instruction",
+        "<source>:5: warning: JCN at the end of page 1 jumps inside page 2",
+    ]);
+}
+
+#[test]
+fn test_page_warnings() {
+    let asm = assemble("
+        ORG 0x0FF
+        FIN P1
+        JUN 0x100
+        ORG 0x2FF
+        JIN P0
+").unwrap();
+
+    let lines: Vec<(usize, String)> = asm.warnings.iter().map(|w| (w.location.line, w.message.clone())).collect();
+    assert_eq!(lines, vec![
+        (3, "FIN at the end of page 0 uses page 1".to_string()),
+        (6, "JIN at the end of page 2 uses page 3".to_string()),
+    ]);
+}
+
+#[test]
+fn test_auto_pad() {
+    let mut assembler = Assembler::new();
+    assembler.auto_pad(true);
+
+    let asm = assembler.assemble_str("
+        ORG 0x0FD
+        NOP
+here:   JCN C, here
+", "pad.asm").unwrap();
+
+    assert_eq!(asm.labels["here"], 0x100);
+    assert_eq!(asm.image[0x0FD..], [0x00, 0x00, 0x00, 0x12, 0x00]);
+    assert_eq!(asm.warnings.len(), 1);
+    assert_eq!(asm.warnings[0].location.line, 4);
+}
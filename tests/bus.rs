@@ -0,0 +1,222 @@
+#[cfg(test)]
+use intel4004_emu::assembler::{assemble, Assembler};
+use intel4004_emu::bus::{Bus, BusDevice, Phase, Signals};
+use intel4004_emu::intel4004::{Intel4004, RAM_CHIPS};
+use intel4004_emu::opcodes::Model;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PROGRAM: &str = "
+        FIM P0, 0x45
+        SRC P0
+        LDM 9
+        WRM
+        WR2
+        WMP
+        FIM P0, 0x30
+        SRC P0
+        WRR
+        RDR
+        IAC
+        FIM P0, 0x45
+        SRC P0
+        ADM
+        RD2
+        JMS sub
+        LDM 2
+        DCL
+        FIM P0, 0x00
+        FIN P0
+done:   JUN done
+sub:    BBL 3
+";
+
+fn cpu(bus: bool) -> Intel4004 {
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&assemble(PROGRAM).unwrap().image);
+    if bus {
+        cpu.enable_bus(Bus::new());
+    }
+    cpu
+}
+
+/// Records every phase.
+struct Probe(Rc<RefCell<Vec<Signals>>>);
+
+impl BusDevice for Probe {
+    fn sample(&mut self, signals: &Signals) {
+        self.0.borrow_mut().push(*signals);
+    }
+}
+
+#[test]
+fn test_same_as_direct() {
+    let mut direct = cpu(false);
+    let mut bus = cpu(true);
+
+    while direct.get_pc() != 0x019 {
+        direct.clock();
+        bus.clock();
+        assert_eq!((bus.get_pc(), bus.get_acc(), bus.get_carry()),
+            (direct.get_pc(), direct.get_acc(), direct.get_carry()));
+        assert_eq!(bus.get_index(), direct.get_index());
+    }
+    assert_eq!(bus.ram[1].ram[0x05], 0x9);
+    assert_eq!((bus.ram[1].status[2], bus.ram[1].output, bus.rom[3].io.value()), (0x9, 0x9, 0x9));
+    assert_eq!(bus.get_reg_pair(0), 0x20);                          // FIN read the first bytes of the ROM.
+}
+
+#[test]
+fn test_phases() {
+    let mut cpu = cpu(true);
+    let signals = Rc::new(RefCell::new(Vec::new()));
+    cpu.bus_mut().unwrap().attach(Box::new(Probe(signals.clone())));
+
+    cpu.clock();                                                    // FIM, two cycles.
+    cpu.clock();                                                    // SRC
+    let trace = signals.borrow().clone();
+    assert_eq!(trace.len(), 24);
+    assert_eq!(trace.iter().map(|s| s.time).collect::<Vec<u64>>(), (0..24).collect::<Vec<u64>>());
+    assert!(trace.iter().all(|s| s.sync == (s.phase == Phase::X3)));
+
+    // The second word at 0x001 goes out at A1-A3 and comes back at M1-M2.
+    let data: Vec<u8> = trace[8..13].iter().map(|s| s.data).collect();
+    assert_eq!(data, vec![0x1, 0x0, 0x0, 0x4, 0x5]);
+    assert_eq!(trace[10].cm_rom, 1);
+
+    // SRC sends the chip and register at X2 with every CM line, the character at X3.
+    let src = &trace[16..24];
+    assert_eq!((src[6].phase, src[6].data, src[6].cm_rom, src[6].cm_ram), (Phase::X2, 0x4, 1, 1));
+    assert_eq!((src[7].data, src[7].cm_ram), (0x5, 0));
+    signals.borrow_mut().clear();
+
+    cpu.clock();                                                    // LDM 9
+    cpu.clock();                                                    // WRM
+    let trace = signals.borrow();
+    let wrm = &trace[8..16];
+    assert_eq!((wrm[4].data, wrm[4].cm_rom, wrm[4].cm_ram), (0x0, 1, 1));    // OPA of WRM with the CM lines.
+    assert_eq!(wrm[6].data, 0x9);                                   // The accumulator at X2.
+}
+
+/// A 4002 stand-in on CM-RAM1 that answers RDM with 0x7 wherever SRC points.
+struct FakeRam {
+    io: bool,
+}
+
+impl BusDevice for FakeRam {
+    fn drive(&mut self, signals: &mut Signals) {
+        if signals.phase == Phase::X2 && self.io {
+            signals.data = 0x7;
+        }
+    }
+
+    fn sample(&mut self, signals: &Signals) {
+        if signals.phase == Phase::M2 {
+            self.io = signals.cm_ram & 0x2 != 0 && signals.data == 0x9;
+        }
+    }
+}
+
+#[test]
+fn test_device() {
+    let mut cpu = Intel4004::with_chips(16, 4);                     // Bank 0 only, bank 1 is the device.
+    cpu.load_bytes(&assemble("
+        LDM 1
+        DCL
+        RDM
+        LDM 0
+        DCL
+        XCH R2
+        RDM
+").unwrap().image);
+    let mut bus = Bus::new();
+    bus.attach(Box::new(FakeRam { io: false }));
+    cpu.enable_bus(bus);
+
+    (0..3).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_acc(), 0x7);
+    (0..4).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_acc(), 0x0);                                 // Bank 0 is a real 4002.
+}
+
+#[test]
+fn test_src_reaches_selected_bank() {
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&assemble("
+        FIM P0, 0x10
+        SRC P0
+        LDM 1
+        DCL
+        FIM P0, 0x20
+        SRC P0
+        LDM 0
+        DCL
+        LDM 5
+        WRM
+").unwrap().image);
+    cpu.enable_bus(Bus::new());
+    (0..10).for_each(|_| cpu.clock());
+
+    // Bank 0 chips never saw the second SRC, they still point at character 0x10.
+    assert_eq!(cpu.ram[0].ram[0x10], 0x5);
+    assert_eq!(cpu.ram[0].ram[0x20], 0x0);
+}
+
+const INTERRUPTED: &str = "
+        JUN main
+        NOP
+isr:    FIM P0, 0x10
+        SRC P0
+        BBS
+main:   FIM P0, 0x00
+        SRC P0
+        LDM 7
+        WRM
+        EIN
+        LDM 0
+        RDM
+done:   JUN done
+";
+
+fn cpu_4040(bus: bool) -> Intel4004 {
+    let mut assembler = Assembler::new();
+    assembler.model(Model::I4040);
+    let mut cpu = Intel4004::with_model(Model::I4040, 32, RAM_CHIPS);
+    cpu.load_bytes(&assembler.assemble_str(INTERRUPTED, "test").unwrap().image);
+    if bus {
+        cpu.enable_bus(Bus::new());
+    }
+    cpu
+}
+
+#[test]
+fn test_interrupt_same_as_direct() {
+    let mut direct = cpu_4040(false);
+    let mut bus = cpu_4040(true);
+    let signals = Rc::new(RefCell::new(Vec::new()));
+    bus.bus_mut().unwrap().attach(Box::new(Probe(signals.clone())));
+
+    let mut taken = false;
+    while direct.get_pc() != 0x00F {
+        let interrupt = direct.get_pc() == 0x00E && !taken;         // Before RDM.
+        taken |= interrupt;
+        direct.set_interrupt(interrupt);
+        bus.set_interrupt(interrupt);
+        signals.borrow_mut().clear();
+
+        direct.clock();
+        bus.clock();
+        assert_eq!((bus.get_pc(), bus.get_acc(), bus.get_ram_addrs()),
+            (direct.get_pc(), direct.get_acc(), direct.get_ram_addrs()));
+
+        if interrupt {
+            // The acknowledge cycle fetches RDM without running it: no CM lines at M2.
+            let trace = signals.borrow();
+            assert_eq!(trace.len(), 8);
+            assert_eq!((trace[3].data, trace[4].data, trace[4].cm_ram), (0xE, 0x9, 0));
+        }
+    }
+    assert_eq!(direct.get_acc(), 0x7);
+    assert_eq!(bus.get_acc(), 0x7);                                 // BBS sent the restored SRC to the 4002.
+}
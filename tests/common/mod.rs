@@ -0,0 +1,15 @@
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::intel4004::Intel4004;
+use intel4004_emu::strict::Diagnostic;
+
+/// A CPU with `rom_chips` and `ram_chips` populated, running `source` from address 0.
+pub fn assembled(source: &str, rom_chips: usize, ram_chips: usize) -> Intel4004 {
+    let mut cpu = Intel4004::with_chips(rom_chips, ram_chips);
+    cpu.load_bytes(&assemble(source).unwrap().image);
+    cpu
+}
+
+/// PC and message of every diagnostic.
+pub fn messages(diagnostics: &[Diagnostic]) -> Vec<(u16, String)> {
+    diagnostics.iter().map(|d| (d.pc, d.message.clone())).collect()
+}
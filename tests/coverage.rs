@@ -0,0 +1,74 @@
+#[cfg(test)]
+use intel4004_emu::assembler::{assemble, Assembly};
+use intel4004_emu::coverage::Coverage;
+use intel4004_emu::intel4004::Intel4004;
+
+const PROGRAM: &str = "
+main:   LDM 0
+loop:   JCN Z, zero
+        JCN C, miss
+done:   JUN done
+zero:   IAC
+        JUN loop
+miss:   NOP
+        JUN done
+";
+
+fn run(steps: usize) -> (Coverage, Assembly) {
+    let asm = assemble(PROGRAM).unwrap();
+
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+    cpu.enable_coverage();
+
+    for _ in 0..steps {
+        cpu.clock();
+    }
+    (cpu.take_coverage().unwrap(), asm)
+}
+
+#[test]
+fn test_branch_outcomes() {
+    let (coverage, asm) = run(10);
+    let symbols = asm.symbols();
+
+    assert_eq!(coverage.branch(symbols.address_of("loop").unwrap()), (1, 1));
+    assert_eq!(coverage.branch(0x003), (0, 1));
+    assert_eq!(coverage.executions(symbols.address_of("done").unwrap()), 4);
+    assert_eq!(coverage.executions(symbols.address_of("miss").unwrap()), 0);
+
+    let mut merged = Coverage::new();
+    merged.merge(&coverage);
+    merged.merge(&coverage);
+    assert_eq!(merged.branch(0x001), (2, 2));
+}
+
+#[test]
+fn test_annotated_listing() {
+    let (coverage, asm) = run(10);
+    let symbols = asm.symbols();
+
+    let listing = coverage.annotated_listing(&asm.image, Some(&symbols));
+    assert!(listing.contains("         1  003  JCN C, miss  [taken 0, not taken 1]  ; never taken\n"));
+    assert!(listing.contains("                 miss:\n     #####  00A  NOP\n"));
+
+    let source = coverage.annotated_source(&asm.listing);
+    assert!(source.contains("         2     3  loop:   JCN Z, zero  [taken 1, not taken 1]\n"));
+    assert!(source.contains("     #####     8  miss:   NOP\n"));
+}
+
+#[test]
+fn test_lcov() {
+    let (coverage, asm) = run(10);
+    let lcov = coverage.lcov(&asm.listing);
+
+    assert!(lcov.starts_with("TN:\nSF:<source>\n"));
+    assert!(lcov.contains("BRDA:3,0,0,1\nBRDA:3,0,1,1\nBRDA:4,0,0,0\nBRDA:4,0,1,1\nBRF:4\nBRH:3\n"));
+    assert!(lcov.contains("DA:5,4\n"));
+    assert!(lcov.contains("DA:8,0\n"));
+    assert!(lcov.ends_with("LF:8\nLH:6\nend_of_record\n"));
+
+    let by_address = coverage.lcov_by_address(&asm.image, "rom.bin");
+    assert!(by_address.contains("SF:rom.bin\n"));
+    assert!(by_address.contains("DA:11,0\n"));
+}
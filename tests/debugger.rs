@@ -0,0 +1,123 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::debugger::{Debugger, StopReason};
+use intel4004_emu::disassembler::disassemble;
+use intel4004_emu::intel4004::{Intel4004, UndefinedPolicy};
+use intel4004_emu::symbols::SymbolTable;
+
+use std::env;
+
+const PROGRAM: &str = "
+main:   LDM 3
+        JMS sub
+loop:   IAC
+        JCN Z, main
+        JUN loop
+sub:    XCH R0
+        BBL 0
+";
+
+#[test]
+fn test_symbol_file() {
+    let asm = assemble(PROGRAM).unwrap();
+    let symbols = asm.symbols();
+
+    assert_eq!(symbols.to_text(), "000 main\n003 loop\n008 sub\n");
+
+    let path = env::temp_dir().join("intel4004_debugger.sym");
+    asm.save_symbols(path.to_str().unwrap()).unwrap();
+
+    let loaded = SymbolTable::load(path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.address_of("sub"), Some(0x008));
+    assert_eq!(loaded.label_at(0x003), Some("loop"));
+    assert_eq!(loaded.resolve("loop+2"), Some(0x005));
+    assert_eq!(loaded.resolve("0x7"), Some(0x007));
+    assert_eq!(loaded.resolve("loop+0xFFFF"), None);
+    assert_eq!(loaded.resolve("sub+0xFFF"), Some(0x007));
+    assert_eq!(loaded.format_address(0x004), "loop+1");
+
+    assert!(SymbolTable::parse("12G main\n").is_err());
+}
+
+#[test]
+fn test_shared_address() {
+    let asm = assemble("zeta:\nmid:\nalpha:  NOP\n").unwrap();
+    let symbols = asm.symbols();
+    let loaded = SymbolTable::parse(&symbols.to_text()).unwrap();
+
+    assert_eq!(symbols.label_at(0x000), Some("alpha"));
+    assert_eq!(loaded.label_at(0x000), Some("alpha"));
+}
+
+#[test]
+fn test_listing() {
+    let asm = assemble(PROGRAM).unwrap();
+    let listing = asm.listing_text();
+
+    assert!(listing.lines().any(|l| l == "001   50 08         3           JMS sub"));
+    assert_eq!(asm.listing.iter().filter(|l| !l.bytes.is_empty()).count(), 7);
+}
+
+#[test]
+fn test_disassemble_with_labels() {
+    let asm = assemble(PROGRAM).unwrap();
+    let symbols = asm.symbols();
+
+    assert_eq!(disassemble(&asm.image, 0x001, Some(&symbols)), ("JMS sub".to_string(), 2));
+    assert_eq!(disassemble(&asm.image, 0x004, Some(&symbols)), ("JCN Z, main".to_string(), 2));
+    assert_eq!(disassemble(&asm.image, 0x004, None), ("JCN Z, 0x000".to_string(), 2));
+    assert_eq!(disassemble(&[0xFE], 0x000, None), ("DB 0xFE".to_string(), 1));
+}
+
+#[test]
+fn test_breakpoints_and_trace() {
+    let asm = assemble(PROGRAM).unwrap();
+
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+
+    let mut debugger = Debugger::new(cpu);
+    debugger.set_symbols(asm.symbols());
+
+    assert_eq!(debugger.add_breakpoint("sub"), Some(0x008));
+    assert_eq!(debugger.add_breakpoint("nowhere"), None);
+    assert_eq!(debugger.list_breakpoints(), vec!["008 sub"]);
+
+    assert_eq!(debugger.run(100), StopReason::Breakpoint(0x008));
+    assert_eq!(debugger.step(), "008  sub:         XCH R0");
+    assert_eq!(debugger.trace_line(debugger.cpu.get_pc()), "009               BBL 0");
+}
+
+#[test]
+fn test_commands() {
+    let asm = assemble(PROGRAM).unwrap();
+
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+
+    let mut debugger = Debugger::new(cpu);
+    debugger.set_symbols(asm.symbols());
+
+    assert_eq!(debugger.execute("b sub").as_deref(), Some("breakpoint at 008"));
+    assert_eq!(debugger.execute("c").as_deref(), Some("breakpoint sub"));
+    assert_eq!(debugger.execute("s 2").as_deref(), Some("008  sub:         XCH R0\n009               BBL 0"));
+    assert!(debugger.execute("r").unwrap().starts_with("PC=003 ACC=0"));
+    assert_eq!(debugger.execute("x").as_deref(), Some("unknown command, type 'help'"));
+    assert_eq!(debugger.execute("q"), None);
+}
+
+#[test]
+fn test_undefined_trap() {
+    let mut cpu = Intel4004::new();
+    cpu.set_undefined_policy(UndefinedPolicy::Trap);
+    cpu.load_bytes(&[0xD5, 0xFF, 0xF2]);                            // LDM 5, undefined, IAC
+    let mut debugger = Debugger::new(cpu);
+
+    assert_eq!(debugger.run(100), StopReason::Undefined(0x001));
+    assert_eq!(debugger.execute("s").unwrap(), "skipped undefined opcode at 001");
+    assert_eq!(debugger.step(), "002               IAC");
+    assert_eq!(debugger.cpu.get_acc(), 0x6);
+
+    // Continuing wraps around through the empty ROM and traps again.
+    assert_eq!(debugger.execute("c").unwrap(), "undefined opcode FF at 0x001");
+}
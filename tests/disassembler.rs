@@ -0,0 +1,77 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::disassembler::{discover, disassemble_source, ByteKind};
+
+use std::fs;
+
+fn round_trip(rom: &[u8]) -> String {
+    let source = disassemble_source(rom, &[0x000], None);
+    let asm = assemble(&source).unwrap_or_else(|e| panic!("{:?}\n{}", e, source));
+
+    assert_eq!(asm.image, rom, "\n{}", source);
+    source
+}
+
+#[test]
+fn test_round_trip_program() {
+    let asm = assemble("
+        FIM P0, table & 0xFF
+        FIN P1
+        JMS sub
+loop:   JCN NZ, loop
+        JUN 0x0A0
+sub:    ISZ R3, sub
+        BBL 0
+table:  DB 0x12, 0x34, 0xFE
+        ORG 0x0A0
+        JIN P1
+").unwrap();
+
+    let source = round_trip(&asm.image);
+
+    assert!(source.contains("sub_009:\n        ISZ R3, sub_009\n        BBL 0\n"));
+    assert!(source.contains("        FIM P0, data_00C & 0xFF\n"));
+    assert!(source.contains("data_00C:\n        DB 0x12, 0x34, 0xFE"));
+    assert!(source.contains("L_0A0:\n        JIN P1\n"));
+}
+
+#[test]
+fn test_discover() {
+    // JUN over a data byte, the undefined opcode 0xFF is never reached.
+    let rom = [0x40, 0x03, 0xFF, 0x50, 0x06, 0xC0, 0xC1];
+    let map = discover(&rom, &[0x000], None);
+
+    assert_eq!(map.kinds, vec![
+        ByteKind::Code, ByteKind::Operand, ByteKind::Unknown, ByteKind::Code, ByteKind::Operand,
+        ByteKind::Code, ByteKind::Code,
+    ]);
+    assert_eq!(map.labels[&0x006], "sub_006");
+}
+
+#[test]
+fn test_round_trip_overlapping_targets() {
+    // The JCN targets the second byte of the JUN, which needs an EQU.
+    let rom = [0x12, 0x03, 0x40, 0x02, 0xC0];
+    let source = round_trip(&rom);
+
+    assert!(source.contains("L_003 EQU 0x003\n"));
+    assert!(source.contains("        JCN C, L_003\n"));
+}
+
+#[test]
+fn test_round_trip_rom_files() {
+    for file in ["rom/ram_test", "rom/ram_status_test", "rom/RDn", "rom/WDn", "rom/WRM"] {
+        round_trip(&fs::read(file).unwrap());
+    }
+}
+
+#[test]
+fn test_round_trip_random() {
+    let mut seed: u32 = 4004;
+    let rom: Vec<u8> = (0..1024).map(|_| {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        (seed >> 16) as u8
+    }).collect();
+
+    round_trip(&rom);
+}
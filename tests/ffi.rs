@@ -0,0 +1,98 @@
+#![cfg(feature = "ffi")]
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::ffi::*;
+
+use std::env;
+use std::ffi::c_void;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+
+const PROGRAM: &str = "
+        FIM P0, 0x40
+        SRC P0
+        RDR
+        WMP
+        IAC
+        WRR
+        WRM
+done:   JUN done
+";
+
+#[derive(Default)]
+struct Bench {
+    ram_writes: Vec<(u8, u8)>,
+    rom_writes: Vec<(u8, u8)>,
+}
+
+extern "C" fn ram_write(user: *mut c_void, chip: u8, value: u8) {
+    unsafe { (*(user as *mut Bench)).ram_writes.push((chip, value)) };
+}
+
+extern "C" fn rom_write(user: *mut c_void, chip: u8, value: u8) {
+    unsafe { (*(user as *mut Bench)).rom_writes.push((chip, value)) };
+}
+
+extern "C" fn rom_read(_user: *mut c_void, chip: u8) -> i32 {
+    if chip == 4 { 6 } else { -1 }
+}
+
+#[test]
+fn test_machine() {
+    let image = assemble(PROGRAM).unwrap().image;
+    let mut bench = Bench::default();
+
+    unsafe {
+        let machine = i4004_new(0, 0);
+        assert_eq!(i4004_load_rom(machine, image.as_ptr(), image.len()), 0);
+        assert_eq!(i4004_load_rom(machine, image.as_ptr(), 4097), -1);
+        i4004_set_io(machine, Some(rom_write), Some(rom_read), Some(ram_write), &mut bench as *mut Bench as *mut c_void);
+
+        assert_eq!(i4004_step(machine), 2);
+        assert_eq!(i4004_get_reg(machine, 0), 4);
+
+        let mut result = I4004RunResult { exit: I4004Exit::CycleLimit, address: 0, instructions: 0, cycles: 0 };
+        assert_eq!(i4004_run(machine, 1000, -1, &mut result), 0);
+        assert_eq!(result.exit, I4004Exit::Halt);
+        assert_eq!((result.address, result.instructions, result.cycles), (0x008, 7, 8));
+
+        assert_eq!(bench.ram_writes, vec![(1, 6)]);
+        assert_eq!(bench.rom_writes, vec![(4, 7)]);
+        assert_eq!(i4004_read_ram(machine, 1, 0), 7);
+        assert_eq!(i4004_read_ram_port(machine, 1), 6);
+
+        i4004_write_status(machine, 15, 15, 0x1F);
+        assert_eq!(i4004_read_status(machine, 15, 15), 0xF);
+        assert_eq!(i4004_read_ram(machine, 16, 0), 0);
+
+        i4004_set_pc(machine, 0x004);
+        i4004_set_acc(machine, 2);
+        assert_eq!(i4004_run(machine, 1000, 0x006, ptr::null_mut()), 0);
+        assert_eq!(i4004_get_acc(machine), 3);
+        assert_eq!(i4004_run(machine, 1, -1, ptr::null_mut()), 1);
+
+        i4004_reset(machine);
+        assert_eq!((i4004_get_pc(machine), i4004_get_reg(machine, 0), i4004_read_ram(machine, 1, 0)), (0, 0, 0));
+        assert_eq!(i4004_step(machine), 2);                         // The ROM survives.
+        assert_eq!(i4004_get_reg(machine, 0), 4);
+
+        i4004_free(machine);
+        i4004_free(ptr::null_mut());
+    }
+}
+
+/// The checked in header matches the code, run with UPDATE_HEADER=1 to rewrite it.
+#[test]
+fn test_header() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(root.join("cbindgen.toml")).unwrap();
+    let mut header = Vec::new();
+    cbindgen::Builder::new().with_config(config).with_src(root.join("src/ffi.rs")).generate().unwrap().write(&mut header);
+
+    let path = root.join("include/intel4004.h");
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, &header).unwrap();
+    }
+    assert!(fs::read(&path).unwrap_or_default() == header, "include/intel4004.h is out of date, run with UPDATE_HEADER=1");
+}
@@ -0,0 +1,133 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::gdb::{checksum, GdbStub};
+use intel4004_emu::intel4004::{Intel4004, UndefinedPolicy, RAM_CHIPS};
+use intel4004_emu::opcodes::Model;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+const PROGRAM: &str = "
+main:   LDM 5
+        FIM P0, 0x21
+        SRC P0
+        WRM
+        JUN main
+";
+
+/// Send a packet and return the reply, acknowledging both ways like gdb does.
+fn request(stream: &mut TcpStream, payload: &str) -> String {
+    write!(stream, "${}#{:02x}", payload, checksum(payload.as_bytes())).unwrap();
+    reply(stream)
+}
+
+fn reply(stream: &mut TcpStream) -> String {
+    let mut byte = [0];
+    let mut packet = Vec::new();
+
+    while byte[0] != b'$' {
+        stream.read_exact(&mut byte).unwrap();
+    }
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+        match byte[0] {
+            b'#' => break,
+            b => packet.push(b),
+        }
+    }
+    let mut sum = [0; 2];
+    stream.read_exact(&mut sum).unwrap();
+    assert_eq!(format!("{:02x}", checksum(&packet)), String::from_utf8_lossy(&sum));
+    stream.write_all(b"+").unwrap();
+    String::from_utf8(packet).unwrap()
+}
+
+#[test]
+fn test_session() {
+    let image = assemble(PROGRAM).unwrap().image;
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let mut cpu = Intel4004::new();
+        cpu.load_bytes(&image);
+        let (stream, _) = listener.accept().unwrap();
+        GdbStub::new(cpu).serve(stream).unwrap();
+    });
+    let mut gdb = TcpStream::connect(addr).unwrap();
+    gdb.set_nodelay(true).unwrap();
+
+    assert!(request(&mut gdb, "qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert!(request(&mut gdb, "qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+    assert_eq!(request(&mut gdb, "?"), "S05");
+    assert_eq!(request(&mut gdb, "m0,3"), "d52021");
+
+    assert_eq!(request(&mut gdb, "Z0,4,1"), "OK");
+    assert_eq!(request(&mut gdb, "c"), "S05");
+    assert_eq!(request(&mut gdb, "g"), format!("040005000201{}{}", "00".repeat(14), "0000".repeat(3)));
+    assert_eq!(request(&mut gdb, "s"), "S05");
+    assert_eq!(request(&mut gdb, "p0"), "0500");
+    assert_eq!(request(&mut gdb, "m1021,2"), "0500");
+    assert_eq!(request(&mut gdb, "z0,4,1"), "OK");
+
+    assert_eq!(request(&mut gdb, "M1021,1:00"), "OK");
+    assert_eq!(request(&mut gdb, "Z2,1021,1"), "OK");
+    assert_eq!(request(&mut gdb, "c"), "T05watch:1021;");
+    assert_eq!(request(&mut gdb, "p0"), "0500");
+    assert_eq!(request(&mut gdb, "z2,1021,1"), "OK");
+    assert_eq!(request(&mut gdb, "z2,fff0,20"), "E01");
+
+    assert_eq!(request(&mut gdb, "P1=07"), "OK");
+    assert_eq!(request(&mut gdb, "p1"), "07");
+    assert_eq!(request(&mut gdb, "m3000,1"), "E01");
+
+    write!(gdb, "$c#63").unwrap();
+    gdb.write_all(&[0x03]).unwrap();
+    assert_eq!(reply(&mut gdb), "S02");
+
+    assert_eq!(request(&mut gdb, "D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn test_4040_registers() {
+    let mut stub = GdbStub::new(Intel4004::with_model(Model::I4040, 32, RAM_CHIPS));
+    let mut request = |packet: &str| stub.handle(packet, &mut || false).unwrap();
+
+    let xml = request("qXfer:features:read:target.xml:0,1000");
+    assert!(xml.contains(r#"<reg name="s6" bitsize="16" type="code_ptr"/>"#) && xml.contains(r#"<reg name="db""#));
+    assert_eq!(request("g").len(), (4 + 16 + 7 * 2 + 2) * 2);      // PC, ACC, CY, R0-R15, 7 levels, SB and DB.
+
+    assert_eq!(request("P18=2301"), "OK");                          // s5.
+    assert_eq!(request("P1b=01"), "OK");                            // DB1.
+    assert_eq!(request("M0,1:ab"), "OK");
+    assert_eq!(request("m0,1"), "ab");
+    assert_eq!(request("p1b"), "01");
+    assert_eq!(request("p1c"), "E01");
+
+    assert_eq!(stub.cpu.get_stack()[5], 0x123);
+    assert_eq!((stub.cpu.rom[16].rom[0], stub.cpu.rom[0].rom[0]), (0xAB, 0x00));
+}
+
+#[test]
+fn test_stop_replies() {
+    let mut cpu = Intel4004::with_model(Model::I4040, 32, RAM_CHIPS);
+    cpu.load_bytes(&[0xD5, 0x01, 0xF2]);                            // LDM 5, HLT, IAC
+    let mut stub = GdbStub::new(cpu);
+
+    assert_eq!(stub.handle("c", &mut || false).unwrap(), "S11");
+    assert_eq!(stub.handle("p0", &mut || false).unwrap(), "0200");
+    stub.cpu.set_halted(false);
+    stub.cpu.set_stop(true);
+    assert_eq!(stub.handle("s", &mut || false).unwrap(), "S11");
+    stub.cpu.set_stop(false);
+    assert_eq!(stub.handle("s", &mut || false).unwrap(), "S05");
+    assert_eq!(stub.cpu.get_acc(), 0x6);
+
+    let mut cpu = Intel4004::new();
+    cpu.set_undefined_policy(UndefinedPolicy::Trap);
+    cpu.load_bytes(&[0xD5, 0xFF]);                                  // LDM 5, undefined
+    let mut stub = GdbStub::new(cpu);
+    assert_eq!(stub.handle("c", &mut || false).unwrap(), "S04");
+}
@@ -0,0 +1,291 @@
+#[cfg(test)]
+use intel4004_emu::assembler::Assembler;
+use intel4004_emu::debugger::Debugger;
+use intel4004_emu::disassembler::{disassemble, disassemble_for, disassemble_source_for};
+use intel4004_emu::intel4004::{Intel4004, RAM_CHIPS};
+use intel4004_emu::opcodes::{Model, Op, DECODE_TABLE_4040};
+
+fn cpu4040(source: &str) -> Intel4004 {
+    let mut assembler = Assembler::new();
+    assembler.model(Model::I4040);
+    let asm = assembler.assemble_str(source, "test").unwrap();
+
+    let mut cpu = Intel4004::with_model(Model::I4040, 32, RAM_CHIPS);
+    cpu.load_bytes(&asm.image);
+    cpu
+}
+
+#[test]
+fn test_decode_table_4040() {
+    assert_eq!(DECODE_TABLE_4040.iter().filter(|i| i.op == Op::Undefined).count(), 3);
+    assert_eq!(Model::I4040.decode(0x01).op, Op::Hlt);
+    assert_eq!(Model::I4040.decode(0x0E).op, Op::Rpm);
+    assert!(Model::I4040.is_undefined(0x0F));
+    assert!(Model::I4004.is_undefined(0x01));
+    assert_eq!(Model::I4040.lookup("SB1"), Some((0x0B, Model::I4040.decode(0x0B).operands)));
+    assert_eq!(Model::I4004.lookup("SB1"), None);
+}
+
+#[test]
+fn test_logic() {
+    let mut cpu = cpu4040("
+        LDM 0xC
+        XCH R4
+        LDM 0xA
+        XCH R6
+        LDM 0x3
+        OR4
+        AN6
+        DCL
+        LCR
+");
+    (0..7).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_acc(), 0xA);                                 // (0x3 | 0xC) & 0xA.
+
+    cpu.clock();
+    cpu.set_acc(0x0);
+    cpu.clock();
+    assert_eq!(cpu.get_acc(), 0xA);
+    assert_eq!(cpu.get_cc(), 0xA);
+}
+
+#[test]
+fn test_register_banks() {
+    let mut cpu = cpu4040("
+        LDM 5
+        XCH R0
+        LDM 9
+        XCH R8
+        SB1
+        LD R0
+        SB0
+");
+    (0..6).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_register_bank(), 1);
+    assert_eq!(cpu.get_acc(), 0x0);                                 // R0 of bank 1.
+    assert_eq!(cpu.get_index()[8].value(), 0x9);                    // R8-R15 are shared.
+    assert_eq!(cpu.get_other_bank()[0].value(), 0x5);
+
+    cpu.clock();
+    assert_eq!(cpu.get_index()[0].value(), 0x5);
+}
+
+#[test]
+fn test_seven_level_stack() {
+    let mut source = String::new();
+    for level in 0..7 {
+        source.push_str(&format!("        JMS sub{}\n        BBL 0\nsub{}:\n", level, level));
+    }
+    source.push_str("        HLT\n");
+    let mut cpu = cpu4040(&source);
+
+    (0..8).for_each(|_| cpu.clock());
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.get_stack(), &[0x002, 0x005, 0x008, 0x00B, 0x00E, 0x011, 0x014]);
+
+    let pc = cpu.get_pc();
+    cpu.clock();
+    assert_eq!(cpu.get_pc(), pc);                                   // Halted CPUs don't execute.
+
+    cpu.set_halted(false);
+    cpu.set_pc(0x014);
+    (0..7).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_pc(), 0x002);
+}
+
+#[test]
+fn test_rom_banks() {
+    let mut cpu = cpu4040("
+        DB1
+        NOP
+");
+    cpu.rom[16].rom[0x001] = 0xD7;                                  // LDM 7 in bank 1, in place of the NOP.
+
+    (0..2).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_rom_bank(), 1);
+    assert_eq!(cpu.get_acc(), 0x7);
+}
+
+#[test]
+fn test_bbs_and_interrupt_enable() {
+    let mut cpu = cpu4040("
+        EIN
+        JMS sub
+        DIN
+sub:    BBS
+");
+    cpu.clock();
+    assert!(cpu.get_interrupts_enabled());
+
+    cpu.set_ram_addrs(0x42);
+    cpu.clock();
+    cpu.clock();
+    assert_eq!(cpu.get_pc(), 0x003);
+    assert_eq!(cpu.get_ram_addrs(), 0x00);                          // SRC saved when no interrupt was taken.
+
+    cpu.clock();
+    assert!(!cpu.get_interrupts_enabled());
+}
+
+#[test]
+fn test_4004_ignores_4040_opcodes() {
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&[0x01, 0x0B]);
+    cpu.clock();
+    cpu.clock();
+    assert!(!cpu.is_halted());
+    assert_eq!(cpu.get_register_bank(), 0);
+    assert_eq!(cpu.get_stack().len(), 3);
+}
+
+#[test]
+fn test_assemble_and_disassemble() {
+    let mut assembler = Assembler::new();
+    assert!(assembler.assemble_str("        HLT\n", "test").is_err());
+
+    assembler.model(Model::I4040);
+    let source = "        HLT\n        BBS\n        OR5\n        AN7\n        DB0\n        SB1\n        EIN\n        RPM\n";
+    let asm = assembler.assemble_str(source, "test").unwrap();
+    assert_eq!(asm.image, vec![0x01, 0x02, 0x05, 0x07, 0x08, 0x0B, 0x0C, 0x0E]);
+
+    assert_eq!(disassemble_for(Model::I4040, &asm.image, 0x005, None), ("SB1".to_string(), 1));
+    assert_eq!(disassemble(&asm.image, 0x005, None), ("DB 0x0B".to_string(), 1));
+
+    let text = disassemble_source_for(Model::I4040, &asm.image, &[0x000], None);
+    assert_eq!(assembler.assemble_str(&text, "disassembly").unwrap().image, asm.image);
+}
+
+#[test]
+fn test_debugger_4040() {
+    let mut debugger = Debugger::new(cpu4040("
+        SB1
+        HLT
+"));
+    assert!(debugger.step().ends_with("SB1"));
+    assert!(debugger.execute("r").unwrap().contains("SB=1 DB=0"));
+}
+
+const INTERRUPTS: &str = "
+        JUN main
+        ORG 3
+isr:    FIM P0, 0x20                                                ; Change SRC, BBS restores it.
+        SRC P0
+        JMS isub
+        BBS
+isub:   BBL 0
+main:   FIM P0, 0x10
+        SRC P0
+        EIN
+        JMS sub1
+        DIN
+        HLT
+sub1:   JMS sub2
+        BBL 0
+sub2:   NOP
+        BBL 0
+";
+
+fn labels(source: &str) -> std::collections::HashMap<String, u16> {
+    let mut assembler = Assembler::new();
+    assembler.model(Model::I4040);
+    assembler.assemble_str(source, "test").unwrap().labels
+}
+
+#[test]
+fn test_nested_interrupt() {
+    let labels = labels(INTERRUPTS);
+    let mut cpu = cpu4040(INTERRUPTS);
+
+    while cpu.get_pc() != labels["sub2"] {
+        cpu.clock();
+    }
+    assert_eq!(cpu.get_stack()[..2], [labels["main"] + 6, labels["sub1"] + 2]);    // Return to DIN and to BBL.
+
+    cpu.set_interrupt(true);
+    cpu.clock();
+    assert_eq!(cpu.get_pc(), 0x003);
+    assert!(cpu.get_interrupt_ack());
+    assert_eq!(cpu.get_stack()[2], labels["sub2"]);                 // Returns to the interrupted instruction.
+
+    // The ISR calls a subroutine of its own, 4 of the 7 levels are in use. INT is still high but INTA holds it off.
+    (0..3).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_pc(), labels["isub"]);
+    assert_eq!(cpu.get_stack()[3], labels["isub"] - 1);
+    cpu.clock();
+    assert_eq!(cpu.get_ram_addrs(), 0x20);
+
+    cpu.clock();                                                    // BBS
+    assert_eq!(cpu.get_pc(), labels["sub2"]);
+    assert_eq!(cpu.get_ram_addrs(), 0x10);
+    assert!(!cpu.get_interrupt_ack());
+
+    // Level triggered, the pin is still high so the interrupt is taken again.
+    cpu.clock();
+    assert_eq!(cpu.get_pc(), 0x003);
+    cpu.set_interrupt(false);
+    while cpu.get_pc() != labels["sub2"] {
+        cpu.clock();
+    }
+
+    // Back to main, DIN masks the pin and HLT waits.
+    while !cpu.is_halted() {
+        cpu.clock();
+    }
+    cpu.set_interrupt(true);
+    cpu.clock();
+    assert!(cpu.is_halted());
+    assert!(!cpu.get_interrupt_ack());
+}
+
+#[test]
+fn test_interrupt_releases_halt() {
+    let mut cpu = cpu4040("
+        JUN main
+        ORG 3
+        BBS
+main:   EIN
+        HLT
+        LDM 1
+");
+    (0..3).for_each(|_| cpu.clock());
+    assert!(cpu.is_halted());
+
+    cpu.set_interrupt(true);
+    cpu.clock();
+    cpu.set_interrupt(false);
+    assert!(!cpu.is_halted());
+    cpu.clock();
+    cpu.clock();
+    assert_eq!(cpu.get_acc(), 0x1);
+}
+
+#[test]
+fn test_stop() {
+    let mut cpu = cpu4040("
+        HLT
+        LDM 1
+        LDM 2
+");
+    cpu.clock();
+    cpu.set_stop(true);
+    cpu.clock();
+    cpu.clock();
+    assert!(cpu.get_stop_ack());
+    assert!(!cpu.is_halted());
+    assert_eq!(cpu.get_pc(), 0x001);
+
+    cpu.set_stop(false);
+    cpu.clock();
+    assert!(!cpu.get_stop_ack());
+    assert_eq!(cpu.get_acc(), 0x1);
+}
+
+#[test]
+fn test_4004_has_no_interrupts() {
+    let mut cpu = Intel4004::new();
+    cpu.set_interrupt(true);
+    cpu.set_stop(true);
+    cpu.clock();
+    assert_eq!(cpu.get_pc(), 0x001);
+    assert!(!cpu.get_interrupt_ack() && !cpu.get_stop_ack());
+}
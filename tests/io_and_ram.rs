@@ -1,6 +1,10 @@
 #[cfg(test)]
 
 use intel4004_emu::intel4004::Intel4004;
+use intel4004_emu::peripherals::Peripheral;
+
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use arbitrary_int::{u4};
 
@@ -12,7 +16,7 @@ fn test_wrm() {
     cpu.set_ram_addrs(0xF);
 
     cpu.decode_op(0xE0);
-    assert_eq!(cpu.ram.ram[0xF], 0x7);
+    assert_eq!(cpu.ram[0].ram[0xF], 0x7);
 }
 
 #[test]
@@ -22,7 +26,7 @@ fn test_wmp() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE1);
-    assert_eq!(cpu.ram.output, 0x7);
+    assert_eq!(cpu.ram[0].output, 0x7);
 }
 
 #[test]
@@ -32,7 +36,7 @@ fn test_wrr() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE2);
-    assert_eq!(cpu.rom.io.value(), 0x7);
+    assert_eq!(cpu.rom[0].io.value(), 0x7);
 }
 
 #[test]
@@ -43,7 +47,7 @@ fn test_wr0() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE4);
-    assert_eq!(cpu.ram.status[(ram_register * 4) as usize], 0x7);
+    assert_eq!(cpu.ram[0].status[(ram_register * 4) as usize], 0x7);
 }
 
 #[test]
@@ -54,7 +58,7 @@ fn test_wr1() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE5);
-    assert_eq!(cpu.ram.status[((ram_register * 4) + 1) as usize], 0x7);
+    assert_eq!(cpu.ram[0].status[((ram_register * 4) + 1) as usize], 0x7);
 }
 
 #[test]
@@ -65,7 +69,7 @@ fn test_wr2() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE6);
-    assert_eq!(cpu.ram.status[((ram_register * 4) + 2) as usize], 0x7);
+    assert_eq!(cpu.ram[0].status[((ram_register * 4) + 2) as usize], 0x7);
 }
 
 #[test]
@@ -76,7 +80,7 @@ fn test_wr3() {
     cpu.set_acc(0x7);
 
     cpu.decode_op(0xE7);
-    assert_eq!(cpu.ram.status[((ram_register * 4) + 3) as usize], 0x7);
+    assert_eq!(cpu.ram[0].status[((ram_register * 4) + 3) as usize], 0x7);
 }
 
 #[test]
@@ -84,19 +88,38 @@ fn test_sbm() {
     let mut cpu = Intel4004::new();
 
     cpu.set_ram_addrs(0x1E);
-    cpu.ram.ram[0x1E] = 0x4;
+    cpu.ram[0].ram[0x1E] = 0x4;
 
     cpu.set_acc(0x7);
     cpu.decode_op(0xE8);
     assert_eq!(cpu.get_acc(), 0x3);
 }
 
+#[test]
+fn test_sbm_borrow() {
+    let mut cpu = Intel4004::new();
+
+    cpu.set_ram_addrs(0x1E);
+    cpu.ram[0].ram[0x1E] = 0x5;
+
+    cpu.set_acc(0x3);
+    cpu.decode_op(0xE8);
+    assert_eq!(cpu.get_acc(), 0xE);
+    assert_eq!(cpu.get_carry(), false);                             // Borrow.
+
+    cpu.set_acc(0x7);
+    cpu.set_carry(true);
+    cpu.decode_op(0xE8);
+    assert_eq!(cpu.get_acc(), 0x1);                                 // ACC + !RAM + !CY.
+    assert_eq!(cpu.get_carry(), true);
+}
+
 #[test]
 fn test_rdm() {
     let mut cpu = Intel4004::new();
 
     cpu.set_ram_addrs(0x1E);
-    cpu.ram.ram[0x1E] = 0x4;
+    cpu.ram[0].ram[0x1E] = 0x4;
 
     cpu.set_acc(0x7);
     cpu.decode_op(0xE9);
@@ -107,7 +130,7 @@ fn test_rdm() {
 fn test_rdr() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.io = u4::new(0x3);
+    cpu.rom[0].io = u4::new(0x3);
 
     cpu.set_acc(0x7);
     cpu.decode_op(0xEA);
@@ -119,7 +142,7 @@ fn test_adm() {
     let mut cpu = Intel4004::new();
 
     cpu.set_ram_addrs(0x1E);
-    cpu.ram.ram[0x1E] = 0x4;
+    cpu.ram[0].ram[0x1E] = 0x4;
 
     cpu.set_acc(0x7);
     cpu.decode_op(0xEB);
@@ -131,7 +154,7 @@ fn test_rd0() {
     let mut cpu = Intel4004::new();
     let ram_register = ((cpu.get_ram_addrs() & 0xF0) >> 4) & 0x03; 
 
-    cpu.ram.status[(ram_register * 4) as usize] = 0x7;
+    cpu.ram[0].status[(ram_register * 4) as usize] = 0x7;
 
     cpu.decode_op(0xEC);
     assert_eq!(cpu.get_acc(), 0x7);
@@ -142,7 +165,7 @@ fn test_rd1() {
     let mut cpu = Intel4004::new();
     let ram_register = ((cpu.get_ram_addrs() & 0xF0) >> 4) & 0x03; 
 
-    cpu.ram.status[((ram_register * 4) + 1) as usize] = 0x7;
+    cpu.ram[0].status[((ram_register * 4) + 1) as usize] = 0x7;
 
     cpu.decode_op(0xED);
     assert_eq!(cpu.get_acc(), 0x7);
@@ -153,7 +176,7 @@ fn test_rd2() {
     let mut cpu = Intel4004::new();
     let ram_register = ((cpu.get_ram_addrs() & 0xF0) >> 4) & 0x03; 
 
-    cpu.ram.status[((ram_register * 4) + 2) as usize] = 0x5;
+    cpu.ram[0].status[((ram_register * 4) + 2) as usize] = 0x5;
 
     cpu.decode_op(0xEE);
     assert_eq!(cpu.get_acc(), 0x5);
@@ -164,8 +187,88 @@ fn test_rd3() {
     let mut cpu = Intel4004::new();
     let ram_register = ((cpu.get_ram_addrs() & 0xF0) >> 4) & 0x03; 
 
-    cpu.ram.status[((ram_register * 4) + 3) as usize] = 0x6;
+    cpu.ram[0].status[((ram_register * 4) + 3) as usize] = 0x6;
 
     cpu.decode_op(0xEF);
     assert_eq!(cpu.get_acc(), 0x6);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_chip_select() {
+    let mut cpu = Intel4004::with_chips(2, 8);
+
+    cpu.set_cc(0x1);                                                // Bank 1.
+    cpu.set_ram_addrs(0x65);                                        // Chip 1, register 2, character 5.
+    cpu.set_acc(0x9);
+    cpu.decode_op(0xE0);
+    cpu.decode_op(0xE6);
+    assert_eq!(cpu.selected_ram_chip(), 5);
+    assert_eq!(cpu.ram[5].ram[0x25], 0x9);
+    assert_eq!(cpu.ram[5].status[0xA], 0x9);
+
+    cpu.set_cc(0x4);                                                // Bank 3 is not populated.
+    cpu.decode_op(0xE0);
+    cpu.decode_op(0xE9);
+    assert_eq!(cpu.get_acc(), 0x0);
+    assert!(cpu.ram[13].ram.iter().all(|c| *c == 0));
+
+    cpu.load_bytes(&[0xD1; 0x300]);
+    assert_eq!(cpu.fetch_u8(0x1FF), 0xD1);
+    assert_eq!(cpu.fetch_u8(0x200), 0x00);
+    assert_eq!(cpu.rom_image().len(), 0x200);
+}
+
+struct Recorder {
+    writes: Rc<RefCell<Vec<(usize, u8)>>>,
+}
+
+impl Peripheral for Recorder {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        self.writes.borrow_mut().push((chip, value));
+    }
+
+    fn rom_port_read(&mut self, chip: usize) -> Option<u8> {
+        Some(chip as u8 + 1)
+    }
+}
+
+#[test]
+fn test_peripheral() {
+    let mut cpu = Intel4004::new();
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    cpu.attach_peripheral(Box::new(Recorder { writes: writes.clone() }));
+
+    cpu.set_ram_addrs(0x30);
+    cpu.set_acc(0x7);
+    cpu.decode_op(0xE2);
+    assert_eq!(cpu.rom[3].io.value(), 0x7);
+    assert_eq!(*writes.borrow(), vec![(3, 0x7)]);
+
+    cpu.decode_op(0xEA);
+    assert_eq!(cpu.get_acc(), 0x4);
+}
+
+struct Latch {
+    rom: [u8; 16],
+}
+
+impl Peripheral for Latch {
+    fn rom_port_write(&mut self, chip: usize, value: u8) {
+        self.rom[chip] = value;
+    }
+}
+
+#[test]
+fn test_clock_with() {
+    let mut cpu = Intel4004::new();
+    let writes = Rc::new(RefCell::new(Vec::new()));
+    cpu.attach_peripheral(Box::new(Recorder { writes: writes.clone() }));
+    cpu.load_bytes(&[0xD6, 0xE2]);                                  // LDM 6, WRR
+
+    let mut latch = Latch { rom: [0; 16] };
+    cpu.clock_with(&mut latch);
+    cpu.clock_with(&mut latch);
+    assert_eq!(latch.rom[0], 0x6);
+    assert!(writes.borrow().is_empty());                            // The attached peripheral is bypassed.
+    assert!(cpu.take_peripheral().is_some());
+}
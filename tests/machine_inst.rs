@@ -8,7 +8,7 @@ use arbitrary_int::{u4};
 fn test_jcn() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x14, 0x03, 0xFA, 0xF2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -37,7 +37,7 @@ fn test_jcn() {
 fn test_fim() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x24, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -75,7 +75,7 @@ fn test_src() {
 fn test_fin() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x24, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -114,7 +114,7 @@ fn test_jin() {
 fn test_jun() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x40, 0x05, 0xFA, 0xF2, 0x00, 0xF2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -143,7 +143,7 @@ fn test_jun() {
 fn test_jms() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x00, 0x50, 0x05, 0xFA, 0xF2, 0xF2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -167,7 +167,33 @@ fn test_jms() {
     cpu.clock();
 
     assert_eq!(cpu.get_acc(), 0x1);
-    assert_eq!(cpu.get_stack()[0], 0x2);
+    assert_eq!(cpu.get_stack()[0], 0x3);                 // Return address, after the 2 bytes of the JMS.
+}
+
+#[test]
+fn test_jms_bbl() {
+    let mut cpu = Intel4004::new();
+
+    cpu.set_pc(0x010);
+    cpu.decode_op(0x50);                                            // JMS 0x000, the ROM is empty.
+    cpu.decode_op(0xC5);
+
+    assert_eq!(cpu.get_pc(), 0x012);
+    assert_eq!(cpu.get_acc(), 0x5);
+}
+
+#[test]
+fn test_stack_wraps() {
+    let mut cpu = Intel4004::new();
+
+    for pc in [0x010, 0x020, 0x030, 0x040] {                        // The 4th JMS overwrites the oldest level.
+        cpu.set_pc(pc);
+        cpu.decode_op(0x50);
+    }
+    for pc in [0x042, 0x032, 0x022] {
+        cpu.decode_op(0xC0);
+        assert_eq!(cpu.get_pc(), pc);
+    }
 }
 
 #[test]
@@ -184,7 +210,7 @@ fn test_inc() {
 fn test_isz() {
     let mut cpu = Intel4004::new();
 
-    cpu.rom.rom = [
+    cpu.rom[0].rom = [
         0x73, 0x05, 0x05, 0xFA, 0xF2, 0xF2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
@@ -272,3 +298,85 @@ fn test_ldm() {
  
     assert_eq!(cpu.get_acc(), 0x9);
 }
+
+#[test]
+fn test_jcn_invert() {
+    let mut cpu = Intel4004::new();
+
+    cpu.rom[0].rom[0x01] = 0x40;
+    cpu.set_acc(0x3);
+    cpu.decode_op(0x14);                                            // JCN Z
+    assert_eq!(cpu.get_pc(), 0x002);
+
+    cpu.set_pc(0x000);
+    cpu.decode_op(0x1C);                                            // JCN NZ
+    assert_eq!(cpu.get_pc(), 0x040);
+}
+
+#[test]
+fn test_jcn_test_pin() {
+    let mut cpu = Intel4004::new();
+
+    cpu.rom[0].rom[0x01] = 0x40;
+    cpu.decode_op(0x11);                                            // JCN T, TEST is low.
+    assert_eq!(cpu.get_pc(), 0x040);
+
+    cpu.set_pc(0x000);
+    cpu.decode_op(0x19);                                            // JCN NT
+    assert_eq!(cpu.get_pc(), 0x002);
+}
+
+#[test]
+fn test_pc_wraps() {
+    let mut cpu = Intel4004::new();
+
+    cpu.set_pc(0xFFF);
+    cpu.clock();                                                    // NOP at the last address.
+
+    assert_eq!(cpu.get_pc(), 0x000);
+}
+
+#[test]
+fn test_fin_jin_page() {
+    let mut cpu = Intel4004::new();
+
+    cpu.rom[1].rom[0x20] = 0x5A;
+    cpu.set_reg_pair(0, 0x20);
+    cpu.set_pc(0x150);
+    cpu.decode_op(0x30);                                            // FIN P0 reads from page 1.
+    assert_eq!(cpu.get_reg_pair(0), 0x5A);
+
+    cpu.set_pc(0x2FF);
+    cpu.decode_op(0x31);                                            // JIN P0 at the end of page 2.
+    assert_eq!(cpu.get_pc(), 0x35A);
+}
+
+#[test]
+fn test_jcn_invert_and_page() {
+    let mut cpu = Intel4004::new();
+
+    cpu.load_bytes(&[0x00; 0x2FE]);
+    cpu.rom[2].rom[0xFF] = 0x40;
+    cpu.set_pc(0x2FE);
+    cpu.set_acc(0x3);
+    cpu.decode_op(0x1C);                                            // JCN NZ, at the end of page 2.
+
+    assert_eq!(cpu.get_pc(), 0x340);
+}
+
+#[test]
+fn test_isz_wraps() {
+    let mut cpu = Intel4004::new();
+
+    cpu.rom[1].rom[0x21] = 0x10;
+    cpu.set_pc(0x120);
+    cpu.set_index(3, u4::new(0xF));
+    cpu.decode_op(0x73);
+
+    assert_eq!(cpu.get_index()[3].value(), 0x0);
+    assert_eq!(cpu.get_pc(), 0x122);
+
+    cpu.set_pc(0x120);
+    cpu.decode_op(0x73);
+    assert_eq!(cpu.get_pc(), 0x110);
+}
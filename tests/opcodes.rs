@@ -0,0 +1,30 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::disassembler::disassemble;
+use intel4004_emu::opcodes::{decode, is_undefined, lookup, Op, Operands, DECODE_TABLE};
+
+#[test]
+fn test_decode_table() {
+    assert_eq!(DECODE_TABLE.iter().filter(|i| i.op == Op::Undefined).count(), 17);
+    assert!((0x01..=0x0F).chain([0xFE, 0xFF]).all(is_undefined));
+
+    let fim = decode(0x2C);
+    assert_eq!((fim.op, fim.op.mnemonic(), fim.operands, fim.opa, fim.size), (Op::Fim, "FIM", Operands::PairData8, 0xC, 2));
+    assert_eq!(decode(0x2D).op, Op::Src);
+    assert_eq!(decode(0xEB).op.mnemonic(), "ADM");
+
+    assert_eq!(lookup("SRC"), Some((0x21, Operands::Pair)));
+    assert_eq!(lookup("JIN"), Some((0x31, Operands::Pair)));
+    assert_eq!(lookup(""), None);
+}
+
+#[test]
+fn test_every_opcode_round_trips() {
+    for op_code in (0..=0xFFu8).filter(|op| !is_undefined(*op)) {
+        let rom = [op_code, 0x00];
+        let (text, size) = disassemble(&rom, 0x000, None);
+
+        let asm = assemble(&format!("        {}\n", text)).unwrap();
+        assert_eq!(&asm.image[..], &rom[..size as usize], "{}", text);
+    }
+}
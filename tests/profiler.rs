@@ -0,0 +1,73 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::intel4004::Intel4004;
+
+const PROGRAM: &str = "
+main:   JMS outer
+        JMS leaf
+done:   JUN done
+outer:  LDM 2
+        JMS leaf
+        BBL 0
+leaf:   IAC
+        BBL 1
+";
+
+fn profile(steps: usize) -> (Intel4004, Vec<u8>, intel4004_emu::symbols::SymbolTable) {
+    let asm = assemble(PROGRAM).unwrap();
+
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+    cpu.enable_profiler();
+
+    for _ in 0..steps {
+        cpu.clock();
+    }
+    (cpu, asm.image.clone(), asm.symbols())
+}
+
+#[test]
+fn test_profile_addresses() {
+    let (cpu, _, symbols) = profile(14);
+    let profiler = cpu.profiler().unwrap();
+    let leaf = symbols.address_of("leaf").unwrap();
+
+    assert_eq!(profiler.executions(leaf), 2);
+    assert_eq!(profiler.cycles(leaf), 2);
+    assert_eq!(profiler.executions(0x000), 1);
+    assert_eq!(profiler.cycles(0x000), 2);
+    assert_eq!(profiler.executions(symbols.address_of("done").unwrap()), 5);
+
+    // JMS, LDM, JMS, IAC, BBL, BBL, JMS, IAC, BBL then 5 times JUN.
+    assert_eq!(profiler.total_cycles(), 2 + 1 + 2 + 1 + 1 + 1 + 2 + 1 + 1 + 5 * 2);
+}
+
+#[test]
+fn test_profile_subroutines() {
+    let (cpu, _, symbols) = profile(14);
+    let profiler = cpu.profiler().unwrap();
+
+    let main = profiler.subroutine(0x000).unwrap();
+    assert_eq!((main.calls, main.inclusive, main.exclusive), (1, 22, 2 + 2 + 10));
+
+    let outer = profiler.subroutine(symbols.address_of("outer").unwrap()).unwrap();
+    assert_eq!((outer.calls, outer.inclusive, outer.exclusive), (1, 6, 4));
+
+    let leaf = profiler.subroutine(symbols.address_of("leaf").unwrap()).unwrap();
+    assert_eq!((leaf.calls, leaf.inclusive, leaf.exclusive), (2, 4, 4));
+}
+
+#[test]
+fn test_profile_report() {
+    let (mut cpu, rom, symbols) = profile(14);
+    let profiler = cpu.take_profiler().unwrap();
+    assert!(cpu.profiler().is_none());
+
+    let report = profiler.report(&rom, Some(&symbols), 1);
+    assert!(report.starts_with("Total: 22 cycles (237.6 us)\n"));
+    assert!(report.contains("\nmain                      1          22  100.0%         14   63.6%\n"));
+    assert!(report.ends_with("004           5        10   45.5%  JUN done\n"));
+
+    let annotated = profiler.annotated_disassembly(&rom, Some(&symbols));
+    assert!(annotated.contains("                            leaf:\n         2         2   9.1%  00A  IAC\n"));
+}
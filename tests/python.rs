@@ -0,0 +1,33 @@
+#![cfg(feature = "python")]
+#[cfg(test)]
+use intel4004_emu::python::intel4004_emu;
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+
+/// Run tests/python in an embedded interpreter with the module registered as a builtin.
+#[test]
+fn test_python() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/python/test_intel4004.py");
+    let source = CString::new(fs::read_to_string(&path).unwrap()).unwrap();
+    let file_name = CString::new(path.to_str().unwrap()).unwrap();
+
+    pyo3::append_to_inittab!(intel4004_emu);
+    pyo3::prepare_freethreaded_python();
+
+    Python::with_gil(|py| -> PyResult<()> {
+        let module = PyModule::from_code(py, &source, &file_name, c"test_intel4004")?;
+        let unittest = py.import("unittest")?;
+        let suite = unittest.getattr("defaultTestLoader")?.call_method1("loadTestsFromModule", (module,))?;
+
+        let options = PyDict::new(py);
+        options.set_item("verbosity", 2)?;
+        let result = unittest.getattr("TextTestRunner")?.call((), Some(&options))?.call_method1("run", (suite,))?;
+        assert!(result.call_method0("wasSuccessful")?.extract::<bool>()?, "Python tests failed");
+        Ok(())
+    }).unwrap();
+}
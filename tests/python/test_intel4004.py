@@ -0,0 +1,76 @@
+"""Tests of the Python bindings. `cargo test --features python` runs them in an embedded interpreter; against a built
+module they run with `python3 -m unittest discover tests/python`."""
+
+import unittest
+
+from intel4004_emu import Intel4004, assemble
+
+PROGRAM = """
+        FIM P0, 0x40
+        SRC P0
+        RDR
+        WMP
+        IAC
+        WRR
+        WRM
+done:   JUN done
+"""
+
+
+class TestIntel4004(unittest.TestCase):
+    def setUp(self):
+        self.cpu = Intel4004()
+        self.cpu.load_rom(assemble(PROGRAM))
+
+    def test_registers(self):
+        self.cpu.step()
+        self.assertEqual(self.cpu.pc, 2)
+        self.assertEqual(self.cpu.get_pair(0), 0x40)
+        self.assertEqual(list(self.cpu.registers[:2]), [4, 0])
+
+        self.cpu.acc = 9
+        self.cpu.carry = True
+        self.cpu.set_reg(15, 3)
+        self.cpu.set_pair(3, 0xA5)
+        self.assertEqual((self.cpu.acc, self.cpu.carry), (9, True))
+        self.assertEqual((self.cpu.get_reg(15), self.cpu.get_reg(6), self.cpu.get_reg(7)), (3, 0xA, 5))
+        self.assertEqual(self.cpu.stack, (0, 0, 0))
+
+        with self.assertRaises(ValueError):
+            self.cpu.acc = 16
+        with self.assertRaises(IndexError):
+            self.cpu.get_reg(16)
+
+    def test_run_and_ports(self):
+        writes = []
+        self.cpu.set_io(ram_write=lambda chip, value: writes.append(("ram", chip, value)),
+                        rom_write=lambda chip, value: writes.append(("rom", chip, value)),
+                        rom_read=lambda chip: 6 if chip == 4 else None)
+
+        result = self.cpu.run(max_cycles=1000)
+        self.assertEqual(result, {"reason": "halted at 008", "limit": False, "instructions": 8, "cycles": 10})
+        self.assertEqual(writes, [("ram", 1, 6), ("rom", 4, 7)])
+        self.assertEqual((self.cpu.ram_port(1), self.cpu.rom_port(4)), (6, 7))
+        self.assertEqual(self.cpu.read_ram(1, 0), 7)
+        self.assertEqual(self.cpu.ram_characters(1)[:2], bytes([7, 0]))
+
+    def test_stop_conditions(self):
+        self.assertEqual(self.cpu.run(stop_at=[0x004])["reason"], "reached 004")
+        self.assertEqual(self.cpu.run(watch="ram:64")["reason"], "RAM character 64 changed 0 -> 1")
+        self.assertTrue(self.cpu.run(max_instructions=3, halt=False)["limit"])
+
+    def test_callback_errors(self):
+        def broken(chip, value):
+            raise RuntimeError("bench failed")
+
+        self.cpu.set_io(ram_write=broken)
+        with self.assertRaisesRegex(RuntimeError, "bench failed"):
+            self.cpu.run()
+
+    def test_assemble_errors(self):
+        with self.assertRaises(ValueError):
+            assemble("LDM 16")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,103 @@
+#[cfg(test)]
+use intel4004_emu::assembler::{assemble, Assembler};
+use intel4004_emu::intel4004::{Fill, Intel4004, RAM_CHIPS};
+use intel4004_emu::opcodes::Model;
+use intel4004_emu::peripherals::Peripheral;
+use intel4004_emu::sanitizer::Storage;
+use intel4004_emu::strict::StrictMode;
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+const PROGRAM: &str = "
+        FIM P0, 0x25
+        SRC P0
+        LDM 9
+        WRM
+        WR1
+        WMP
+        WRR
+        STC
+        DCL
+        JMS sub
+sub:    NOP
+";
+
+struct Resets(Rc<Cell<usize>>);
+
+impl Peripheral for Resets {
+    fn reset(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[test]
+fn test_reset() {
+    let image = assemble(PROGRAM).unwrap().image;
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&image);
+    let resets = Rc::new(Cell::new(0));
+    cpu.attach_peripheral(Box::new(Resets(resets.clone())));
+    cpu.set_test(true);
+
+    (0..10).for_each(|_| cpu.clock());
+    assert_eq!(cpu.get_stack()[0], 0x00C);
+    assert_eq!(cpu.ram[0].ram[0x25], 0x9);
+
+    cpu.reset();
+    assert_eq!(resets.get(), 1);
+    assert_eq!((cpu.get_pc(), cpu.get_acc(), cpu.get_carry()), (0x000, 0x0, false));
+    assert_eq!((cpu.get_ram_addrs(), cpu.get_cc()), (0x00, 0x0));
+    assert!(cpu.get_index().iter().all(|r| r.value() == 0));
+    assert_eq!(cpu.get_stack(), &[0, 0, 0]);
+    assert!(cpu.ram.iter().all(|chip| chip.ram.iter().chain(chip.status.iter()).all(|c| *c == 0) && chip.output == 0));
+    assert!(cpu.rom.iter().all(|chip| chip.io.value() == 0));
+    assert!(cpu.get_test());                                        // Pins are driven from outside.
+    assert_eq!(cpu.rom_image()[..image.len()], image[..]);          // ROM survives.
+
+    cpu.clock();
+    assert_eq!(cpu.get_reg_pair(0), 0x25);
+}
+
+#[test]
+fn test_reset_4040() {
+    let mut assembler = Assembler::new();
+    assembler.model(Model::I4040);
+    let image = assembler.assemble_str("
+        LDM 3
+        XCH R0
+        SB1
+        EIN
+        DB1
+", "test").unwrap().image;
+    let mut cpu = Intel4004::with_model(Model::I4040, 32, RAM_CHIPS);
+    cpu.load_bytes(&image);
+
+    (0..5).for_each(|_| cpu.clock());
+    assert_eq!((cpu.get_register_bank(), cpu.get_rom_bank()), (1, 1));
+
+    cpu.reset();
+    assert_eq!((cpu.get_register_bank(), cpu.get_rom_bank()), (0, 0));
+    assert!(!cpu.get_interrupts_enabled());
+    assert!(cpu.get_other_bank().iter().all(|r| r.value() == 0));
+    assert_eq!(cpu.get_stack().len(), 7);
+    assert_eq!(cpu.fetch_u8(0x000), 0xD3);
+}
+
+#[test]
+fn test_power_on() {
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&assemble(PROGRAM).unwrap().image);
+    cpu.enable_sanitizer(StrictMode::Log);
+    (0..3).for_each(|_| cpu.clock());
+
+    cpu.power_on(Fill::Pattern(0x6));
+    assert_eq!(cpu.get_pc(), 0x000);
+    assert!(cpu.get_index().iter().all(|r| r.value() == 0x6));
+    assert_eq!((cpu.ram[0].ram[0], cpu.ram[0].output), (0x6, 0x6));
+    assert!(!cpu.sanitizer().unwrap().is_defined(&cpu, Storage::Register(0)));
+
+    cpu.reset();
+    assert_eq!(cpu.ram[0].ram[0], 0x0);
+    assert!(cpu.sanitizer().unwrap().is_defined(&cpu, Storage::Ram(0)));
+}
@@ -0,0 +1,137 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::intel4004::{Intel4004, UndefinedPolicy, RAM_CHIPS};
+use intel4004_emu::opcodes::Model;
+use intel4004_emu::runner::{run, ExitReason, Port, RunLimits, Watch};
+
+const PROGRAM: &str = "
+        FIM P0, 0x05
+        SRC P0
+        LDM 9
+        WRM
+        XCH R4
+        LDM 3
+        WRR
+        JMS sub
+done:   JUN done
+sub:    LD R4
+        WMP
+        BBL 0
+";
+
+fn cpu() -> Intel4004 {
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&assemble(PROGRAM).unwrap().image);
+    cpu
+}
+
+#[test]
+fn test_run_until_halt() {
+    let mut cpu = cpu();
+    let mut limits = RunLimits::new();
+    limits.halt_on_self_jump(true);
+    limits.max_cycles(1000);
+
+    let result = run(&mut cpu, &limits);
+    assert_eq!(result.reason, ExitReason::Halt(0x00A));
+    assert_eq!((result.instructions, result.cycles), (12, 15));
+    assert_eq!(result.state.pc, 0x00A);
+    assert_eq!(result.state.index[4], 0x9);
+    assert_eq!(result.state.ram_addrs, 0x05);
+    assert!(!result.reason.is_limit());
+    assert!(result.to_text().starts_with("stopped: halted at 00A\ninstructions: 12\ncycles: 15\nPC=00A ACC=0"));
+}
+
+#[test]
+fn test_run_limits() {
+    let mut limits = RunLimits::new();
+    limits.max_cycles(4);
+    assert_eq!(run(&mut cpu(), &limits).reason, ExitReason::CycleLimit);
+
+    let mut limits = RunLimits::new();
+    limits.max_instructions(100);
+    let result = run(&mut cpu(), &limits);
+    assert_eq!((result.reason, result.instructions), (ExitReason::InstructionLimit, 100));
+    assert!(result.reason.is_limit());
+
+    limits.stop_at(0x00D);
+    assert_eq!(run(&mut cpu(), &limits).reason, ExitReason::Address(0x00D));
+}
+
+#[test]
+fn test_run_ports_and_watchpoints() {
+    let mut limits = RunLimits::new();
+    limits.watch(Watch::Ram(5));
+    limits.max_instructions(100);
+    let result = run(&mut cpu(), &limits);
+    assert_eq!(result.reason, ExitReason::Watchpoint(Watch::Ram(5), 0, 9));
+    assert_eq!(result.reason.to_string(), "RAM character 5 changed 0 -> 9");
+
+    let mut limits = RunLimits::new();
+    limits.stop_on_port(Port::Ram(0), 0x9);
+    limits.max_instructions(100);
+    let result = run(&mut cpu(), &limits);
+    assert_eq!((result.reason, result.state.pc), (ExitReason::Port(Port::Ram(0), 0x9), 0x00E));
+}
+
+#[test]
+fn test_parse_port() {
+    assert_eq!(Port::parse_condition("rom:0x1F=0xF"), Some((Port::Rom(31), 0xF)));
+    assert_eq!(Port::parse_condition("ram=3"), Some((Port::Ram(0), 3)));
+    assert_eq!(Port::parse_condition("rom:32=1"), None);
+    assert_eq!(Port::parse_condition("ram:1=16"), None);            // Not truncated to 0.
+}
+
+#[test]
+fn test_run_halted() {
+    let mut cpu = Intel4004::with_model(Model::I4040, 32, RAM_CHIPS);
+    cpu.load_bytes(&[0xD5, 0x01]);                                  // LDM 5, HLT
+    let mut limits = RunLimits::new();
+    limits.max_cycles(10);
+
+    let result = run(&mut cpu, &limits);
+    assert_eq!(result.reason, ExitReason::CycleLimit);
+    assert_eq!((result.instructions, result.cycles), (2, 10));      // Halted clocks count one cycle each.
+    assert_eq!((result.state.pc, result.state.acc), (0x002, 0x5));
+}
+
+#[test]
+fn test_undefined_policies() {
+    let image = assemble("
+        LDM 3
+        DB 0xFE
+        IAC
+done:   JUN done
+").unwrap().image;
+    let mut limits = RunLimits::new();
+    limits.halt_on_self_jump(true);
+    limits.max_cycles(100);
+
+    for policy in [UndefinedPolicy::Nop, UndefinedPolicy::Silicon] {
+        let mut cpu = Intel4004::new();
+        cpu.set_undefined_policy(policy);
+        cpu.load_bytes(&image);
+
+        let result = run(&mut cpu, &limits);
+        assert_eq!(result.reason, ExitReason::Halt(0x003));
+        assert_eq!((result.undefined, result.state.acc), (1, 0x4));
+        assert!(result.to_text().contains("undefined opcodes: 1"));
+    }
+
+    let mut cpu = Intel4004::new();
+    cpu.set_undefined_policy(UndefinedPolicy::Trap);
+    cpu.load_bytes(&image);
+
+    let result = run(&mut cpu, &limits);
+    assert_eq!(result.reason, ExitReason::Undefined(0x001, 0xFE));
+    assert_eq!((result.instructions, result.state.pc), (1, 0x001));
+    assert_eq!(cpu.get_undefined_count(), 1);
+
+    cpu.clock();                                                    // Stays trapped.
+    assert_eq!((cpu.get_pc(), cpu.get_acc()), (0x001, 0x3));
+
+    cpu.clear_trap();
+    cpu.set_pc(0x002);
+    cpu.clock();
+    assert_eq!(cpu.get_acc(), 0x4);
+}
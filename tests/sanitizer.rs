@@ -0,0 +1,162 @@
+#[cfg(test)]
+mod common;
+
+use intel4004_emu::intel4004::{Fill, Intel4004, RAM_CHIPS, ROM_CHIPS};
+use intel4004_emu::peripherals::Peripheral;
+use intel4004_emu::runner::{run, ExitReason, RunLimits};
+use intel4004_emu::sanitizer::Storage;
+use intel4004_emu::strict::StrictMode;
+
+fn sanitized(source: &str, mode: StrictMode) -> Intel4004 {
+    let mut cpu = common::assembled(source, ROM_CHIPS, RAM_CHIPS);
+    cpu.enable_sanitizer(mode);
+    cpu
+}
+
+fn messages(cpu: &Intel4004) -> Vec<(u16, String)> {
+    common::messages(cpu.sanitizer().unwrap().diagnostics())
+}
+
+#[test]
+fn test_registers() {
+    let mut cpu = sanitized("
+        LDM 3
+        XCH R2
+        LD R2
+        ADD R3
+        FIM P2, 0x12
+        SUB R5
+loop:   LD R7
+        JUN loop
+", StrictMode::Log);
+    (0..10).for_each(|_| cpu.clock());
+
+    assert_eq!(messages(&cpu), vec![
+        (0x001, "reads R2 before it was written".to_string()),      // XCH hands the old R2 to the accumulator.
+        (0x003, "reads R3 before it was written".to_string()),
+        (0x007, "reads R7 before it was written".to_string()),      // Once, not on every pass of the loop.
+    ]);
+    assert_eq!(cpu.sanitizer().unwrap().diagnostics()[1].instruction, "ADD R3");
+    assert!(cpu.sanitizer().unwrap().is_defined(&cpu, Storage::Register(5)));
+}
+
+#[test]
+fn test_ram() {
+    let mut cpu = sanitized("
+        FIM P0, 0x45
+        SRC P0
+        RDM
+        WRM
+        ADM
+        RD2
+        WR2
+        SBM
+        RD2
+", StrictMode::Log);
+    (0..9).for_each(|_| cpu.clock());
+
+    assert_eq!(messages(&cpu), vec![
+        (0x003, "reads RAM 1 character 5 before it was written".to_string()),
+        (0x006, "reads RAM 1 status character 2 before it was written".to_string()),
+    ]);
+}
+
+#[test]
+fn test_address_registers() {
+    let mut cpu = sanitized("
+        INC R3
+        ISZ R4, next
+next:   SRC P3
+        FIN P2
+        FIM P0, 0x08
+        JIN P0
+done:   JUN done
+", StrictMode::Log);
+    (0..7).for_each(|_| cpu.clock());
+
+    assert_eq!(messages(&cpu), vec![
+        (0x000, "reads R3 before it was written".to_string()),
+        (0x001, "reads R4 before it was written".to_string()),
+        (0x003, "reads R6 before it was written".to_string()),
+        (0x003, "reads R7 before it was written".to_string()),
+        (0x004, "reads R0 before it was written".to_string()),      // FIN reads its address from P0.
+        (0x004, "reads R1 before it was written".to_string()),
+    ]);
+    assert_eq!(cpu.get_pc(), 0x008);
+    assert!(cpu.sanitizer().unwrap().is_defined(&cpu, Storage::Register(3)));
+}
+
+#[test]
+fn test_ports() {
+    let source = "
+        FIM P0, 0x30
+        SRC P0
+        RDR
+        WRR
+        WMP
+        RDR
+";
+    let mut cpu = sanitized(source, StrictMode::Log);
+    (0..6).for_each(|_| cpu.clock());
+
+    let sanitizer = cpu.sanitizer().unwrap();
+    assert!(sanitizer.is_defined(&cpu, Storage::RomPort(3)));
+    assert!(sanitizer.is_defined(&cpu, Storage::RamPort(0)));
+    assert!(!sanitizer.is_defined(&cpu, Storage::RomPort(0)));
+    assert_eq!(messages(&cpu), vec![(0x003, "reads ROM 3 port before it was written".to_string())]);
+
+    let mut cpu = sanitized(source, StrictMode::Log);
+    cpu.attach_peripheral(Box::new(Inputs));
+    (0..3).for_each(|_| cpu.clock());
+    assert!(messages(&cpu).is_empty());                             // The peripheral drives the lines.
+}
+
+struct Inputs;
+
+impl Peripheral for Inputs {
+    fn rom_port_read(&mut self, _chip: usize) -> Option<u8> {
+        Some(0x5)
+    }
+}
+
+#[test]
+fn test_halt() {
+    let mut cpu = sanitized("
+        LDM 1
+        LD R0
+        LDM 2
+done:   JUN done
+", StrictMode::Halt);
+    let mut limits = RunLimits::new();
+    limits.halt_on_self_jump(true);
+    limits.max_cycles(100);
+
+    let result = run(&mut cpu, &limits);
+    assert_eq!(result.reason, ExitReason::Uninitialized(0x001));
+    assert_eq!(result.state.pc, 0x002);
+
+    cpu.sanitizer_mut().unwrap().resume();
+    assert_eq!(run(&mut cpu, &limits).reason, ExitReason::Halt(0x003));
+    assert_eq!(cpu.get_acc(), 0x2);
+}
+
+#[test]
+fn test_fill() {
+    let mut cpu = Intel4004::new();
+    cpu.fill(Fill::Pattern(0xA));
+    assert!(cpu.get_index().iter().all(|r| r.value() == 0xA));
+    assert!(cpu.ram.iter().all(|chip| chip.ram.iter().chain(chip.status.iter()).all(|c| *c == 0xA)));
+    assert_eq!((cpu.ram[3].output, cpu.rom[7].io.value()), (0xA, 0xA));
+
+    let mut other = Intel4004::new();
+    cpu.fill(Fill::Random(42));
+    other.fill(Fill::Random(42));
+    assert_eq!(cpu.ram[0].ram, other.ram[0].ram);                   // Seeded, runs repeat.
+    assert!(cpu.ram[0].ram.iter().any(|c| *c != cpu.ram[0].ram[0]));
+    assert!(cpu.ram.iter().all(|chip| chip.ram.iter().all(|c| *c < 16)));
+
+    assert_eq!(Fill::parse("random:0x10"), Some(Fill::Random(16)));
+    assert_eq!(Fill::parse("pattern:5"), Some(Fill::Pattern(5)));
+    assert_eq!(Fill::parse("pattern:16"), None);
+    assert_eq!(Fill::parse("zero"), Some(Fill::Zero));
+}
@@ -0,0 +1,33 @@
+#![cfg(feature = "scripting")]
+#[cfg(test)]
+use intel4004_emu::script::Script;
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_scripts() {
+    let script = Script::new();
+    let mut paths: Vec<_> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/scripts")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "rhai"))
+        .collect();
+    paths.sort();
+
+    assert!(!paths.is_empty());
+    for path in paths {
+        script.run_file(&path).unwrap_or_else(|e| panic!("{}", e));
+    }
+}
+
+#[test]
+fn test_failures() {
+    let script = Script::new();
+
+    let error = script.run("let m = machine();\nassert_eq(m.acc, 1);").unwrap_err();
+    assert!(error.contains("left: 0") && error.contains("line 2"), "{}", error);
+
+    assert!(script.run("machine().set_reg(16, 0);").unwrap_err().contains("register 16 out of range 0-15"));
+    assert!(script.run("machine().assemble(\"LDM 16\");").is_err());
+    assert!(script.run("machine().run(#{ stop_at: \"nowhere\" });").unwrap_err().contains("unknown address 'nowhere'"));
+}
@@ -0,0 +1,121 @@
+#[cfg(test)]
+mod common;
+
+use intel4004_emu::debugger::Debugger;
+use intel4004_emu::intel4004::Intel4004;
+use intel4004_emu::runner::{run, ExitReason, RunLimits};
+use intel4004_emu::strict::StrictMode;
+
+fn strict_cpu(source: &str, mode: StrictMode, rom_chips: usize, ram_chips: usize) -> Intel4004 {
+    let mut cpu = common::assembled(source, rom_chips, ram_chips);
+    cpu.enable_strict(mode);
+    cpu
+}
+
+fn messages(cpu: &Intel4004) -> Vec<(u16, String)> {
+    common::messages(cpu.strict().unwrap().diagnostics())
+}
+
+#[test]
+fn test_ram_and_ports() {
+    let mut cpu = strict_cpu("
+        WRR
+        FIM P0, 0x40
+        SRC P0
+        WRM
+        FIM P0, 0x20
+        SRC P0
+        RDR
+        WPM
+", StrictMode::Log, 1, 1);
+    (0..8).for_each(|_| cpu.clock());
+
+    assert_eq!(messages(&cpu), vec![
+        (0x000, "RAM or port access before any SRC selected a chip".to_string()),
+        (0x004, "RAM chip 1 is not populated".to_string()),
+        (0x008, "ROM chip 2 is not populated".to_string()),
+        (0x009, "no 4008/4009 or 4289 program memory is connected".to_string()),
+    ]);
+    assert_eq!(cpu.strict().unwrap().diagnostics()[1].to_string(), "004  WRM              RAM chip 1 is not populated");
+    assert!(!cpu.strict().unwrap().is_halted());                    // Log mode only records.
+}
+
+#[test]
+fn test_input_lines() {
+    let mut cpu = strict_cpu("
+        FIM P0, 0x10
+        SRC P0
+        WRR
+", StrictMode::Log, 16, 16);
+    cpu.rom[1].inputs = 0b0011;
+    (0..3).for_each(|_| cpu.clock());
+
+    assert_eq!(messages(&cpu), vec![(0x003, "ROM chip 1 lines 0011 are configured as inputs".to_string())]);
+}
+
+#[test]
+fn test_page_boundary_and_stack() {
+    let mut cpu = strict_cpu("
+        JMS sub1
+        JUN fin
+sub1:   JMS sub2
+        BBL 0
+sub2:   JMS sub3
+        BBL 0
+sub3:   JMS sub4
+        BBL 0
+sub4:   BBL 0
+        ORG 0xFF
+fin:    FIN P0
+", StrictMode::Log, 16, 16);
+    (0..4).for_each(|_| cpu.clock());
+    cpu.set_pc(0x0FF);                                              // The lost return address would loop forever.
+    cpu.clock();
+
+    assert_eq!(messages(&cpu), vec![
+        (0x00A, "JMS nests 4 deep, the stack has 3 levels and loses a return address".to_string()),
+        (0x0FF, "FIN at the end of page 0 uses page 1".to_string()),
+    ]);
+}
+
+#[test]
+fn test_halt_mode() {
+    let mut cpu = strict_cpu("
+        RDM
+        LDM 1
+done:   JUN done
+", StrictMode::Halt, 16, 16);
+    cpu.clock();
+    cpu.clock();
+    assert!(cpu.strict().unwrap().is_halted());
+    assert_eq!(cpu.get_pc(), 0x001);                                // RDM ran, LDM waits.
+
+    cpu.strict_mut().unwrap().resume();
+    cpu.clock();
+    assert_eq!(cpu.get_acc(), 0x1);
+
+    let mut cpu = strict_cpu("
+        LDM 2
+        RDM
+        LDM 1
+done:   JUN done
+", StrictMode::Halt, 16, 16);
+    let mut limits = RunLimits::new();
+    limits.halt_on_self_jump(true);
+    limits.max_cycles(100);
+    let result = run(&mut cpu, &limits);
+    assert_eq!(result.reason, ExitReason::Strict(0x001));
+    assert_eq!(result.instructions, 2);
+}
+
+#[test]
+fn test_debugger() {
+    let mut debugger = Debugger::new(strict_cpu("
+        WMP
+        LDM 1
+done:   JUN done
+", StrictMode::Halt, 16, 16));
+    assert_eq!(debugger.execute("c").unwrap(), "strict mode at 0x000: RAM or port access before any SRC selected a chip");
+    assert!(debugger.step().ends_with("LDM 1"));                    // Stepping resumes.
+    assert_eq!(debugger.cpu.get_acc(), 0x1);
+}
@@ -0,0 +1,76 @@
+#![cfg(feature = "tui")]
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::debugger::Debugger;
+use intel4004_emu::intel4004::Intel4004;
+use intel4004_emu::tui::Tui;
+
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::style::Color;
+use ratatui::Terminal;
+
+const PROGRAM: &str = "
+main:   LDM 5
+        FIM P0, 0x20
+        SRC P0
+        WRM
+        JUN main
+";
+
+fn render(tui: &Tui) -> Buffer {
+    let mut terminal = Terminal::new(TestBackend::new(100, 40)).unwrap();
+    terminal.draw(|frame| tui.draw(frame)).unwrap();
+    terminal.backend().buffer().clone()
+}
+
+/// Position of the first occurrence of `text`.
+fn find(buffer: &Buffer, text: &str) -> Option<(u16, u16)> {
+    (0..buffer.area.height).find_map(|y| {
+        let line: String = (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect();
+        line.find(text).map(|x| (line[..x].chars().count() as u16, y))
+    })
+}
+
+#[test]
+fn test_panes() {
+    let asm = assemble(PROGRAM).unwrap();
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+    let mut debugger = Debugger::new(cpu);
+    debugger.set_symbols(asm.symbols());
+    let tui = Tui::new(debugger);
+
+    let buffer = render(&tui);
+    for title in ["Code", "Registers", "Stack", "RAM bank 0 of 4", "I/O ports", "Output", "Command"] {
+        assert!(find(&buffer, title).is_some(), "missing {}", title);
+    }
+    assert!(find(&buffer, "000  main:        LDM 5").is_some());
+    assert!(find(&buffer, "005               JUN main").is_some());
+}
+
+#[test]
+fn test_changes_highlighted() {
+    let asm = assemble(PROGRAM).unwrap();
+    let mut cpu = Intel4004::new();
+    cpu.load_bytes(&asm.image);
+    let mut tui = Tui::new(Debugger::new(cpu));
+
+    tui.execute("");
+    let buffer = render(&tui);
+    let (x, y) = find(&buffer, "ACC 5").unwrap();
+    assert_eq!(buffer[(x + 4, y)].fg, Color::Yellow);
+    let (x, y) = find(&buffer, "CY 0").unwrap();
+    assert_eq!(buffer[(x + 3, y)].fg, Color::Reset);
+
+    tui.execute("s 3");
+    let buffer = render(&tui);
+    let (x, y) = find(&buffer, ">2: 5").unwrap();
+    assert_eq!(buffer[(x + 4, y)].fg, Color::Yellow);
+    assert_eq!(buffer[(x + 5, y)].fg, Color::Reset);
+    let (x, y) = find(&buffer, "ACC 5").unwrap();
+    assert_eq!(buffer[(x + 4, y)].fg, Color::Reset);
+
+    tui.execute("q");
+    assert!(tui.is_done());
+}
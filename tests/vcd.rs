@@ -0,0 +1,121 @@
+#[cfg(test)]
+use intel4004_emu::assembler::assemble;
+use intel4004_emu::bus::{Bus, PHASE_NS};
+use intel4004_emu::intel4004::Intel4004;
+use intel4004_emu::vcd::Vcd;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+const PROGRAM: &str = "
+        FIM P0, 0x10
+        SRC P0
+        LDM 5
+        WRR
+        WMP
+done:   JUN done
+";
+
+/// Keeps what the recorder writes readable after it is finished.
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn record(bus: bool, instructions: usize) -> String {
+    let mut cpu = Intel4004::with_chips(2, 1);
+    cpu.load_bytes(&assemble(PROGRAM).unwrap().image);
+    if bus {
+        cpu.enable_bus(Bus::new());
+    }
+    let out = Shared::default();
+    cpu.enable_vcd(Vcd::new(Box::new(out.clone()), &cpu).unwrap());
+    (0..instructions).for_each(|_| cpu.clock());
+    cpu.take_vcd().unwrap().finish().unwrap();
+
+    let bytes = out.0.borrow().clone();
+    String::from_utf8(bytes).unwrap()
+}
+
+/// Identifier code of the variable called `name`.
+fn id(text: &str, name: &str) -> String {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>())
+        .find(|words| words.len() == 6 && words[0] == "$var" && words[4] == name)
+        .map(|words| words[3].to_string())
+        .unwrap()
+}
+
+/// Every (time, value) change of a multi-bit variable.
+fn changes(text: &str, name: &str) -> Vec<(u64, u64)> {
+    let id = id(text, name);
+    let mut time = 0;
+    let mut changes = Vec::new();
+    for line in text.lines() {
+        if let Some(stamp) = line.strip_prefix('#') {
+            time = stamp.parse().unwrap();
+        } else if let Some((value, var)) = line.strip_prefix('b').and_then(|change| change.split_once(' ')) {
+            if var == id {
+                changes.push((time, u64::from_str_radix(value, 2).unwrap()));
+            }
+        }
+    }
+    changes
+}
+
+/// Changes during the instruction cycle starting at `start`.
+fn within(changes: Vec<(u64, u64)>, start: u64) -> Vec<(u64, u64)> {
+    changes.into_iter().filter(|(time, _)| (start..start + 8 * PHASE_NS).contains(time)).collect()
+}
+
+#[test]
+fn test_header() {
+    let text = record(false, 0);
+    assert!(text.contains("$timescale 1ns $end"));
+    for name in ["pc", "phase", "sync", "test", "rom0_io", "rom1_io", "ram0_out"] {
+        id(&text, name);
+    }
+    assert!(!text.contains(" data ") && !text.contains(" rom2_io "));
+
+    let text = record(true, 0);
+    for name in ["data", "cm_rom", "cm_ram"] {
+        id(&text, name);
+    }
+}
+
+#[test]
+fn test_direct() {
+    let text = record(false, 6);
+    let cycle = 8 * PHASE_NS;
+    assert_eq!(changes(&text, "pc"), vec![(0, 0x000), (2 * cycle, 0x002), (3 * cycle, 0x003), (4 * cycle, 0x004),
+        (5 * cycle, 0x005), (6 * cycle, 0x006)]);
+    assert_eq!(changes(&text, "rom1_io"), vec![(0, 0x0), (4 * cycle + 6 * PHASE_NS, 0x5)]);    // X2 of WRR.
+    assert_eq!(changes(&text, "ram0_out"), vec![(0, 0x0), (5 * cycle + 6 * PHASE_NS, 0x5)]);
+    assert_eq!(changes(&text, "phase").len(), 8 * 8);                                       // JUN takes 2 cycles.
+    assert!(text.ends_with(&format!("#{}\n", 8 * cycle)));
+}
+
+#[test]
+fn test_bus() {
+    let text = record(true, 6);
+    let cycle = 8 * PHASE_NS;
+
+    // A1-A3 of the second cycle send the address of the second word of FIM, M1-M2 bring back 0x10.
+    let data = within(changes(&text, "data"), cycle);
+    assert_eq!(data, vec![(cycle, 0x1), (cycle + PHASE_NS, 0x0), (cycle + 3 * PHASE_NS, 0x1), (cycle + 4 * PHASE_NS, 0x0)]);
+
+    // SRC asserts CM-RAM0 at X2 of the third cycle, after the one at A3 of every fetch.
+    let cm_ram = within(changes(&text, "cm_ram"), 2 * cycle);
+    assert_eq!(cm_ram[2..], [(2 * cycle + 6 * PHASE_NS, 1), (2 * cycle + 7 * PHASE_NS, 0)]);
+    assert_eq!(changes(&text, "rom1_io"), vec![(0, 0x0), (4 * cycle + 6 * PHASE_NS, 0x5)]);
+}